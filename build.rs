@@ -1,7 +1,16 @@
-use std::process::Command;
+use std::{process::Command, env, time::{SystemTime, UNIX_EPOCH}};
 
 fn main() {
     let output = Command::new("git").args(&["rev-parse", "HEAD"]).output().unwrap();
     let git_hash = String::from_utf8(output.stdout).unwrap();
     println!("cargo:rustc-env=GIT_HASH={}", git_hash);
+
+    let rustc = env::var("RUSTC").unwrap_or("rustc".to_string());
+    let output = Command::new(rustc).arg("--version").output().unwrap();
+    let rustc_version = String::from_utf8(output.stdout).unwrap();
+    println!("cargo:rustc-env=RUSTC_VERSION={}", rustc_version.trim());
+
+    println!("cargo:rustc-env=BUILD_PROFILE={}", env::var("PROFILE").unwrap());
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", timestamp);
 }
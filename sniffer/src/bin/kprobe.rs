@@ -27,11 +27,23 @@ static mut syscall_contexts: HashMap<SyscallContextKey, SyscallContextFull> = Ha
 #[map]
 static mut connections: HashSet<SocketId> = HashSet::with_max_entries(0x8000);
 
+// local ports the node accepts the peers on, filled by userspace with the configured p2p port
 #[map]
 static mut ports_to_watch: HashSet<u16> = HashSet::with_max_entries(0x100);
 
+// the sockets bound to the watched ports, only the connections accepted on them are captured
 #[map]
-static mut process_ids: HashSet<u16> = HashSet::with_max_entries(0x100);
+static mut listeners: HashSet<SocketId> = HashSet::with_max_entries(0x100);
+
+// remote ports which are never tezos peers, like dns or the rpc of the debugger, filled by userspace;
+// the node connects to the peers on any port, so the outgoing connections are filtered by this list
+#[map]
+static mut ports_to_ignore: HashSet<u16> = HashSet::with_max_entries(0x100);
+
+// the processes whose connections are captured, the bpf code adds the node's pid once it binds its p2p port,
+// userspace adds it too, in case the map update failed, and removes the pid of the previous node
+#[map]
+static mut process_ids: HashSet<u32> = HashSet::with_max_entries(0x100);
 
 // each bpf map is safe to access from multiple threads
 #[inline(always)]
@@ -67,6 +79,75 @@ fn forget_connection(id: &SocketId) {
     unsafe { connections.delete(id) };
 }
 
+#[inline(always)]
+fn is_process_watched(id: &SocketId) -> bool {
+    unsafe { process_ids.get(&id.pid) }.is_some()
+}
+
+// drop the irrelevant connections here, so their data is not copied in the ring buffer
+#[inline(always)]
+fn is_watched(id: &SocketId, address: &Address) -> bool {
+    is_process_watched(id) && unsafe { ports_to_ignore.get(&address.port()) }.is_none()
+}
+
+// the connection is accepted on the node's p2p port
+#[inline(always)]
+fn is_listener_watched(id: &SocketId) -> bool {
+    is_process_watched(id) && unsafe { listeners.get(id) }.is_some()
+}
+
+// the vectored syscalls might pass many buffers, capture at most this many, the verifier rejects
+// the unbounded loop; the data in the rest is reported as truncated, the userspace counts it
+// separately from the ring buffer overflow, but cannot decode the direction after it either
+const MAX_IOV: usize = 8;
+
+// size of `struct iovec`, pointer and length
+const IOVEC_SIZE: usize = 16;
+
+/// Send the data transferred by the vectored syscall, each buffer is a separate event
+#[inline(always)]
+fn send_iov(fd: u32, ts: u64, tag: DataTag, iov_ptr: usize, iov_len: usize, transferred: usize) {
+    let mut remaining = transferred;
+    for i in 0..MAX_IOV {
+        if i >= iov_len || remaining == 0 {
+            break;
+        }
+        let mut iov = [0usize; 2];
+        let result = unsafe {
+            gen::bpf_probe_read_user(
+                iov.as_mut_ptr() as _,
+                IOVEC_SIZE as u32,
+                (iov_ptr + i * IOVEC_SIZE) as _,
+            )
+        };
+        if result != 0 {
+            break;
+        }
+        let length = iov[1].min(remaining);
+        let data = unsafe { slice::from_raw_parts(iov[0] as *const u8, length) };
+        send::dyn_sized::<typenum::B0>(EventId::now(fd, ts), tag, data, rb());
+        remaining -= length;
+    }
+    if remaining > 0 {
+        send::truncated(EventId::now(fd, ts), tag, remaining, rb());
+    }
+}
+
+/// The pointer to the array of `struct iovec` and its length in the `struct user_msghdr`,
+/// they are at offset 16, after the name and its length
+#[inline(always)]
+fn read_msghdr(msg_ptr: usize) -> Option<(usize, usize)> {
+    let mut iov = [0usize; 2];
+    let result = unsafe {
+        gen::bpf_probe_read_user(iov.as_mut_ptr() as _, IOVEC_SIZE as u32, (msg_ptr + 16) as _)
+    };
+    if result == 0 {
+        Some((iov[0], iov[1]))
+    } else {
+        None
+    }
+}
+
 #[kprobe("ksys_write")]
 fn kprobe_write(regs: Registers) {
     let fd = regs.parm1() as u32;
@@ -179,6 +260,143 @@ fn kretprobe_recvfrom(regs: Registers) {
     });
 }
 
+#[kprobe("do_writev")]
+fn kprobe_writev(regs: Registers) {
+    let fd = regs.parm1() as u32;
+    let iov_ptr = regs.parm2() as usize;
+    let iov_len = regs.parm3() as usize;
+
+    if !is_connection(&SocketId::this(fd)) {
+        return;
+    }
+
+    let context = SyscallContext::WriteV { fd, iov_ptr, iov_len };
+    context.push(&regs, syscall_contexts_map(), rb());
+}
+
+#[kretprobe("do_writev")]
+fn kretprobe_writev(regs: Registers) {
+    SyscallContext::pop_with(&regs, syscall_contexts_map(), rb(), |s, ts| match s {
+        SyscallContext::WriteV { fd, iov_ptr, iov_len } => {
+            let written = regs.rc();
+            if regs.is_syscall_success() && written as i64 > 0 {
+                send_iov(fd, ts, DataTag::WriteV, iov_ptr, iov_len, written as usize)
+            }
+        },
+        _ => (),
+    });
+}
+
+#[kprobe("do_readv")]
+fn kprobe_readv(regs: Registers) {
+    let fd = regs.parm1() as u32;
+    let iov_ptr = regs.parm2() as usize;
+    let iov_len = regs.parm3() as usize;
+
+    if !is_connection(&SocketId::this(fd)) {
+        return;
+    }
+
+    let context = SyscallContext::ReadV { fd, iov_ptr, iov_len };
+    context.push(&regs, syscall_contexts_map(), rb());
+}
+
+#[kretprobe("do_readv")]
+fn kretprobe_readv(regs: Registers) {
+    SyscallContext::pop_with(&regs, syscall_contexts_map(), rb(), |s, ts| match s {
+        SyscallContext::ReadV { fd, iov_ptr, iov_len } => {
+            let read = regs.rc();
+            if regs.is_syscall_success() && read as i64 > 0 {
+                send_iov(fd, ts, DataTag::ReadV, iov_ptr, iov_len, read as usize)
+            }
+        },
+        _ => (),
+    });
+}
+
+#[kprobe("__sys_sendmsg")]
+fn kprobe_sendmsg(regs: Registers) {
+    let fd = regs.parm1() as u32;
+    let msg_ptr = regs.parm2() as usize;
+
+    if !is_connection(&SocketId::this(fd)) {
+        return;
+    }
+
+    if let Some((iov_ptr, iov_len)) = read_msghdr(msg_ptr) {
+        let context = SyscallContext::SendMsg { fd, iov_ptr, iov_len };
+        context.push(&regs, syscall_contexts_map(), rb());
+    }
+}
+
+#[kretprobe("__sys_sendmsg")]
+fn kretprobe_sendmsg(regs: Registers) {
+    SyscallContext::pop_with(&regs, syscall_contexts_map(), rb(), |s, ts| match s {
+        SyscallContext::SendMsg { fd, iov_ptr, iov_len } => {
+            let written = regs.rc();
+            if regs.is_syscall_success() && written as i64 > 0 {
+                send_iov(fd, ts, DataTag::SendMsg, iov_ptr, iov_len, written as usize)
+            }
+        },
+        _ => (),
+    });
+}
+
+#[kprobe("__sys_recvmsg")]
+fn kprobe_recvmsg(regs: Registers) {
+    let fd = regs.parm1() as u32;
+    let msg_ptr = regs.parm2() as usize;
+
+    if !is_connection(&SocketId::this(fd)) {
+        return;
+    }
+
+    if let Some((iov_ptr, iov_len)) = read_msghdr(msg_ptr) {
+        let context = SyscallContext::RecvMsg { fd, iov_ptr, iov_len };
+        context.push(&regs, syscall_contexts_map(), rb());
+    }
+}
+
+#[kretprobe("__sys_recvmsg")]
+fn kretprobe_recvmsg(regs: Registers) {
+    SyscallContext::pop_with(&regs, syscall_contexts_map(), rb(), |s, ts| match s {
+        SyscallContext::RecvMsg { fd, iov_ptr, iov_len } => {
+            let read = regs.rc();
+            if regs.is_syscall_success() && read as i64 > 0 {
+                send_iov(fd, ts, DataTag::RecvMsg, iov_ptr, iov_len, read as usize)
+            }
+        },
+        _ => (),
+    });
+}
+
+// the data is copied from the file by the kernel, it is not in the user memory,
+// only its length is reported, so the userspace knows the stream has a gap
+#[kprobe("do_sendfile")]
+fn kprobe_sendfile(regs: Registers) {
+    let fd = regs.parm1() as u32;
+
+    if !is_connection(&SocketId::this(fd)) {
+        return;
+    }
+
+    let context = SyscallContext::SendFile { fd, unused: 0 };
+    context.push(&regs, syscall_contexts_map(), rb());
+}
+
+#[kretprobe("do_sendfile")]
+fn kretprobe_sendfile(regs: Registers) {
+    SyscallContext::pop_with(&regs, syscall_contexts_map(), rb(), |s, ts| match s {
+        SyscallContext::SendFile { fd, unused: _ } => {
+            let written = regs.rc();
+            if regs.is_syscall_success() && written as i64 > 0 {
+                send::lost(EventId::now(fd, ts), DataTag::SendFile, written as usize, rb())
+            }
+        },
+        _ => (),
+    });
+}
+
 #[kprobe("__sys_connect")]
 fn kprobe_connect(regs: Registers) {
     let fd = regs.parm1() as u32;
@@ -205,10 +423,12 @@ fn kretprobe_connect(regs: Registers) {
                     )
                 };
 
-                if let Ok(_) = Address::try_from(tmp.as_ref()) {
+                if let Ok(parsed) = Address::try_from(tmp.as_ref()) {
                     let id = EventId::now(fd, ts);
-                    reg_connection(&id.socket_id, false);
-                    send::sized::<typenum::U28, typenum::B0>(id, DataTag::Connect, address, rb())
+                    if is_watched(&id.socket_id, &parsed) {
+                        reg_connection(&id.socket_id, false);
+                        send::sized::<typenum::U28, typenum::B0>(id, DataTag::Connect, address, rb())
+                    }
                 } else {
                     // AF_UNSPEC
                     if tmp[0] == 0 && tmp[1] == 0 {
@@ -250,9 +470,21 @@ fn kretprobe_bind(regs: Registers) {
                     )
                 };
 
-                if let Ok(_) = Address::try_from(tmp.as_ref()) {
+                if let Ok(parsed) = Address::try_from(tmp.as_ref()) {
                     let id = EventId::now(fd, ts);
-                    reg_connection(&id.socket_id, false);
+                    if is_process_watched(&id.socket_id) {
+                        reg_connection(&id.socket_id, false);
+                    }
+                    // the node binds its p2p port, watch its pid right here, before it can connect or accept;
+                    // userspace learns the pid from the bind event later, the connections made meanwhile
+                    // would be dropped, if the pid was watched only by userspace
+                    if unsafe { ports_to_watch.get(&parsed.port()) }.is_some() {
+                        unsafe {
+                            process_ids.set(&id.socket_id.pid, &1);
+                            listeners.set(&id.socket_id, &1);
+                        }
+                    }
+                    // report the bind of any process, userspace learns the node's pid from it
                     send::sized::<typenum::U28, typenum::B0>(id, DataTag::Bind, address, rb())
                 } else {
                     // AF_UNSPEC
@@ -321,8 +553,10 @@ fn kretprobe_accept(regs: Registers) {
 
                 if let Ok(_) = Address::try_from(&tmp[4..]) {
                     let id = EventId::now(fd, ts);
-                    reg_connection(&id.socket_id, true);
-                    send::sized::<typenum::U32, typenum::B1>(id, DataTag::Accept, tmp.as_ref(), rb())
+                    if is_listener_watched(&SocketId::this(listen_on_fd)) {
+                        reg_connection(&id.socket_id, true);
+                        send::sized::<typenum::U32, typenum::B1>(id, DataTag::Accept, tmp.as_ref(), rb())
+                    }
                 } else {
                     // ignore connection to other type of address
                     // track only ipv4 (af_inet) and ipv6 (af_inet6)
@@ -338,6 +572,7 @@ fn kprobe_close(regs: Registers) {
     let fd = regs.parm1() as u32;
 
     let socket_id = SocketId::this(fd);
+    unsafe { listeners.delete(&socket_id) };
     if is_connection(&socket_id) {
         forget_connection(&socket_id);
 
@@ -13,6 +13,16 @@ pub struct DataDescriptor {
     pub size: i32,
 }
 
+impl DataDescriptor {
+    /// Size of the data which did not fit in the ring buffer, it is `-EMSGSIZE`,
+    /// the descriptor is followed by the length of the lost data, 64 bit little endian
+    pub const OVERFLOW: i32 = -90;
+
+    /// Size of the data in the buffers of the vectored syscall beyond the captured ones, it is `-E2BIG`,
+    /// the descriptor is followed by the length of the lost data, like for `OVERFLOW`
+    pub const TRUNCATED: i32 = -7;
+}
+
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct EventId {
     pub socket_id: SocketId,
@@ -21,8 +31,8 @@ pub struct EventId {
 }
 
 impl EventId {
-    #[cfg(feature = "probes")]
-    fn new(socket_id: SocketId, _ts_start: u64, ts_finish: u64) -> Self {
+    #[cfg(any(feature = "probes", test))]
+    pub(crate) fn new(socket_id: SocketId, _ts_start: u64, ts_finish: u64) -> Self {
         EventId {
             socket_id: socket_id,
             ts: ts_finish,
@@ -110,7 +120,7 @@ impl TryFrom<&[u8]> for DataDescriptor {
 }
 
 #[repr(u32)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum DataTag {
     Write,
     SendTo,
@@ -125,6 +135,14 @@ pub enum DataTag {
     Close,
 
     Debug,
+
+    // the vectored syscalls, added after the others, so the values of the old tags stay the same
+    WriteV,
+    SendMsg,
+    ReadV,
+    RecvMsg,
+    // the data is not in the user memory, only its length is sent, like if it was lost
+    SendFile,
 }
 
 impl DataTag {
@@ -150,6 +168,16 @@ impl DataTag {
             Some(Self::Close)
         } else if v == Self::Debug as u32 {
             Some(Self::Debug)
+        } else if v == Self::WriteV as u32 {
+            Some(Self::WriteV)
+        } else if v == Self::SendMsg as u32 {
+            Some(Self::SendMsg)
+        } else if v == Self::ReadV as u32 {
+            Some(Self::ReadV)
+        } else if v == Self::RecvMsg as u32 {
+            Some(Self::RecvMsg)
+        } else if v == Self::SendFile as u32 {
+            Some(Self::SendFile)
         } else {
             None
         }
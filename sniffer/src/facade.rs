@@ -9,7 +9,10 @@ use std::{
 use redbpf::{load::Loader, Module, ringbuf::RingBuffer, ringbuf_sync::RingBufferSync, HashMap, Map};
 use super::{SocketId, EventId, DataDescriptor, DataTag, address::Address, bpf_code::CODE};
 
-pub struct BpfModule(Module);
+pub struct BpfModule {
+    module: Module,
+    kprobes: Vec<(String, Result<(), String>)>,
+}
 
 impl From<Address> for SocketAddr {
     fn from(a: Address) -> Self {
@@ -29,6 +32,12 @@ pub enum SnifferEvent<'a> {
     Accept { id: EventId, listen_on_fd: u32, address: SocketAddr },
     Close { id: EventId },
     Debug { id: EventId, msg: String },
+    /// The data did not fit in the ring buffer, `lost` bytes are missing from the stream
+    Overflow { id: EventId, incoming: bool, lost: usize },
+    /// The vectored syscall passed more buffers than the bpf code captures, `lost` bytes are missing from the stream
+    Truncated { id: EventId, incoming: bool, lost: usize },
+    /// The kernel sent `length` bytes from the file, they are not captured
+    SendFile { id: EventId, length: usize },
 }
 
 #[derive(Debug)]
@@ -82,8 +91,29 @@ impl<'a> TryFrom<&'a [u8]> for SnifferEvent<'a> {
         let descriptor = DataDescriptor::try_from(value)
             .map_err(|()| SnifferError::SliceTooShort(value.len()))?;
         let data = &value[mem::size_of::<DataDescriptor>()..];
+        // the length of the data which is not captured follows the descriptor
+        let lost = || {
+            data.get(..8)
+                .map(|lost| u64::from_le_bytes(TryFrom::try_from(lost).unwrap()) as usize)
+                .unwrap_or(0)
+        };
+        if descriptor.size == DataDescriptor::OVERFLOW || descriptor.size == DataDescriptor::TRUNCATED {
+            let incoming = match descriptor.tag {
+                DataTag::Write | DataTag::SendTo | DataTag::WriteV | DataTag::SendMsg => Some(false),
+                DataTag::Read | DataTag::RecvFrom | DataTag::ReadV | DataTag::RecvMsg => Some(true),
+                _ => None,
+            };
+            match incoming {
+                Some(incoming) if descriptor.size == DataDescriptor::TRUNCATED => {
+                    return Ok(SnifferEvent::Truncated { id: descriptor.id, incoming, lost: lost() });
+                },
+                Some(incoming) => return Ok(SnifferEvent::Overflow { id: descriptor.id, incoming, lost: lost() }),
+                None => (),
+            }
+        }
         match descriptor.tag {
-            DataTag::Write | DataTag::SendTo => {
+            DataTag::SendFile => Ok(SnifferEvent::SendFile { id: descriptor.id, length: lost() }),
+            DataTag::Write | DataTag::SendTo | DataTag::WriteV | DataTag::SendMsg => {
                 SnifferError::write(descriptor.id, descriptor.size, data.len()).map(|(id, size)| {
                     SnifferEvent::Write {
                         id,
@@ -91,7 +121,7 @@ impl<'a> TryFrom<&'a [u8]> for SnifferEvent<'a> {
                     }
                 })
             },
-            DataTag::Read | DataTag::RecvFrom => {
+            DataTag::Read | DataTag::RecvFrom | DataTag::ReadV | DataTag::RecvMsg => {
                 SnifferError::read(descriptor.id, descriptor.size, data.len()).map(|(id, size)| {
                     SnifferEvent::Read {
                         id,
@@ -133,23 +163,44 @@ impl<'a> TryFrom<&'a [u8]> for SnifferEvent<'a> {
 }
 
 impl BpfModule {
-    // TODO: handle error
-    pub fn load(namespace: &str) -> Self {
-        let mut loaded = Loader::load(CODE).expect("Error loading BPF program");
+    /// Load the BPF program and attach its kprobes, fails if the program cannot be loaded,
+    /// the kprobes which failed to attach are reported by `kprobes`
+    pub fn load(namespace: &str) -> Result<Self, String> {
+        let mut loaded = Loader::load(CODE)
+            .map_err(|error| format!("error loading BPF program: {:?}", error))?;
+        let mut kprobes = Vec::new();
         for probe in loaded.kprobes_mut() {
             // try to detach the kprobe, if previous run of the sniffer did not cleanup
             let _ = probe
                 .detach_kprobe_namespace(namespace, &probe.name());
-            probe
+            // the sniffer still works without some kprobe, it just misses the syscalls
+            let attached = probe
                 .attach_kprobe_namespace(namespace, &probe.name(), 0)
-                .expect(&format!("Error attaching kprobe program {}", probe.name()));
+                .map(|_| ())
+                .map_err(|error| format!("{:?}", error));
+            kprobes.push((probe.name(), attached));
+        }
+        Ok(BpfModule {
+            module: loaded.module,
+            kprobes,
+        })
+    }
+
+    /// Detach the kprobes, so they do not stay in the kernel after the sniffer exits
+    pub fn detach(&mut self, namespace: &str) {
+        for probe in self.module.kprobes_mut() {
+            let _ = probe.detach_kprobe_namespace(namespace, &probe.name());
         }
-        BpfModule(loaded.module)
+    }
+
+    /// Names of the kprobes with the error of attaching, if it failed
+    pub fn kprobes(&self) -> &[(String, Result<(), String>)] {
+        &self.kprobes
     }
 
     fn main_buffer_map(&self) -> &Map {
         self
-            .0
+            .module
             .maps
             .iter()
             .find(|m| m.name == "main_buffer")
@@ -166,17 +217,129 @@ impl BpfModule {
         RingBufferSync::from_map(&rb_map).unwrap()
     }
 
-    fn connections_map(&self) -> HashMap<SocketId, u32> {
+    fn hash_set<K: Clone>(&self, name: &str) -> HashMap<K, u32> {
         let map = self
-            .0
+            .module
             .maps
             .iter()
-            .find(|m| m.name == "connections")
+            .find(|m| m.name == name)
             .unwrap();
         HashMap::new(map).unwrap()
     }
 
     pub fn ignore(&self, id: SocketId) {
-        self.connections_map().delete(id);
+        self.hash_set::<SocketId>("connections").delete(id);
+    }
+
+    /// Capture the connections of the process, the connections of other processes are dropped by the bpf code
+    pub fn watch_pid(&self, pid: u32) {
+        self.hash_set::<u32>("process_ids").set(pid, 1);
+    }
+
+    /// Stop capturing the connections of the process, like the previous node replaced by the restarted one
+    pub fn unwatch_pid(&self, pid: u32) {
+        self.hash_set::<u32>("process_ids").delete(pid);
+    }
+
+    /// Capture the connections accepted on the local port, the node's p2p port
+    pub fn watch_port(&self, port: u16) {
+        self.hash_set::<u16>("ports_to_watch").set(port, 1);
+    }
+
+    /// Drop the connections to the remote port in the bpf code
+    pub fn ignore_port(&self, port: u16) {
+        self.hash_set::<u16>("ports_to_ignore").set(port, 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::TryFrom, mem, ptr};
+    use super::{SnifferEvent, SnifferError, SnifferErrorCode};
+    use crate::{DataDescriptor, DataTag, EventId, SocketId};
+
+    // the same layout the bpf code writes in the ring buffer
+    fn event(tag: DataTag, size: i32, data: &[u8]) -> Vec<u8> {
+        let id = EventId::new(SocketId { pid: 1, fd: 2 }, 0, 3);
+        let mut bytes = vec![0; mem::size_of::<DataDescriptor>()];
+        unsafe { ptr::write_unaligned(bytes.as_mut_ptr() as *mut DataDescriptor, DataDescriptor { id, tag, size }) };
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn vectored() {
+        for &tag in &[DataTag::WriteV, DataTag::SendMsg] {
+            let bytes = event(tag, 3, b"abcd");
+            match SnifferEvent::try_from(bytes.as_slice()) {
+                Ok(SnifferEvent::Write { id, data }) => {
+                    assert_eq!((id.socket_id.pid, id.socket_id.fd), (1, 2));
+                    assert_eq!(data, b"abc");
+                },
+                _ => panic!("{:?} is not decoded as write", tag),
+            }
+        }
+        for &tag in &[DataTag::ReadV, DataTag::RecvMsg] {
+            let bytes = event(tag, 4, b"abcd");
+            assert!(matches!(SnifferEvent::try_from(bytes.as_slice()), Ok(SnifferEvent::Read { data: b"abcd", .. })));
+        }
+
+        let bytes = event(DataTag::ReadV, 5, b"abcd");
+        assert!(matches!(
+            SnifferEvent::try_from(bytes.as_slice()),
+            Err(SnifferError::Read { code: SnifferErrorCode::SliceTooShort(4, 5), .. })
+        ));
+    }
+
+    #[test]
+    fn lost() {
+        let bytes = event(DataTag::SendMsg, DataDescriptor::OVERFLOW, &0x1000_u64.to_le_bytes());
+        assert!(matches!(
+            SnifferEvent::try_from(bytes.as_slice()),
+            Ok(SnifferEvent::Overflow { incoming: false, lost: 0x1000, .. })
+        ));
+        let bytes = event(DataTag::RecvMsg, DataDescriptor::OVERFLOW, &0x20_u64.to_le_bytes());
+        assert!(matches!(
+            SnifferEvent::try_from(bytes.as_slice()),
+            Ok(SnifferEvent::Overflow { incoming: true, lost: 0x20, .. })
+        ));
+        let bytes = event(DataTag::SendFile, DataDescriptor::OVERFLOW, &0x10000_u64.to_le_bytes());
+        assert!(matches!(
+            SnifferEvent::try_from(bytes.as_slice()),
+            Ok(SnifferEvent::SendFile { length: 0x10000, .. })
+        ));
+        let bytes = event(DataTag::WriteV, DataDescriptor::TRUNCATED, &0x30_u64.to_le_bytes());
+        assert!(matches!(
+            SnifferEvent::try_from(bytes.as_slice()),
+            Ok(SnifferEvent::Truncated { incoming: false, lost: 0x30, .. })
+        ));
+
+        assert!(matches!(SnifferEvent::try_from(&[0; 4][..]), Err(SnifferError::SliceTooShort(4))));
+    }
+
+    #[test]
+    fn overflow() {
+        // the length of the lost data did not fit either
+        let bytes = event(DataTag::Read, DataDescriptor::OVERFLOW, &[]);
+        match SnifferEvent::try_from(bytes.as_slice()) {
+            Ok(SnifferEvent::Overflow { id, incoming: true, lost: 0 }) => {
+                assert_eq!((id.socket_id.pid, id.socket_id.fd, id.ts_finish()), (1, 2, 3));
+            },
+            _ => panic!("overflow of read is not decoded"),
+        }
+        let bytes = event(DataTag::Write, DataDescriptor::OVERFLOW, &0x40_u64.to_le_bytes());
+        assert!(matches!(
+            SnifferEvent::try_from(bytes.as_slice()),
+            Ok(SnifferEvent::Overflow { incoming: false, lost: 0x40, .. })
+        ));
+
+        // only the data events overflow
+        let bytes = event(DataTag::Close, DataDescriptor::OVERFLOW, &[]);
+        assert!(matches!(SnifferEvent::try_from(bytes.as_slice()), Ok(SnifferEvent::Close { .. })));
+        let bytes = event(DataTag::Debug, DataDescriptor::OVERFLOW, &[]);
+        assert!(matches!(
+            SnifferEvent::try_from(bytes.as_slice()),
+            Err(SnifferError::Debug { code: SnifferErrorCode::Unknown(DataDescriptor::OVERFLOW), .. })
+        ));
     }
 }
@@ -51,11 +51,31 @@ where
         return;
     }
 
-    // failed to allocate buffer, try allocate smaller buffer to report error
-    if let Ok(mut buffer) = rb.reserve(mem::size_of::<DataDescriptor>() as u64, 0) {
-        let descriptor = DataDescriptor { id, tag, size: -90 };
+    // failed to allocate buffer, try allocate smaller buffer to report error and how much data is lost
+    lost(id, tag, data.len(), rb)
+}
+
+/// Report the data which is not captured, the userspace marks the gap in the stream
+#[inline(always)]
+pub fn lost(id: EventId, tag: DataTag, length: usize, rb: &mut RingBuffer) {
+    gap(id, tag, DataDescriptor::OVERFLOW, length, rb)
+}
+
+/// Report the data in the buffers of the vectored syscall which are not captured
+#[inline(always)]
+pub fn truncated(id: EventId, tag: DataTag, length: usize, rb: &mut RingBuffer) {
+    gap(id, tag, DataDescriptor::TRUNCATED, length, rb)
+}
+
+#[inline(always)]
+fn gap(id: EventId, tag: DataTag, cause: i32, length: usize, rb: &mut RingBuffer) {
+    let size = mem::size_of::<DataDescriptor>() + mem::size_of::<u64>();
+    if let Ok(mut buffer) = rb.reserve(size as u64, 0) {
+        let p_buffer = buffer.as_mut().as_mut_ptr() as *mut DataDescriptor;
+        let descriptor = DataDescriptor { id, tag, size: cause };
         unsafe {
-            ptr::write(buffer.as_mut().as_mut_ptr() as *mut _, descriptor);
+            ptr::write(p_buffer, descriptor);
+            ptr::write_unaligned(p_buffer.offset(1) as *mut u64, (length as u64).to_le());
         }
         buffer.submit(0);
     }
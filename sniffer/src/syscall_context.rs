@@ -54,6 +54,32 @@ pub enum SyscallContext {
         listen_on_fd: u32,
         address: &'static [u8],
     },
+
+    // `iov_ptr` points to the array of `iov_len` of `struct iovec`
+    WriteV {
+        fd: u32,
+        iov_ptr: usize,
+        iov_len: usize,
+    },
+    SendMsg {
+        fd: u32,
+        iov_ptr: usize,
+        iov_len: usize,
+    },
+    ReadV {
+        fd: u32,
+        iov_ptr: usize,
+        iov_len: usize,
+    },
+    RecvMsg {
+        fd: u32,
+        iov_ptr: usize,
+        iov_len: usize,
+    },
+    SendFile {
+        fd: u32,
+        unused: usize,
+    },
 }
 
 impl SyscallContext {
@@ -1,24 +1,33 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use std::{process::exit, path::Path, sync::Arc, env::var, fs};
+use std::{process::exit, path::Path, sync::Arc, env::var, fs, time::Duration};
 use tracing::{info, error, Level};
+use tokio::signal::unix::{signal, SignalKind};
 use storage::persistent::{open_kv, DbConfiguration};
 use tezedge_debugger::{
-    system::{SystemSettings, syslog_producer::syslog_producer, Parser},
+    system::{
+        SystemSettings, SystemStatus, LogSource, NetworkProfile, PowTarget, Parser,
+        syslog_producer::syslog_producer,
+        file_producer::{file_producer, FileFormat},
+        alerts::{Alerts, AlertConfig},
+    },
     endpoints::routes,
-    storage::{MessageStore, cfs},
+    messages::log_parser::LogParserRegistry,
+    storage::{MessageStore, cfs, history_cfs},
 };
 
-/// Create new message store, from well defined path
+/// Create new message store, from well defined paths, the captured messages are removed on start,
+/// the connection reports and the alerts are kept
 fn open_database() -> Result<MessageStore, failure::Error> {
     let path = Path::new("/tmp/volume/debugger_db");
     if path.exists() {
-        fs::remove_dir_all(path).unwrap();
+        fs::remove_dir_all(path)?;
     }
-    let schemas = cfs();
-    let rocksdb = Arc::new(open_kv(path, schemas, &DbConfiguration::default())?);
-    Ok(MessageStore::new(rocksdb))
+    let rocksdb = Arc::new(open_kv(path, cfs(), &DbConfiguration::default())?);
+    let history_path = Path::new("/tmp/volume/debugger_history");
+    let history = Arc::new(open_kv(history_path, history_cfs(), &DbConfiguration::default())?);
+    Ok(MessageStore::new(rocksdb, history)?)
 }
 
 #[tokio::main]
@@ -37,37 +46,139 @@ async fn main() -> Result<(), failure::Error> {
         }
     };
 
+    // Evaluate alerting rules, if configured
+    let alerts = match var("ALERT_RULES") {
+        Ok(path) => match AlertConfig::load(Path::new(&path)).and_then(|config| Alerts::spawn(storage.clone(), config)) {
+            Ok(alerts) => alerts,
+            Err(err) => {
+                error!(error = tracing::field::display(&err), "failed to load alert rules");
+                exit(1);
+            },
+        },
+        Err(_) => Alerts::disabled(),
+    };
+
+    // Select the network the node is running on, by the name of the preset or from the file
+    let network = match (var("NETWORK_FILE"), var("NETWORK")) {
+        (Ok(path), _) => NetworkProfile::load(Path::new(&path)).map_err(|err| err.to_string()),
+        (Err(_), Ok(name)) => name.parse::<NetworkProfile>(),
+        (Err(_), Err(_)) => Ok(NetworkProfile::default()),
+    };
+    let mut network = match network {
+        Ok(network) => network,
+        Err(err) => {
+            error!(error = tracing::field::display(&err), "invalid network profile");
+            exit(1);
+        },
+    };
+    if let Ok(pow_target) = var("P2P_POW_TARGET") {
+        match pow_target.parse::<PowTarget>() {
+            Ok(pow_target) => network.pow_target = pow_target,
+            Err(err) => {
+                error!(error = tracing::field::display(&err), "invalid P2P_POW_TARGET");
+                exit(1);
+            },
+        }
+    }
+    info!(network = tracing::field::display(&network.name), "network selected");
+
+    // How long the shutdown may take, parsed now, so a wrong value does not spoil the shutdown
+    let shutdown_timeout = match var("SHUTDOWN_TIMEOUT").map(|timeout| timeout.parse::<u64>()) {
+        Ok(Ok(timeout)) => Duration::from_secs(timeout),
+        Ok(Err(err)) => {
+            error!(error = tracing::field::display(&err), "invalid SHUTDOWN_TIMEOUT");
+            exit(1);
+        },
+        Err(_) => Duration::from_secs(8),
+    };
+
     // Create system setting to drive the rest of the system
-    let p2p_port_str = var("P2P_PORT").unwrap();
+    let node_p2p_port = match var("P2P_PORT").map(|port| port.parse::<u16>()) {
+        Ok(Ok(port)) => port,
+        Ok(Err(err)) => {
+            error!(error = tracing::field::display(&err), "invalid P2P_PORT");
+            exit(1);
+        },
+        Err(_) => network.p2p_port,
+    };
+    let node_rpc_port = match var("NODE_RPC_PORT").map(|port| port.parse::<u16>()) {
+        Ok(Ok(port)) => port,
+        Ok(Err(err)) => {
+            error!(error = tracing::field::display(&err), "invalid NODE_RPC_PORT");
+            exit(1);
+        },
+        Err(_) => network.rpc_port,
+    };
     let settings = SystemSettings {
         storage: storage.clone(),
-        namespace: format!("n{}", p2p_port_str),
+        namespace: format!("n{}", node_p2p_port),
         syslog_port: 13131,
         rpc_port: 17732,
-        node_p2p_port: p2p_port_str.parse().unwrap(),
-        node_rpc_port: 8732,
+        node_p2p_port,
+        node_rpc_host: var("NODE_RPC_HOST").unwrap_or("localhost".to_string()),
+        node_rpc_port,
         max_message_number: var("P2P_MESSAGE_NUMBER_LIMIT").unwrap_or("1000000".to_string()).parse().unwrap(),
+        log_sources: match var("LOG_SOURCES").unwrap_or("syslog".to_string()).split(',').map(str::parse::<LogSource>).collect() {
+            Ok(log_sources) => log_sources,
+            Err(err) => {
+                error!(error = tracing::field::display(&err), "invalid LOG_SOURCES");
+                exit(1);
+            },
+        },
+        log_parsers: Arc::new(LogParserRegistry::default()),
+        alerts,
+        network,
+        status: SystemStatus::default(),
     };
 
-    // Create syslog server to capture logs from docker / syslogs
-    if let Err(err) = syslog_producer(settings.clone()).await {
-        error!(error = tracing::field::display(&err), "failed to build syslog server");
-        exit(1);
+    // Capture logs of the node from configured sources
+    for log_source in &settings.log_sources {
+        match log_source {
+            // Create syslog server to capture logs from docker / syslogs
+            LogSource::Syslog => if let Err(err) = syslog_producer(settings.clone()).await {
+                error!(error = tracing::field::display(&err), "failed to build syslog server");
+                exit(1);
+            },
+            LogSource::File(path) => file_producer(settings.clone(), path.clone(), FileFormat::Plain),
+            LogSource::Docker(path) => file_producer(settings.clone(), path.clone(), FileFormat::DockerJson),
+        }
     }
 
     // Create and spawn bpf sniffing system
-    let reporter = Parser::new(&settings).spawn();
+    let (reporter, parser) = Parser::new(&settings).spawn();
 
     // Spawn warp RPC server
-    tokio::spawn(warp::serve(routes(storage, reporter)).run(([0, 0, 0, 0], settings.rpc_port)));
+    tokio::spawn(warp::serve(routes(storage.clone(), reporter)).run(([0, 0, 0, 0], settings.rpc_port)));
 
-    // Wait for SIGTERM signal
-    if let Err(err) = tokio::signal::ctrl_c().await {
-        error!(error = tracing::field::display(&err), "failed while listening for signal");
-        exit(1);
+    // Wait for SIGTERM or SIGINT signal
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(err) => {
+            error!(error = tracing::field::display(&err), "failed to listen for SIGTERM");
+            exit(1);
+        },
+    };
+    tokio::select! {
+        _ = terminate.recv() => info!("SIGTERM received"),
+        result = tokio::signal::ctrl_c() => match result {
+            Ok(()) => info!("ctrl-c received"),
+            Err(err) => {
+                error!(error = tracing::field::display(&err), "failed while listening for signal");
+                exit(1);
+            },
+        },
     }
 
-    info!("ctrl-c received");
+    // Stop capturing and detach the kprobes, store what was captured, give up after the timeout
+    let shutdown = async {
+        parser.shutdown().await;
+        storage.flush()
+    };
+    match tokio::time::timeout(shutdown_timeout, shutdown).await {
+        Ok(Ok(())) => info!("shutdown completed"),
+        Ok(Err(err)) => error!(error = tracing::field::display(&err), "failed to flush database"),
+        Err(_) => error!(timeout = shutdown_timeout.as_secs(), "shutdown timed out"),
+    }
 
     Ok(())
 }
@@ -0,0 +1,31 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use crate::storage::MessageStore;
+use warp::{
+    Filter, Rejection,
+    reply::{with_status, json, WithStatus, Json},
+    http::StatusCode,
+};
+use serde::{Serialize, Deserialize};
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+/// Cursor structure mapped from the endpoint URI
+pub struct AlertCursor {
+    pub cursor_id: Option<u64>,
+    pub limit: Option<usize>,
+    pub rule: Option<String>,
+}
+
+/// Basic handler for raised alerts with cursor
+pub fn alerts(storage: MessageStore) -> impl Filter<Extract=(WithStatus<Json>, ), Error=Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("v2" / "alerts")
+        .and(warp::query::query())
+        .map(move |cursor: AlertCursor| -> WithStatus<Json> {
+            let limit = cursor.limit.unwrap_or(100);
+            match storage.alert().get_cursor(cursor.cursor_id, limit, cursor.rule) {
+                Ok(alerts) => with_status(json(&alerts), StatusCode::OK),
+                Err(err) => with_status(json(&format!("database error: {}", err)), StatusCode::INTERNAL_SERVER_ERROR),
+            }
+        })
+}
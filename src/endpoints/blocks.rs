@@ -0,0 +1,28 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use crate::system::Reporter;
+use warp::{
+    Filter, Rejection,
+    reply::{with_status, json, WithStatus, Json},
+    http::StatusCode,
+};
+
+/// Percentiles of the propagation latencies over the recent blocks
+pub fn propagation_summary(reporter: Reporter) -> impl Filter<Extract=(WithStatus<Json>, ), Error=Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("v2" / "blocks" / "propagation")
+        .map(move || -> WithStatus<Json> {
+            with_status(json(&reporter.get_propagation_summary()), StatusCode::OK)
+        })
+}
+
+/// When each peer announced the block and how long the node took to fetch it
+pub fn block_propagation(reporter: Reporter) -> impl Filter<Extract=(WithStatus<Json>, ), Error=Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("v2" / "blocks" / String / "propagation")
+        .map(move |hash: String| -> WithStatus<Json> {
+            match reporter.get_block_propagation(&hash) {
+                Some(propagation) => with_status(json(&propagation), StatusCode::OK),
+                None => with_status(json(&format!("block {} not found", hash)), StatusCode::NOT_FOUND),
+            }
+        })
+}
@@ -0,0 +1,25 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use crate::system::Reporter;
+use warp::{
+    Filter, Rejection,
+    reply::{with_status, json, WithStatus, Json},
+    http::StatusCode,
+};
+
+/// Heads of the node and of the best peer, to see whether the node is lagging or forking
+pub fn chain_heads(reporter: Reporter) -> impl Filter<Extract=(WithStatus<Json>, ), Error=Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("v2" / "chain" / "heads")
+        .map(move || -> WithStatus<Json> {
+            with_status(json(&reporter.get_chain_heads()), StatusCode::OK)
+        })
+}
+
+/// Heads announced by every peer, the highest first
+pub fn chain_peers(reporter: Reporter) -> impl Filter<Extract=(WithStatus<Json>, ), Error=Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("v2" / "chain" / "peers")
+        .map(move || -> WithStatus<Json> {
+            with_status(json(&reporter.get_chain_peers()), StatusCode::OK)
+        })
+}
@@ -0,0 +1,90 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use crate::{
+    storage::MessageStore,
+    system::{Reporter, P2pConnectionReport, P2pParserError, HandshakeInfo, HandshakeStep, HandshakeSummary},
+};
+use warp::{
+    Filter, Rejection,
+    reply::{with_status, json, WithStatus, Json},
+    http::StatusCode,
+};
+use serde::{Serialize, Deserialize};
+use std::net::SocketAddr;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Cursor structure mapped from the endpoint URI
+pub struct HandshakesCursor {
+    limit: Option<usize>,
+    peer_id: Option<String>,
+    remote_addr: Option<SocketAddr>,
+    failed_at: Option<HandshakeStep>,
+    pending: Option<HandshakeStep>,
+}
+
+impl HandshakesCursor {
+    fn matches(&self, report: &P2pConnectionReport) -> bool {
+        self.peer_id.as_ref().map(|peer_id| report.peer_id.as_ref() == Some(peer_id)).unwrap_or(true)
+            && self.remote_addr.map(|addr| report.remote_address == addr.to_string()).unwrap_or(true)
+            && self.failed_at.map(|step| report.handshake.failed_at == Some(step)).unwrap_or(true)
+            && self.pending.map(|step| report.handshake.pending == Some(step)).unwrap_or(true)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+/// Handshake of the single connection
+pub struct HandshakeView {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peer_id: Option<String>,
+    remote_address: String,
+    opened_timestamp: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<P2pParserError>,
+    #[serde(flatten)]
+    handshake: HandshakeInfo,
+}
+
+#[derive(Debug, Clone, Serialize)]
+/// Handshakes of the latest connections and their summary
+pub struct Handshakes {
+    summary: HandshakeSummary,
+    handshakes: Vec<HandshakeView>,
+}
+
+/// Handshakes of the working connections and of the latest closed connections, newest first
+pub fn handshakes(storage: MessageStore, reporter: Reporter) -> impl Filter<Extract=(WithStatus<Json>, ), Error=Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("v2" / "handshakes")
+        .and(warp::query::query())
+        .map(move |cursor: HandshakesCursor| -> WithStatus<Json> {
+            let limit = cursor.limit.unwrap_or(100);
+            let mut reports = reporter.get_p2p_report()
+                .working_connections()
+                .iter()
+                .filter(|report| cursor.matches(report))
+                .cloned()
+                .collect::<Vec<_>>();
+            reports.sort_by(|a, b| b.opened_timestamp.cmp(&a.opened_timestamp));
+            reports.truncate(limit);
+            match storage.connection().find(limit - reports.len(), |report| cursor.matches(report)) {
+                Ok(closed) => reports.extend(closed),
+                Err(err) => return with_status(json(&format!("database error: {}", err)), StatusCode::INTERNAL_SERVER_ERROR),
+            }
+            let handshakes = Handshakes {
+                summary: HandshakeSummary::new(&reports),
+                handshakes: reports.into_iter()
+                    .map(|report| HandshakeView {
+                        id: report.id,
+                        peer_id: report.peer_id,
+                        remote_address: report.remote_address,
+                        opened_timestamp: report.opened_timestamp,
+                        error: report.error_report.map(|e| e.error),
+                        handshake: report.handshake,
+                    })
+                    .collect(),
+            };
+            with_status(json(&handshakes), StatusCode::OK)
+        })
+}
@@ -0,0 +1,31 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use warp::{
+    Filter, Rejection,
+    reply::{with_status, json, WithStatus, Json},
+    http::StatusCode,
+};
+use crate::system::Reporter;
+
+/// Liveness, whether the kprobes are attached and the database writer keeps up
+pub fn health(reporter: Reporter) -> impl Filter<Extract=(WithStatus<Json>, ), Error=Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("health")
+        .and(warp::query::query())
+        .map(move |()| -> WithStatus<Json> {
+            let health = reporter.get_settings().status.health_now();
+            let status = if health.healthy() { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+            with_status(json(&health), status)
+        })
+}
+
+/// Readiness, additionally whether the node is found and the events are flowing from the ring buffer
+pub fn ready(reporter: Reporter) -> impl Filter<Extract=(WithStatus<Json>, ), Error=Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("ready")
+        .and(warp::query::query())
+        .map(move |()| -> WithStatus<Json> {
+            let health = reporter.get_settings().status.health_now();
+            let status = if health.ready() { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+            with_status(json(&health), status)
+        })
+}
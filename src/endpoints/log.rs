@@ -3,14 +3,19 @@
 
 use crate::storage::{MessageStore, log_indexes::{LogLevel, ParseLogLevel}, LogFilters};
 use crate::messages::log_message::LogMessage;
+use crate::system::{Reporter, P2pReport, P2pConnectionReport};
 use failure::Error;
 use warp::{
     Filter, Reply, Rejection,
     reply::{with_status, json, WithStatus, Json},
     http::StatusCode,
 };
-use std::convert::TryInto;
+use std::{
+    convert::TryInto,
+    net::{IpAddr, SocketAddr},
+};
 use serde::{Serialize, Deserialize};
+use super::parse_timestamp;
 
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 /// Cursor structure mapped from the endpoint URI
@@ -18,40 +23,64 @@ pub struct LogCursor {
     pub cursor_id: Option<u64>,
     pub limit: Option<usize>,
     pub level: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    // deprecated, same as `to`
     pub timestamp: Option<String>,
+    pub query: Option<String>,
+    pub section: Option<String>,
+    pub peer_id: Option<String>,
+    pub remote_addr: Option<String>,
 }
 
 impl LogCursor {
-    /// Parse given timestamp as UNIX timestamp
-    fn get_timestamp(&self) -> Result<Option<u128>, Error> {
-        if let Some(ref ts) = self.timestamp {
-            Ok(Some(ts.parse()?))
-        } else {
-            Ok(None)
-        }
+    /// Parse the bounds of the time window, the `timestamp` is used if `to` is not given
+    fn get_window(&self) -> Result<(Option<u128>, Option<u128>), Error> {
+        let from = self.from.as_ref().map(|from| parse_timestamp(from)).transpose()?;
+        let to = self.to.as_ref().or(self.timestamp.as_ref()).map(|to| parse_timestamp(to)).transpose()?;
+        Ok((from, to))
     }
 
     /// Parse given log level as an database-understandable value
     fn get_level(&self) -> Result<Vec<LogLevel>, ParseLogLevel> {
         if let Some(ref level) = self.level {
-            let mut ret = vec![];
-            for l in level.split(',') {
-                ret.push(l.parse()?);
-            }
-            Ok(ret)
+            parse_levels(level)
         } else {
             Ok(vec![])
         }
     }
 }
 
+/// Parse given socket address or IP address, in the form the logs store it
+fn parse_address(address: &str) -> Result<String, Error> {
+    match address.parse::<SocketAddr>() {
+        Ok(address) => Ok(address.to_string()),
+        Err(_) => Ok(address.parse::<IpAddr>()?.to_string()),
+    }
+}
+
+/// Parse given comma separated list of log levels
+pub(crate) fn parse_levels(level: &str) -> Result<Vec<LogLevel>, ParseLogLevel> {
+    let mut ret = vec![];
+    for l in level.split(',') {
+        ret.push(l.parse()?);
+    }
+    Ok(ret)
+}
+
 impl TryInto<LogFilters> for LogCursor {
     type Error = Error;
 
     fn try_into(self) -> Result<LogFilters, Self::Error> {
+        let (from, to) = self.get_window()?;
         Ok(LogFilters {
             level: self.get_level()?,
-            date: self.get_timestamp()?,
+            from,
+            to,
+            query: self.query,
+            section: self.section,
+            peer_id: self.peer_id,
+            remote_addr: self.remote_addr.as_ref().map(|address| parse_address(address)).transpose()?,
         })
     }
 }
@@ -71,7 +100,37 @@ pub fn log(storage: MessageStore) -> impl Filter<Extract=impl Reply, Error=Rejec
                     },
                     Err(err) => with_status(json(&format!("database error: {}", err)), StatusCode::INTERNAL_SERVER_ERROR),
                 },
-                Err(level_error) => with_status(json(&format!("invalid type-name: {}", level_error)), StatusCode::BAD_REQUEST),
+                Err(filter_error) => with_status(json(&format!("invalid filter: {}", filter_error)), StatusCode::BAD_REQUEST),
             }
         })
-}
\ No newline at end of file
+}
+
+/// Connections mentioned by the log, by the peer id or by the address,
+/// the working connections are included as well
+pub fn log_connections(storage: MessageStore, reporter: Reporter) -> impl Filter<Extract=impl Reply, Error=Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("v2" / "log" / u64 / "connections")
+        .map(move |id: u64| -> WithStatus<Json> {
+            let log = match storage.log().get_message(id) {
+                Ok(Some(log)) => log,
+                Ok(None) => return with_status(json(&format!("log {} not found", id)), StatusCode::NOT_FOUND),
+                Err(err) => return with_status(json(&format!("database error: {}", err)), StatusCode::INTERNAL_SERVER_ERROR),
+            };
+            let mentioned = |report: &P2pConnectionReport| {
+                report.references().iter().any(|reference| log.peer_ids.contains(reference) || log.addresses.contains(reference))
+            };
+            if log.peer_ids.is_empty() && log.addresses.is_empty() {
+                return with_status(json(&P2pReport::prepare(Vec::new(), Vec::new())), StatusCode::OK);
+            }
+            let closed_connections = match storage.connection().find(100, &mentioned) {
+                Ok(reports) => reports,
+                Err(err) => return with_status(json(&format!("database error: {}", err)), StatusCode::INTERNAL_SERVER_ERROR),
+            };
+            let working_connections = reporter.get_p2p_report()
+                .working_connections()
+                .iter()
+                .filter(|report| mentioned(report))
+                .cloned()
+                .collect();
+            with_status(json(&P2pReport::prepare(closed_connections, working_connections)), StatusCode::OK)
+        })
+}
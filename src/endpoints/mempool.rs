@@ -0,0 +1,41 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use crate::system::{Reporter, MempoolFilters};
+use warp::{
+    Filter, Rejection,
+    reply::{with_status, json, WithStatus, Json},
+    http::StatusCode,
+};
+use serde::{Serialize, Deserialize};
+use std::net::SocketAddr;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Cursor structure mapped from the endpoint URI
+pub struct MempoolCursor {
+    limit: Option<usize>,
+    operation_hash: Option<String>,
+    remote_addr: Option<SocketAddr>,
+    peer_id: Option<String>,
+}
+
+impl From<MempoolCursor> for MempoolFilters {
+    fn from(cursor: MempoolCursor) -> Self {
+        MempoolFilters {
+            operation_hash: cursor.operation_hash,
+            remote_addr: cursor.remote_addr,
+            peer_id: cursor.peer_id,
+        }
+    }
+}
+
+/// Operations gossiped by the peers and forwarded by the node, newest first
+pub fn mempool(reporter: Reporter) -> impl Filter<Extract=(WithStatus<Json>, ), Error=Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("v2" / "mempool")
+        .and(warp::query::query())
+        .map(move |cursor: MempoolCursor| -> WithStatus<Json> {
+            let limit = cursor.limit.unwrap_or(100);
+            let filters: MempoolFilters = cursor.into();
+            with_status(json(&reporter.get_mempool(limit, &filters)), StatusCode::OK)
+        })
+}
@@ -5,6 +5,15 @@ pub mod p2p;
 pub mod rpc;
 pub mod log;
 pub mod stat;
+pub mod timeline;
+pub mod alerts;
+pub mod chain;
+pub mod blocks;
+pub mod mempool;
+pub mod peers;
+pub mod handshakes;
+pub mod info;
+pub mod health;
 mod version;
 
 use warp::{
@@ -14,21 +23,61 @@ use warp::{
 };
 use crate::storage::MessageStore;
 use crate::system::Reporter;
-use crate::endpoints::p2p::{p2p, p2p_report};
+use crate::endpoints::p2p::{p2p, p2p_report, p2p_report_compare, connection};
 use crate::endpoints::rpc::rpc;
-use crate::endpoints::log::log;
-use crate::endpoints::stat::stat;
-use std::sync::{Arc, Mutex};
+use crate::endpoints::log::{log, log_connections};
+use crate::endpoints::stat::{stat, stat_timeseries};
+use crate::endpoints::timeline::timeline;
+use crate::endpoints::alerts::alerts;
+use crate::endpoints::chain::{chain_heads, chain_peers};
+use crate::endpoints::blocks::{block_propagation, propagation_summary};
+use crate::endpoints::mempool::mempool;
+use crate::endpoints::peers::{peers, peer};
+use crate::endpoints::handshakes::handshakes;
+use crate::endpoints::info::info;
+use crate::endpoints::health::{health, ready};
+
+/// Parse the bound of the time window, either unix timestamp in nanoseconds, or RFC 3339 date
+pub(crate) fn parse_timestamp(value: &str) -> Result<u128, failure::Error> {
+    if value.chars().all(|c| c.is_ascii_digit()) {
+        Ok(value.parse()?)
+    } else {
+        let date = chrono::DateTime::parse_from_rfc3339(value)?;
+        let nanos = date.timestamp_nanos();
+        if nanos < 0 {
+            Err(failure::format_err!("date {} is before unix epoch", value))
+        } else {
+            Ok(nanos as u128)
+        }
+    }
+}
 
 /// Create router for consisting of all endpoint
-pub fn routes(storage: MessageStore, reporter: Arc<Mutex<Reporter>>) -> impl Filter<Extract=impl Reply, Error=Rejection> + Clone + Sync + Send + 'static {
+pub fn routes(storage: MessageStore, reporter: Reporter) -> impl Filter<Extract=impl Reply, Error=Rejection> + Clone + Sync + Send + 'static {
     warp::get().and(
         p2p(storage.clone())
-            .or(p2p_report(reporter))
+            .or(p2p_report_compare(reporter.clone()))
+            .or(p2p_report(storage.clone(), reporter.clone()))
+            .or(connection(storage.clone(), reporter.clone()))
             .or(rpc(storage.clone()))
             .or(log(storage.clone()))
+            .or(log_connections(storage.clone(), reporter.clone()))
+            .or(chain_heads(reporter.clone()))
+            .or(chain_peers(reporter.clone()))
+            .or(propagation_summary(reporter.clone()))
+            .or(block_propagation(reporter.clone()))
+            .or(mempool(reporter.clone()))
+            .or(peers(storage.clone(), reporter.clone()))
+            .or(peer(storage.clone(), reporter.clone()))
+            .or(handshakes(storage.clone(), reporter.clone()))
             .or(stat(storage.clone()))
-            .or(self::version::api_call())
+            .or(stat_timeseries(storage.clone()))
+            .or(timeline(storage.clone()))
+            .or(alerts(storage.clone()))
+            .or(info(reporter.clone()))
+            .or(health(reporter.clone()))
+            .or(ready(reporter.clone()))
+            .or(self::version::api_call(reporter))
     )
         .with(header("Content-Type", "application/json"))
         .with(header("Access-Control-Allow-Origin", "*"))
@@ -3,10 +3,10 @@
 
 use crate::{
     storage::{
-        {MessageStore, P2pFilters},
+        {MessageStore, P2pFilters, ConnectionFilters},
         p2p_indexes::{ParseTypeError, Type},
     },
-    system::Reporter,
+    system::{Reporter, P2pReport, P2pConnectionReport},
 };
 use warp::{
     Filter, Rejection,
@@ -18,10 +18,10 @@ use warp::reply::{WithStatus, Json};
 use std::{
     net::SocketAddr,
     convert::TryInto,
-    sync::{Arc, Mutex},
 };
 use itertools::Itertools;
-use crate::messages::p2p_message::SourceType;
+use crate::messages::{p2p_message::SourceType, log_message::{LogMessage, LogMessageWithId}};
+use super::parse_timestamp;
 
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 /// Cursor structure mapped from the endpoint URI
@@ -33,22 +33,30 @@ pub struct P2pCursor {
     request_id: Option<u64>,
     incoming: Option<bool>,
     source_type: Option<SourceType>,
+    violations: Option<bool>,
+    from: Option<String>,
+    to: Option<String>,
+}
+
+/// Parse given comma separated list of types as bit-flag
+pub(crate) fn parse_types(values: &str) -> Result<Option<u32>, ParseTypeError> {
+    let mut ret = 0u32;
+    for r#type in values.split(',') {
+        let r#type: Type = r#type.parse()?;
+        ret |= r#type as u32;
+    }
+    if ret == 0 {
+        Ok(None)
+    } else {
+        Ok(Some(ret))
+    }
 }
 
 impl P2pCursor {
     /// Parse given list of types as bit-flag
     fn get_types(&self) -> Result<Option<u32>, ParseTypeError> {
         if let Some(ref values) = self.types {
-            let mut ret = 0u32;
-            for r#type in values.split(',') {
-                let r#type: Type = r#type.parse()?;
-                ret |= r#type as u32;
-            }
-            if ret == 0 {
-                Ok(None)
-            } else {
-                Ok(Some(ret))
-            }
+            parse_types(values)
         } else {
             Ok(None)
         }
@@ -56,7 +64,7 @@ impl P2pCursor {
 }
 
 impl TryInto<P2pFilters> for P2pCursor {
-    type Error = ParseTypeError;
+    type Error = failure::Error;
 
     fn try_into(self) -> Result<P2pFilters, Self::Error> {
         Ok(P2pFilters {
@@ -68,6 +76,9 @@ impl TryInto<P2pFilters> for P2pCursor {
             types: self.get_types()?,
             request_id: self.request_id,
             incoming: self.incoming,
+            violations: self.violations.unwrap_or(false),
+            from: self.from.as_ref().map(|from| parse_timestamp(from)).transpose()?,
+            to: self.to.as_ref().map(|to| parse_timestamp(to)).transpose()?,
         })
     }
 }
@@ -84,19 +95,115 @@ pub fn p2p(storage: MessageStore) -> impl Filter<Extract=(WithStatus<Json>, ), E
                     Ok(msgs) => with_status(json(&msgs), StatusCode::OK),
                     Err(err) => with_status(json(&format!("database error: {}", err)), StatusCode::INTERNAL_SERVER_ERROR),
                 },
-                Err(type_err) => with_status(json(&format!("invalid type-name: {}", type_err)), StatusCode::BAD_REQUEST),
+                Err(filter_error) => with_status(json(&format!("invalid filter: {}", filter_error)), StatusCode::BAD_REQUEST),
             }
         })
 }
 
-/// Basic handler for p2p message endpoint with cursor
-pub fn p2p_report(reporter: Arc<Mutex<Reporter>>) -> impl Filter<Extract=(WithStatus<Json>, ), Error=Rejection> + Clone + Sync + Send + 'static {
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+/// Cursor structure for the connection reports mapped from the endpoint URI
+pub struct P2pSummaryCursor {
+    cursor_id: Option<u64>,
+    limit: Option<usize>,
+    peer_id: Option<String>,
+    remote_addr: Option<SocketAddr>,
+    error: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+}
+
+impl TryInto<ConnectionFilters> for P2pSummaryCursor {
+    type Error = failure::Error;
+
+    fn try_into(self) -> Result<ConnectionFilters, Self::Error> {
+        Ok(ConnectionFilters {
+            peer_id: self.peer_id,
+            remote_addr: self.remote_addr,
+            error: self.error.map(|error| error.parse()).transpose()?,
+            from: self.from.as_ref().map(|from| parse_timestamp(from)).transpose()?,
+            to: self.to.as_ref().map(|to| parse_timestamp(to)).transpose()?,
+        })
+    }
+}
+
+/// Basic handler for p2p summary endpoint with cursor,
+/// closed connections are loaded from the database, working connections are only on the first page
+pub fn p2p_report(storage: MessageStore, reporter: Reporter) -> impl Filter<Extract=(WithStatus<Json>, ), Error=Rejection> + Clone + Sync + Send + 'static {
     warp::path!("v2" / "p2p_summary")
         .and(warp::query::query())
-        .map(move |()| -> WithStatus<Json> {
-            let report = tokio::task::block_in_place(|| futures::executor::block_on(reporter.lock().unwrap().get_p2p_report()));
+        .map(move |cursor: P2pSummaryCursor| -> WithStatus<Json> {
+            let limit = cursor.limit.unwrap_or(100);
+            let cursor_id = cursor.cursor_id.clone();
+            let filters: ConnectionFilters = match cursor.try_into() {
+                Ok(filters) => filters,
+                Err(error) => return with_status(json(&format!("invalid filter: {}", error)), StatusCode::BAD_REQUEST),
+            };
+            let closed_connections = match storage.connection().get_cursor(cursor_id, limit, filters.clone()) {
+                Ok(reports) => reports,
+                Err(err) => return with_status(json(&format!("database error: {}", err)), StatusCode::INTERNAL_SERVER_ERROR),
+            };
+            let working_connections = if cursor_id.is_none() {
+                reporter.get_p2p_report()
+                    .working_connections()
+                    .iter()
+                    .filter(|report| filters.matches(report))
+                    .cloned()
+                    .collect()
+            } else {
+                Vec::new()
+            };
+
+            with_status(json(&P2pReport::prepare(closed_connections, working_connections)), StatusCode::OK)
+        })
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+/// Cursor structure for the single connection mapped from the endpoint URI
+pub struct ConnectionCursor {
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+/// Connection together with the logs mentioning it
+pub struct ConnectionView {
+    connection: P2pConnectionReport,
+    logs: Vec<LogMessageWithId>,
+}
+
+/// Connection by its ID, closed or working, with the logs which mention its peer id or address
+pub fn connection(storage: MessageStore, reporter: Reporter) -> impl Filter<Extract=(WithStatus<Json>, ), Error=Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("v2" / "connection" / u64)
+        .and(warp::query::query())
+        .map(move |id: u64, cursor: ConnectionCursor| -> WithStatus<Json> {
+            let limit = cursor.limit.unwrap_or(100);
+            let working = || reporter.get_p2p_report()
+                .working_connections()
+                .iter()
+                .find(|report| report.id == Some(id))
+                .cloned();
+            let connection = match storage.connection().get_report(id) {
+                Ok(Some(connection)) => connection,
+                Ok(None) => match working() {
+                    Some(connection) => connection,
+                    None => return with_status(json(&format!("connection {} not found", id)), StatusCode::NOT_FOUND),
+                },
+                Err(err) => return with_status(json(&format!("database error: {}", err)), StatusCode::INTERNAL_SERVER_ERROR),
+            };
+            match storage.log().get_related(&connection.references(), limit) {
+                Ok(logs) => with_status(json(&ConnectionView { connection, logs: LogMessage::enumerate(logs) }), StatusCode::OK),
+                Err(err) => with_status(json(&format!("database error: {}", err)), StatusCode::INTERNAL_SERVER_ERROR),
+            }
+        })
+}
 
-            with_status(json(&report), StatusCode::OK)
+/// Discrepancies between the debugger counts and the peer metadata reported by the node
+pub fn p2p_report_compare(reporter: Reporter) -> impl Filter<Extract=(WithStatus<Json>, ), Error=Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("v2" / "p2p_summary" / "compare")
+        .map(move || -> WithStatus<Json> {
+            match reporter.get_p2p_comparison() {
+                Some(comparison) => with_status(json(&comparison), StatusCode::OK),
+                None => with_status(json(&"the node was not reachable yet"), StatusCode::SERVICE_UNAVAILABLE),
+            }
         })
 }
 
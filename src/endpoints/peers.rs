@@ -0,0 +1,73 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use crate::{
+    storage::{MessageStore, get_ts},
+    system::{Reporter, P2pConnectionReport, PeerMetric, peer_scores},
+};
+use warp::{
+    Filter, Rejection,
+    reply::{with_status, json, WithStatus, Json},
+    http::StatusCode,
+};
+use serde::{Serialize, Deserialize};
+use storage::StorageError;
+
+/// How many of the latest closed connections are scored
+const MAX_CONNECTIONS: usize = 0x10000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Cursor structure mapped from the endpoint URI
+pub struct PeersCursor {
+    limit: Option<usize>,
+    sort_by: Option<String>,
+}
+
+/// The latest closed connections and all working connections satisfying the predicate
+fn connections<F>(storage: &MessageStore, reporter: &Reporter, predicate: F) -> Result<Vec<P2pConnectionReport>, StorageError>
+where
+    F: Fn(&P2pConnectionReport) -> bool,
+{
+    let mut reports = storage.connection().find(MAX_CONNECTIONS, &predicate)?;
+    reports.extend(reporter.get_p2p_report().working_connections().iter().filter(|r| predicate(r)).cloned());
+    Ok(reports)
+}
+
+/// Scorecards of all peers the node was connected to, sorted by the metric in descending order
+pub fn peers(storage: MessageStore, reporter: Reporter) -> impl Filter<Extract=(WithStatus<Json>, ), Error=Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("v2" / "peers")
+        .and(warp::query::query())
+        .map(move |cursor: PeersCursor| -> WithStatus<Json> {
+            let limit = cursor.limit.unwrap_or(100);
+            let sort_by = match cursor.sort_by.as_ref().map(|metric| metric.parse::<PeerMetric>()).transpose() {
+                Ok(sort_by) => sort_by.unwrap_or(PeerMetric::Connections),
+                Err(error) => return with_status(json(&format!("invalid filter: {}", error)), StatusCode::BAD_REQUEST),
+            };
+            match connections(&storage, &reporter, |_| true) {
+                Ok(reports) => {
+                    let mut scores = peer_scores(&reports, sort_by, get_ts());
+                    scores.truncate(limit);
+                    with_status(json(&scores), StatusCode::OK)
+                },
+                Err(err) => with_status(json(&format!("database error: {}", err)), StatusCode::INTERNAL_SERVER_ERROR),
+            }
+        })
+}
+
+/// Scorecard of the single peer, by its peer id, or by the remote address if the handshake failed
+pub fn peer(storage: MessageStore, reporter: Reporter) -> impl Filter<Extract=(WithStatus<Json>, ), Error=Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("v2" / "peers" / String)
+        .map(move |peer: String| -> WithStatus<Json> {
+            let predicate = |report: &P2pConnectionReport| match report.peer_id {
+                Some(ref peer_id) => peer_id == &peer,
+                None => report.remote_address == peer,
+            };
+            match connections(&storage, &reporter, predicate) {
+                Ok(reports) => match peer_scores(&reports, PeerMetric::Connections, get_ts()).into_iter().next() {
+                    Some(score) => with_status(json(&score), StatusCode::OK),
+                    None => with_status(json(&format!("peer {} not found", peer)), StatusCode::NOT_FOUND),
+                },
+                Err(err) => with_status(json(&format!("database error: {}", err)), StatusCode::INTERNAL_SERVER_ERROR),
+            }
+        })
+}
@@ -9,21 +9,31 @@ use warp::{
 };
 use serde::{Serialize, Deserialize};
 use warp::reply::{WithStatus, Json};
-use std::net::SocketAddr;
+use std::{
+    net::SocketAddr,
+    convert::TryInto,
+};
+use super::parse_timestamp;
 
-#[derive(Default, Debug, Copy, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 /// Cursor structure mapped from the endpoint URI
 pub struct RpcCursor {
     pub cursor_id: Option<u64>,
     pub limit: Option<usize>,
     pub remote_addr: Option<SocketAddr>,
+    pub from: Option<String>,
+    pub to: Option<String>,
 }
 
-impl Into<crate::storage::RpcFilters> for RpcCursor {
-    fn into(self) -> RpcFilters {
-        RpcFilters {
+impl TryInto<RpcFilters> for RpcCursor {
+    type Error = failure::Error;
+
+    fn try_into(self) -> Result<RpcFilters, Self::Error> {
+        Ok(RpcFilters {
             remote_addr: self.remote_addr,
-        }
+            from: self.from.as_ref().map(|from| parse_timestamp(from)).transpose()?,
+            to: self.to.as_ref().map(|to| parse_timestamp(to)).transpose()?,
+        })
     }
 }
 
@@ -34,9 +44,12 @@ pub fn rpc(storage: MessageStore) -> impl Filter<Extract=impl Reply, Error=Rejec
         .map(move |cursor: RpcCursor| -> WithStatus<Json> {
             let limit = cursor.limit.unwrap_or(100);
             let cursor_id = cursor.cursor_id.clone();
-            match storage.rpc().get_cursor(cursor_id, limit, cursor.into()) {
-                Ok(msgs) => with_status(json(&msgs), StatusCode::OK),
-                Err(err) => with_status(json(&format!("database error: {}", err)), StatusCode::INTERNAL_SERVER_ERROR),
+            match cursor.try_into() {
+                Ok(filters) => match storage.rpc().get_cursor(cursor_id, limit, filters) {
+                    Ok(msgs) => with_status(json(&msgs), StatusCode::OK),
+                    Err(err) => with_status(json(&format!("database error: {}", err)), StatusCode::INTERNAL_SERVER_ERROR),
+                },
+                Err(filter_error) => with_status(json(&format!("invalid filter: {}", filter_error)), StatusCode::BAD_REQUEST),
             }
         })
 }
\ No newline at end of file
@@ -1,7 +1,10 @@
-use crate::storage::MessageStore;
+use crate::storage::{MessageStore, Resolution, Grouping, TimeseriesFilters, get_ts};
 use warp::{Filter, Rejection};
 use warp::reply::{WithStatus, Json, with_status, json};
 use warp::http::StatusCode;
+use serde::{Serialize, Deserialize};
+use std::net::SocketAddr;
+use super::parse_timestamp;
 
 /// EXPERIMENTAL: Basic statistics endpoint
 pub fn stat(storage: MessageStore) -> impl Filter<Extract=(WithStatus<Json>, ), Error=Rejection> + Clone + Sync + Send + 'static {
@@ -10,3 +13,65 @@ pub fn stat(storage: MessageStore) -> impl Filter<Extract=(WithStatus<Json>, ),
             with_status(json(&storage.stat().snapshot()), StatusCode::OK)
         })
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Cursor structure mapped from the endpoint URI
+pub struct TimeseriesCursor {
+    resolution: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    group_by: Option<String>,
+    remote_addr: Option<SocketAddr>,
+    incoming: Option<bool>,
+    message_type: Option<String>,
+}
+
+impl TimeseriesCursor {
+    /// Resolution, window in seconds and grouping, the window is the last 60 buckets by default
+    fn query(&self) -> Result<(Resolution, u64, u64, Grouping), failure::Error> {
+        let resolution = match self.resolution {
+            Some(ref resolution) => resolution.parse()?,
+            None => Resolution::Minute,
+        };
+        let seconds = |ts: u128| (ts / 1_000_000_000) as u64;
+        let to = match self.to {
+            Some(ref to) => seconds(parse_timestamp(to)?),
+            None => seconds(get_ts()),
+        };
+        let from = match self.from {
+            Some(ref from) => seconds(parse_timestamp(from)?),
+            None => to.saturating_sub(59 * resolution.seconds()),
+        };
+        let mut grouping = Grouping::default();
+        for group in self.group_by.iter().flat_map(|group_by| group_by.split(',')) {
+            match group {
+                "peer" => grouping.peer = true,
+                "direction" => grouping.direction = true,
+                "type" => grouping.message_type = true,
+                group => return Err(failure::format_err!("unknown group {}", group)),
+            }
+        }
+        Ok((resolution, from, to, grouping))
+    }
+}
+
+/// Bytes and message counts in the buckets of the time window, oldest first
+pub fn stat_timeseries(storage: MessageStore) -> impl Filter<Extract=(WithStatus<Json>, ), Error=Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("v2" / "stat" / "timeseries")
+        .and(warp::query::query())
+        .map(move |cursor: TimeseriesCursor| -> WithStatus<Json> {
+            let (resolution, from, to, grouping) = match cursor.query() {
+                Ok(query) => query,
+                Err(error) => return with_status(json(&format!("invalid filter: {}", error)), StatusCode::BAD_REQUEST),
+            };
+            let filters = TimeseriesFilters {
+                remote_addr: cursor.remote_addr,
+                incoming: cursor.incoming,
+                message_type: cursor.message_type,
+            };
+            match storage.timeseries().get_range(resolution, from, to, grouping, &filters) {
+                Ok(points) => with_status(json(&points), StatusCode::OK),
+                Err(err) => with_status(json(&format!("database error: {}", err)), StatusCode::INTERNAL_SERVER_ERROR),
+            }
+        })
+}
@@ -0,0 +1,337 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use crate::{
+    storage::{MessageStore, LogFilters, P2pFilters, RpcFilters},
+    messages::{log_message::LogMessage, p2p_message::P2pMessage, rpc_message::RpcMessage},
+};
+use warp::{
+    Filter, Rejection,
+    reply::{with_status, json, WithStatus, Json},
+    http::StatusCode,
+};
+use serde::{Serialize, Deserialize};
+use std::net::SocketAddr;
+use itertools::Itertools;
+use super::{parse_timestamp, log::parse_levels, p2p::parse_types};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, strum::EnumString, strum::AsRefStr)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+/// Store the event of the timeline comes from
+pub enum Source {
+    Log,
+    P2p,
+    Rpc,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "source", content = "event", rename_all = "snake_case")]
+/// Single event of the timeline
+pub enum TimelineEvent {
+    Log(LogMessage),
+    P2p(P2pMessage),
+    Rpc(RpcMessage),
+}
+
+impl TimelineEvent {
+    /// Unix timestamp in nanoseconds, the timeline is sorted by it
+    pub fn timestamp(&self) -> u128 {
+        match self {
+            TimelineEvent::Log(msg) => msg.date,
+            TimelineEvent::P2p(msg) => msg.timestamp,
+            TimelineEvent::Rpc(msg) => msg.timestamp,
+        }
+    }
+
+    /// Index of the event in its store
+    fn index(&self) -> Option<u64> {
+        match self {
+            TimelineEvent::Log(msg) => msg.id,
+            TimelineEvent::P2p(msg) => msg.id,
+            TimelineEvent::Rpc(msg) => Some(msg.id),
+        }
+    }
+
+    fn source(&self) -> Source {
+        match self {
+            TimelineEvent::Log(_) => Source::Log,
+            TimelineEvent::P2p(_) => Source::P2p,
+            TimelineEvent::Rpc(_) => Source::Rpc,
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+/// Cursor structure mapped from the endpoint URI, each store has its own cursor
+pub struct TimelineCursor {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sources: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    log_cursor_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    p2p_cursor_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rpc_cursor_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    log_level: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    log_query: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    log_section: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    p2p_remote_addr: Option<SocketAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    p2p_types: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    p2p_incoming: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rpc_remote_addr: Option<SocketAddr>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+/// Page of the timeline, `next` is the cursor of the following page, absent if there are no more events
+pub struct Timeline {
+    events: Vec<TimelineEvent>,
+    next: Option<TimelineCursor>,
+}
+
+/// Filters of the timeline parsed from the cursor
+struct TimelineFilters {
+    sources: Vec<Source>,
+    log: LogFilters,
+    p2p: P2pFilters,
+    rpc: RpcFilters,
+}
+
+impl TimelineCursor {
+    /// Number of events on the page, zero is rejected, as the page would never end
+    fn limit(&self) -> Result<usize, failure::Error> {
+        match self.limit {
+            Some(0) => Err(failure::format_err!("limit must be greater than zero")),
+            limit => Ok(limit.unwrap_or(100)),
+        }
+    }
+
+    fn filters(&self) -> Result<TimelineFilters, failure::Error> {
+        let sources = match self.sources {
+            Some(ref sources) => sources.split(',').map(str::parse).collect::<Result<Vec<Source>, _>>()?,
+            None => vec![Source::Log, Source::P2p, Source::Rpc],
+        };
+        let from = self.from.as_ref().map(|from| parse_timestamp(from)).transpose()?;
+        let to = self.to.as_ref().map(|to| parse_timestamp(to)).transpose()?;
+        Ok(TimelineFilters {
+            sources,
+            log: LogFilters {
+                level: self.log_level.as_ref().map(|level| parse_levels(level)).transpose()?.unwrap_or_default(),
+                from,
+                to,
+                query: self.log_query.clone(),
+                section: self.log_section.clone(),
+                ..LogFilters::default()
+            },
+            p2p: P2pFilters {
+                remote_addr: self.p2p_remote_addr,
+                types: self.p2p_types.as_ref().map(|types| parse_types(types)).transpose()?.flatten(),
+                incoming: self.p2p_incoming,
+                from,
+                to,
+                ..P2pFilters::default()
+            },
+            rpc: RpcFilters {
+                remote_addr: self.rpc_remote_addr,
+                from,
+                to,
+            },
+        })
+    }
+
+    fn cursor_id(&mut self, source: Source) -> &mut Option<u64> {
+        match source {
+            Source::Log => &mut self.log_cursor_id,
+            Source::P2p => &mut self.p2p_cursor_id,
+            Source::Rpc => &mut self.rpc_cursor_id,
+        }
+    }
+}
+
+/// Load one page of the timeline, each requested store is asked for `limit` events,
+/// the results are merged by timestamp in descending order.
+/// The stores give the events by the index, the dates of the logs come from the node and might not follow the index,
+/// the merge keeps the events of each store in the order of the index, so the cursors skip nothing,
+/// and the page is sorted afterwards. The order across the pages is best-effort for the logs
+fn load(storage: &MessageStore, cursor: &TimelineCursor, limit: usize, filters: TimelineFilters) -> Result<Timeline, failure::Error> {
+    let mut loaded = Vec::with_capacity(filters.sources.len());
+    let mut iters = Vec::with_capacity(filters.sources.len());
+    for &source in &filters.sources {
+        let events: Vec<TimelineEvent> = match source {
+            Source::Log => storage.log().get_cursor(cursor.log_cursor_id, limit, filters.log.clone())?
+                .into_iter().map(TimelineEvent::Log).collect(),
+            Source::P2p => storage.p2p().get_cursor(cursor.p2p_cursor_id, limit, filters.p2p.clone())?
+                .into_iter().map(TimelineEvent::P2p).collect(),
+            Source::Rpc => storage.rpc().get_cursor(cursor.rpc_cursor_id, limit, filters.rpc.clone())?
+                .into_iter().map(TimelineEvent::Rpc).collect(),
+        };
+        loaded.push((source, events.len()));
+        iters.push(events.into_iter());
+    }
+
+    let mut events = iters.into_iter()
+        .kmerge_by(|x, y| x.timestamp() > y.timestamp())
+        .take(limit)
+        .collect::<Vec<_>>();
+    events.sort_by(|x, y| y.timestamp().cmp(&x.timestamp()));
+
+    // the store continues right after its oldest event on the page,
+    // the store is done, if all loaded events are on the page and the store had less than `limit` of them
+    let mut next = cursor.clone();
+    let mut sources = Vec::with_capacity(loaded.len());
+    for (source, loaded) in loaded {
+        let on_page = events.iter().filter(|event| event.source() == source);
+        let count = on_page.clone().count();
+        if let Some(oldest) = on_page.filter_map(TimelineEvent::index).min() {
+            match oldest.checked_sub(1) {
+                Some(cursor_id) => *next.cursor_id(source) = Some(cursor_id),
+                None => continue,
+            }
+        }
+        if count < loaded || loaded == limit {
+            sources.push(source.as_ref().to_string());
+        }
+    }
+    let next = if sources.is_empty() {
+        None
+    } else {
+        next.sources = Some(sources.join(","));
+        Some(next)
+    };
+    Ok(Timeline { events, next })
+}
+
+/// Timeline of logs, P2P and RPC messages merged by timestamp, newest first
+pub fn timeline(storage: MessageStore) -> impl Filter<Extract=(WithStatus<Json>, ), Error=Rejection> + Clone + Sync + Send + 'static {
+    warp::path!("v2" / "timeline")
+        .and(warp::query::query())
+        .map(move |cursor: TimelineCursor| -> WithStatus<Json> {
+            let (limit, filters) = match cursor.limit().and_then(|limit| Ok((limit, cursor.filters()?))) {
+                Ok(parsed) => parsed,
+                Err(error) => return with_status(json(&format!("invalid filter: {}", error)), StatusCode::BAD_REQUEST),
+            };
+            match load(&storage, &cursor, limit, filters) {
+                Ok(timeline) => with_status(json(&timeline), StatusCode::OK),
+                Err(err) => with_status(json(&format!("database error: {}", err)), StatusCode::INTERNAL_SERVER_ERROR),
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use crate::{
+        messages::rpc_message::RESTMessage,
+        storage::{open_temp_db, remove_temp_db},
+    };
+
+    fn store(storage: &MessageStore, logs: &[u128], rpcs: &[u128]) {
+        for &date in logs {
+            storage.log().store_message(&mut LogMessage::new(date, "info", "node", "log", BTreeMap::new())).unwrap();
+        }
+        for &timestamp in rpcs {
+            storage.rpc().store_message(&mut RpcMessage {
+                incoming: true,
+                timestamp,
+                id: 0,
+                remote_addr: "127.0.0.1:8732".parse().unwrap(),
+                message: RESTMessage::Response { status: "200".to_string(), payload: String::new() },
+            }).unwrap();
+        }
+    }
+
+    fn page(storage: &MessageStore, cursor: &TimelineCursor) -> (Vec<(Source, u128)>, Option<TimelineCursor>) {
+        let timeline = load(storage, cursor, cursor.limit().unwrap(), cursor.filters().unwrap()).unwrap();
+        let events = timeline.events.iter().map(|event| (event.source(), event.timestamp())).collect();
+        (events, timeline.next)
+    }
+
+    #[test]
+    fn merge_order() {
+        let db = open_temp_db("timeline_merge");
+        let storage = MessageStore::new(db.clone(), db.clone()).unwrap();
+        store(&storage, &[10, 30, 50], &[20, 40]);
+
+        let cursor = TimelineCursor { sources: Some("log,rpc".to_string()), ..TimelineCursor::default() };
+        let (events, next) = page(&storage, &cursor);
+        assert_eq!(events, vec![(Source::Log, 50), (Source::Rpc, 40), (Source::Log, 30), (Source::Rpc, 20), (Source::Log, 10)]);
+        assert!(next.is_none());
+
+        let cursor = TimelineCursor { sources: Some("rpc".to_string()), ..TimelineCursor::default() };
+        assert_eq!(page(&storage, &cursor).0, vec![(Source::Rpc, 40), (Source::Rpc, 20)]);
+
+        drop(storage);
+        remove_temp_db(db);
+    }
+
+    #[test]
+    fn unordered_logs() {
+        let db = open_temp_db("timeline_unordered");
+        let storage = MessageStore::new(db.clone(), db.clone()).unwrap();
+        // the dates of the logs do not follow the order they were stored in
+        store(&storage, &[30, 10, 50], &[20]);
+
+        let cursor = TimelineCursor { sources: Some("log,rpc".to_string()), ..TimelineCursor::default() };
+        let (events, _) = page(&storage, &cursor);
+        assert_eq!(events, vec![(Source::Log, 50), (Source::Log, 30), (Source::Rpc, 20), (Source::Log, 10)]);
+
+        // each page is sorted, no log is skipped, but the order across the pages is best-effort
+        let cursor = TimelineCursor { limit: Some(2), sources: Some("log".to_string()), ..TimelineCursor::default() };
+        let (events, next) = page(&storage, &cursor);
+        assert_eq!(events, vec![(Source::Log, 50), (Source::Log, 10)]);
+        let (events, next) = page(&storage, &next.unwrap());
+        assert_eq!(events, vec![(Source::Log, 30)]);
+        assert!(next.is_none());
+
+        drop(storage);
+        remove_temp_db(db);
+    }
+
+    #[test]
+    fn pages() {
+        let db = open_temp_db("timeline_pages");
+        let storage = MessageStore::new(db.clone(), db.clone()).unwrap();
+        store(&storage, &[10, 30, 50], &[20, 40]);
+
+        let cursor = TimelineCursor { limit: Some(2), sources: Some("log,rpc".to_string()), ..TimelineCursor::default() };
+        let (events, next) = page(&storage, &cursor);
+        assert_eq!(events, vec![(Source::Log, 50), (Source::Rpc, 40)]);
+        let next = next.unwrap();
+        assert_eq!((next.log_cursor_id, next.rpc_cursor_id), (Some(1), Some(0)));
+        assert_eq!(next.sources.as_deref(), Some("log,rpc"));
+
+        // the rpc store is exhausted, only the log store continues
+        let (events, next) = page(&storage, &next);
+        assert_eq!(events, vec![(Source::Log, 30), (Source::Rpc, 20)]);
+        let next = next.unwrap();
+        assert_eq!(next.log_cursor_id, Some(0));
+        assert_eq!(next.sources.as_deref(), Some("log"));
+
+        let (events, next) = page(&storage, &next);
+        assert_eq!(events, vec![(Source::Log, 10)]);
+        assert!(next.is_none());
+
+        drop(storage);
+        remove_temp_db(db);
+    }
+
+    #[test]
+    fn zero_limit() {
+        assert!(TimelineCursor { limit: Some(0), ..TimelineCursor::default() }.limit().is_err());
+        assert_eq!(TimelineCursor::default().limit().unwrap(), 100);
+    }
+}
@@ -1,16 +1,28 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+use serde::Serialize;
 use warp::{
     Filter, Rejection,
     reply::{with_status, json, WithStatus, Json},
     http::StatusCode,
 };
+use crate::system::{Reporter, NetworkProfile};
 
-pub fn api_call() -> impl Filter<Extract=(WithStatus<Json>, ), Error=Rejection> + Clone + Sync + Send + 'static {
+#[derive(Serialize)]
+struct Version<'a> {
+    git_hash: &'static str,
+    network: &'a NetworkProfile,
+}
+
+pub fn api_call(reporter: Reporter) -> impl Filter<Extract=(WithStatus<Json>, ), Error=Rejection> + Clone + Sync + Send + 'static {
     warp::path!("v2" / "version")
         .and(warp::query::query())
         .map(move |()| -> WithStatus<Json> {
-            with_status(json(&env!("GIT_HASH")), StatusCode::OK)
+            let version = Version {
+                git_hash: env!("GIT_HASH"),
+                network: reporter.get_network(),
+            };
+            with_status(json(&version), StatusCode::OK)
         })
 }
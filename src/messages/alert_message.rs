@@ -0,0 +1,45 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use serde::{Serialize, Deserialize};
+use storage::persistent::{Encoder, SchemaError, Decoder};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Alert raised by the rule, as stored in the database and sent to the webhook
+pub struct Alert {
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub id: Option<u64>,
+    pub timestamp: u128,
+    // name of the rule from the configuration
+    pub rule: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub remote_addr: Option<String>,
+}
+
+impl Alert {
+    /// Create new alert raised by the rule at the time
+    pub fn new(timestamp: u128, rule: &str, message: String, remote_addr: Option<String>) -> Self {
+        Alert {
+            id: None,
+            timestamp,
+            rule: rule.to_string(),
+            message,
+            remote_addr,
+        }
+    }
+}
+
+impl Decoder for Alert {
+    fn decode(bytes: &[u8]) -> Result<Self, SchemaError> {
+        serde_cbor::from_slice(bytes)
+            .map_err(|_| SchemaError::DecodeError)
+    }
+}
+
+impl Encoder for Alert {
+    fn encode(&self) -> Result<Vec<u8>, SchemaError> {
+        serde_cbor::to_vec(self)
+            .map_err(|_| SchemaError::EncodeError)
+    }
+}
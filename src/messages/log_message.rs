@@ -1,9 +1,10 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+use std::collections::BTreeMap;
 use serde::{Serialize, Deserialize};
 use crate::storage::get_ts;
-use syslog_loose::Message;
+use crate::messages::log_parser::references;
 use storage::persistent::BincodeEncoded;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -16,6 +17,10 @@ pub struct LogMessage {
     pub section: String,
     #[serde(alias = "msg", rename(serialize = "message"))]
     pub message: String,
+    pub fields: BTreeMap<String, String>,
+    // mentioned in the message or in the fields, links the log to P2P connections
+    pub peer_ids: Vec<String>,
+    pub addresses: Vec<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub id: Option<u64>,
 }
@@ -30,12 +35,30 @@ pub struct LogMessageWithId {
     pub section: String,
     #[serde(alias = "msg", rename(serialize = "message"))]
     pub message: String,
+    pub fields: BTreeMap<String, String>,
+    pub peer_ids: Vec<String>,
+    pub addresses: Vec<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub id: Option<u64>,
     pub ordinal_id: Option<u64>,
 }
 
 impl LogMessage {
+    /// Create new log from parsed parts, see [crate::messages::log_parser]
+    pub fn new(date: u128, level: &str, section: &str, message: &str, fields: BTreeMap<String, String>) -> Self {
+        let (peer_ids, addresses) = references(Some(message).into_iter().chain(fields.values().map(String::as_str)));
+        Self {
+            level: level.to_string(),
+            date,
+            section: section.to_string(),
+            message: message.to_string(),
+            fields,
+            peer_ids,
+            addresses,
+            id: None,
+        }
+    }
+
     /// Create new log from undefined raw string
     pub fn raw(line: String) -> Self {
         Self {
@@ -44,6 +67,9 @@ impl LogMessage {
             section: "".to_string(),
             id: None,
             message: line,
+            fields: BTreeMap::new(),
+            peer_ids: Vec::new(),
+            addresses: Vec::new(),
         }
     }
 
@@ -55,89 +81,14 @@ impl LogMessage {
                 date: x.date,
                 section: x.section,
                 message: x.message,
+                fields: x.fields,
+                peer_ids: x.peer_ids,
+                addresses: x.addresses,
                 id: x.id,
                 ordinal_id: Some(ordinal as u64),
             })
             .collect()
     }
-
-    /// Parse rust formatted log
-    fn rust_log_line(line: &str) -> Option<(&str, &str)> {
-        let (_, level_msg) = line.split_at(20);
-        let level = level_msg.split_whitespace().next()?;
-        let msg = &level_msg[level.len() + 1..];
-        Some((level, msg))
-    }
-
-    /// Parse ocaml formatted log
-    fn ocaml_log_line(line: &str) -> Option<(&str, &str)> {
-        let mut parts = line.split("-");
-        let _ = parts.next();
-        let msg = parts.next();
-        if let Some(value) = msg {
-            let mut parts = value.split(":");
-            let _ = parts.next();
-            let msg = parts.next();
-            if let Some(msg) = msg {
-                Some(("info", msg.trim()))
-            } else {
-                Some(("info", value.trim()))
-            }
-        } else {
-            Some(("info", line.trim()))
-        }
-    }
-}
-
-impl<S: AsRef<str> + Ord + PartialEq + Clone> From<syslog_loose::Message<S>> for LogMessage {
-    /// Create LogMessage from received syslog message
-    /// Syslog messages are of format:
-    /// <27>1 2020-06-24T10:32:37.026683+02:00 Ubuntu-1910-eoan-64-minimal 451e91e7df18 1482 451e91e7df18 - Jun 24 08:32:37.026 INFO Blacklisting IP because peer failed at bootstrap process, ip: 104.248.136.94
-    fn from(msg: Message<S>) -> Self {
-        let date = msg.timestamp
-            .map(|dt| dt.timestamp_nanos() as u128)
-            .unwrap_or_else(get_ts);
-        let line = msg.msg.as_ref();
-
-        let pos = line.find('.').unwrap_or_default();
-        if pos == 15 {
-            if let Some((level, message)) = Self::rust_log_line(line) {
-                Self {
-                    date,
-                    level: level.to_string(),
-                    message: message.to_string(),
-                    section: "".to_string(),
-                    id: None,
-                }
-            } else {
-                Self {
-                    date,
-                    level: "fatal".to_string(),
-                    section: "".to_string(),
-                    id: None,
-                    message: line.to_string(),
-                }
-            }
-        } else {
-            if let Some((level, message)) = Self::ocaml_log_line(line) {
-                Self {
-                    date,
-                    level: level.to_string(),
-                    message: message.to_string(),
-                    section: "".to_string(),
-                    id: None,
-                }
-            } else {
-                Self {
-                    date,
-                    level: "fatal".to_string(),
-                    section: "".to_string(),
-                    id: None,
-                    message: line.to_string(),
-                }
-            }
-        }
-    }
 }
 
 impl BincodeEncoded for LogMessage {}
\ No newline at end of file
@@ -0,0 +1,429 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::{IpAddr, SocketAddr},
+};
+use chrono::{DateTime, Datelike, NaiveDateTime, TimeZone, Utc};
+use crate::messages::log_message::LogMessage;
+
+/// Parser of the single log format
+pub trait LogParser: Send + Sync {
+    /// Name of the format, for diagnostics
+    fn name(&self) -> &'static str;
+
+    /// Parse the line, returns `None` if the line is not in this format.
+    /// The `date` is when the line was captured, used if the line has no complete date
+    fn parse(&self, date: u128, line: &str) -> Option<LogMessage>;
+}
+
+/// Ordered list of log parsers, the first parser which recognizes the line wins
+pub struct LogParserRegistry {
+    parsers: Vec<Box<dyn LogParser>>,
+}
+
+impl Default for LogParserRegistry {
+    /// Registry with all known formats
+    fn default() -> Self {
+        let mut registry = LogParserRegistry::new();
+        registry.register(JsonParser);
+        registry.register(TezedgeParser);
+        registry.register(OcamlParser);
+        registry
+    }
+}
+
+impl LogParserRegistry {
+    /// Empty registry, every line will be stored as is
+    pub fn new() -> Self {
+        LogParserRegistry {
+            parsers: Vec::new(),
+        }
+    }
+
+    /// Add the parser with the lowest priority
+    pub fn register<P>(&mut self, parser: P)
+    where
+        P: LogParser + 'static,
+    {
+        self.parsers.push(Box::new(parser));
+    }
+
+    /// Parse the line by the first parser which recognizes it,
+    /// the line is stored as is with the `info` level if no parser recognizes it
+    pub fn parse(&self, date: u128, line: &str) -> LogMessage {
+        let line = line.trim_end();
+        self.parsers.iter()
+            .find_map(|parser| parser.parse(date, line))
+            .unwrap_or_else(|| LogMessage::new(date, "info", "", line, BTreeMap::new()))
+    }
+}
+
+/// Normalize the level name to one of the names known by the log store:
+/// `trace`, `debug`, `info`, `notice`, `warning`, `error`, `fatal`
+pub fn normalize_level(level: &str) -> Option<&'static str> {
+    let level = match level.trim().to_lowercase().as_str() {
+        "trce" | "trace" | "10" => "trace",
+        "debg" | "debug" | "20" => "debug",
+        "info" | "30" => "info",
+        "notice" => "notice",
+        "warn" | "warning" | "40" => "warning",
+        "erro" | "error" | "err" | "50" => "error",
+        "crit" | "critical" | "fatal" | "60" => "fatal",
+        _ => return None,
+    };
+    Some(level)
+}
+
+/// Parse date in syslog-like format without year `Jun 24 08:32:37.026` (milliseconds are optional),
+/// returns the length of the date in the line
+fn date_length(line: &str) -> Option<usize> {
+    let bytes = line.as_bytes();
+    // `Mmm dd HH:MM:SS`
+    if bytes.len() < 15 || !line.is_char_boundary(15) {
+        return None;
+    }
+    let month_ok = bytes[0..3].iter().all(u8::is_ascii_alphabetic) && bytes[3] == b' ';
+    let day_ok = (bytes[4] == b' ' || bytes[4].is_ascii_digit()) && bytes[5].is_ascii_digit() && bytes[6] == b' ';
+    let time_ok = bytes[7..15].iter().enumerate().all(|(i, b)| if i % 3 == 2 { *b == b':' } else { b.is_ascii_digit() });
+    if !(month_ok && day_ok && time_ok) {
+        return None;
+    }
+    if bytes.len() > 15 && bytes[15] == b'.' {
+        let fraction = bytes[16..].iter().take_while(|b| b.is_ascii_digit()).count();
+        Some(16 + fraction)
+    } else {
+        Some(15)
+    }
+}
+
+/// Date at the beginning of the line in syslog-like format without year, like the one `date_length` recognizes,
+/// as unix timestamp in nanoseconds; the year is the year of `captured`, or the previous one if the date
+/// would be more than a day after `captured`, the date is taken as UTC
+pub fn line_date(line: &str, captured: u128) -> Option<u128> {
+    const DAY: i64 = 24 * 60 * 60 * 1_000_000_000;
+
+    let date = &line[..date_length(line)?];
+    let captured = captured as i64;
+    let year = Utc.timestamp_nanos(captured).year();
+    let parse = |year: i32| NaiveDateTime::parse_from_str(&format!("{} {}", year, date), "%Y %b %e %H:%M:%S%.f")
+        .ok()
+        .map(|date| date.timestamp_nanos());
+    let mut nanos = parse(year)?;
+    if nanos > captured + DAY {
+        nanos = parse(year - 1)?;
+    }
+    if nanos < 0 {
+        None
+    } else {
+        Some(nanos as u128)
+    }
+}
+
+/// Take key-value pairs `, key: value` from the end of the slog message
+fn slog_fields(message: &str) -> (&str, BTreeMap<String, String>) {
+    let mut fields = BTreeMap::new();
+    let mut message = message;
+    while let Some(pos) = message.rfind(", ") {
+        let pair = &message[(pos + 2)..];
+        match pair.find(": ") {
+            Some(colon) if is_key(&pair[..colon]) => {
+                fields.insert(pair[..colon].to_string(), pair[(colon + 2)..].to_string());
+                message = &message[..pos];
+            },
+            _ => break,
+        }
+    }
+    (message, fields)
+}
+
+/// Take key-value pairs `(key = value, key = value)` from the end of the ocaml message
+fn ocaml_fields(message: &str) -> (&str, BTreeMap<String, String>) {
+    let mut fields = BTreeMap::new();
+    if message.ends_with(')') {
+        if let Some(open) = message.rfind(" (") {
+            let inner = &message[(open + 2)..(message.len() - 1)];
+            for pair in inner.split(", ") {
+                match pair.find(" = ") {
+                    Some(eq) if is_key(&pair[..eq]) => {
+                        fields.insert(pair[..eq].to_string(), pair[(eq + 3)..].to_string());
+                    },
+                    _ => return (message, BTreeMap::new()),
+                }
+            }
+            return (&message[..open], fields);
+        }
+    }
+    (message, fields)
+}
+
+/// Peer ids and addresses mentioned in the texts, sorted and without duplicates.
+/// The address with port is also referenced by its IP alone, logs often mention only the IP
+pub fn references<'a, I>(texts: I) -> (Vec<String>, Vec<String>)
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut peer_ids = BTreeSet::new();
+    let mut addresses = BTreeSet::new();
+    let words = texts.into_iter()
+        .flat_map(|text| text.split(|c: char| c.is_whitespace() || ",;()<>\"'=".contains(c)))
+        .map(|word| word.trim_end_matches(|c| c == '.' || c == ':'));
+    for word in words {
+        if is_peer_id(word) {
+            peer_ids.insert(word.to_string());
+        } else if let Ok(address) = word.parse::<SocketAddr>() {
+            addresses.insert(address.to_string());
+            addresses.insert(address.ip().to_string());
+        } else if let Ok(ip) = word.parse::<IpAddr>() {
+            addresses.insert(ip.to_string());
+        }
+    }
+    (peer_ids.into_iter().collect(), addresses.into_iter().collect())
+}
+
+/// Peer id is the base58 encoded hash of the public key, `id` and 28 characters
+fn is_peer_id(word: &str) -> bool {
+    const BASE58: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+    word.len() == 30 && word.starts_with("id") && word.chars().all(|c| BASE58.contains(c))
+}
+
+fn is_key(key: &str) -> bool {
+    !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-')
+}
+
+/// TezEdge node slog terminal format:
+/// `Jun 24 08:32:37.026 INFO Blacklisting IP because peer failed at bootstrap process, ip: 104.248.136.94`
+pub struct TezedgeParser;
+
+impl LogParser for TezedgeParser {
+    fn name(&self) -> &'static str {
+        "tezedge"
+    }
+
+    fn parse(&self, date: u128, line: &str) -> Option<LogMessage> {
+        let rest = &line[date_length(line)?..];
+        let rest = rest.strip_prefix(' ')?;
+        let (level, message) = match rest.find(' ') {
+            Some(pos) => (&rest[..pos], rest[pos..].trim_start()),
+            None => (rest, ""),
+        };
+        let level = normalize_level(level)?;
+        let (message, mut fields) = slog_fields(message);
+        let section = fields.remove("module")
+            .or_else(|| fields.remove("section"))
+            .unwrap_or_default();
+        Some(LogMessage::new(date, level, &section, message, fields))
+    }
+}
+
+/// OCaml node format `Jun 24 08:32:37.026 - p2p.maintenance: Too few connections (min = 10)`,
+/// the level might be after the date `Jun 24 08:32:37.026 warning - ...`,
+/// or at the beginning of the message `... - node.main: WARNING: ...`
+pub struct OcamlParser;
+
+impl LogParser for OcamlParser {
+    fn name(&self) -> &'static str {
+        "ocaml"
+    }
+
+    fn parse(&self, date: u128, line: &str) -> Option<LogMessage> {
+        let rest = &line[date_length(line)?..];
+        let separator = rest.find("- ")?;
+        let level = match rest[..separator].trim() {
+            "" => None,
+            level => Some(normalize_level(level)?),
+        };
+        let rest = &rest[(separator + 2)..];
+        let colon = rest.find(": ")?;
+        let section = &rest[..colon];
+        if !is_key(section) {
+            return None;
+        }
+        let mut message = &rest[(colon + 2)..];
+        let level = match level {
+            Some(level) => level,
+            None => match message.find(": ").and_then(|pos| Some((normalize_level(&message[..pos])?, pos))) {
+                Some((level, pos)) => {
+                    message = &message[(pos + 2)..];
+                    level
+                },
+                None => "info",
+            },
+        };
+        let (message, fields) = ocaml_fields(message);
+        Some(LogMessage::new(date, level, section, message, fields))
+    }
+}
+
+/// JSON lines, each line is an object, the well known keys are
+/// level (`level`, `lvl`, `severity`), message (`msg`, `message`),
+/// section (`module`, `section`, `target`) and date (`time`, `timestamp`, `ts`),
+/// the rest of the keys are fields
+pub struct JsonParser;
+
+impl JsonParser {
+    const LEVEL_KEYS: [&'static str; 3] = ["level", "lvl", "severity"];
+    const MESSAGE_KEYS: [&'static str; 2] = ["msg", "message"];
+    const SECTION_KEYS: [&'static str; 3] = ["module", "section", "target"];
+    const DATE_KEYS: [&'static str; 3] = ["time", "timestamp", "ts"];
+
+    fn take(object: &mut serde_json::Map<String, serde_json::Value>, keys: &[&str]) -> Option<serde_json::Value> {
+        keys.iter().find_map(|key| object.remove(*key))
+    }
+
+    fn stringify(value: serde_json::Value) -> String {
+        match value {
+            serde_json::Value::String(s) => s,
+            // ocaml section is a list of strings
+            serde_json::Value::Array(a) => a.into_iter().map(Self::stringify).collect::<Vec<_>>().join("."),
+            value => value.to_string(),
+        }
+    }
+
+    fn date(value: serde_json::Value) -> Option<u128> {
+        match value {
+            serde_json::Value::String(s) => DateTime::parse_from_rfc3339(&s).ok().map(|d| d.timestamp_nanos() as u128),
+            // seconds, milliseconds or nanoseconds
+            serde_json::Value::Number(n) => match n.as_u64() {
+                Some(n) if n < 100_000_000_000 => Some(n as u128 * 1_000_000_000),
+                Some(n) if n < 100_000_000_000_000 => Some(n as u128 * 1_000_000),
+                Some(n) => Some(n as u128),
+                None => n.as_f64().map(|n| (n * 1e9) as u128),
+            },
+            _ => None,
+        }
+    }
+}
+
+impl LogParser for JsonParser {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn parse(&self, date: u128, line: &str) -> Option<LogMessage> {
+        if !line.starts_with('{') {
+            return None;
+        }
+        let mut object = match serde_json::from_str(line).ok()? {
+            serde_json::Value::Object(object) => object,
+            _ => return None,
+        };
+        let level = Self::take(&mut object, &Self::LEVEL_KEYS)
+            .map(Self::stringify)
+            .and_then(|level| normalize_level(&level))
+            .unwrap_or("info");
+        let message = Self::take(&mut object, &Self::MESSAGE_KEYS).map(Self::stringify).unwrap_or_default();
+        let section = Self::take(&mut object, &Self::SECTION_KEYS).map(Self::stringify).unwrap_or_default();
+        let date = Self::take(&mut object, &Self::DATE_KEYS).and_then(Self::date).unwrap_or(date);
+        let fields = object.into_iter()
+            .map(|(key, value)| (key, Self::stringify(value)))
+            .collect();
+        Some(LogMessage::new(date, level, &section, &message, fields))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEZEDGE: &str = include_str!("../../tests/logs/tezedge.log");
+    const OCAML: &str = include_str!("../../tests/logs/ocaml.log");
+    const JSON: &str = include_str!("../../tests/logs/json.log");
+
+    fn parse_all(parser: &dyn LogParser, fixture: &str) -> Vec<LogMessage> {
+        fixture.lines()
+            .map(|line| parser.parse(0, line).unwrap_or_else(|| panic!("{} failed to parse: {}", parser.name(), line)))
+            .collect()
+    }
+
+    fn fields(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn tezedge() {
+        let logs = parse_all(&TezedgeParser, TEZEDGE);
+        assert_eq!(logs[0].level, "info");
+        assert_eq!(logs[0].message, "Blacklisting IP because peer failed at bootstrap process");
+        assert_eq!(logs[0].fields, fields(&[("ip", "104.248.136.94")]));
+        assert_eq!(logs[1].level, "warning");
+        assert_eq!(logs[1].section, "p2p");
+        assert_eq!(logs[1].message, "Peer is not responding, disconnecting");
+        assert_eq!(logs[1].fields, fields(&[("peer_id", "idrdoT9g6YwELhUQyshCcHwAzBS9zA"), ("timeout", "8s")]));
+        assert_eq!(logs[2].level, "error");
+        assert_eq!(logs[2].message, "Failed to apply block: storage error");
+        assert!(logs[2].fields.is_empty());
+        assert_eq!(logs[0].addresses, vec!["104.248.136.94"]);
+        assert_eq!(logs[1].peer_ids, vec!["idrdoT9g6YwELhUQyshCcHwAzBS9zA"]);
+        assert!(logs[2].peer_ids.is_empty() && logs[2].addresses.is_empty());
+        assert_eq!(logs[3].level, "debug");
+        assert_eq!(logs[4].level, "fatal");
+
+        assert!(OCAML.lines().all(|line| TezedgeParser.parse(0, line).is_none()));
+    }
+
+    #[test]
+    fn ocaml() {
+        let logs = parse_all(&OcamlParser, OCAML);
+        assert_eq!(logs[0].level, "info");
+        assert_eq!(logs[0].section, "node.main");
+        assert_eq!(logs[0].message, "starting the Tezos node");
+        assert_eq!(logs[0].fields, fields(&[("chain", "TEZOS_MAINNET")]));
+        assert_eq!(logs[1].section, "p2p.maintenance");
+        assert_eq!(logs[1].message, "Too few connections (5)");
+        assert!(logs[1].fields.is_empty());
+        assert_eq!(logs[2].level, "warning");
+        assert_eq!(logs[2].section, "validator.peer");
+        assert_eq!(logs[2].message, "unknown ancestor for block");
+        assert_eq!(logs[3].level, "error");
+        assert_eq!(logs[3].section, "node.main");
+
+        assert!(TEZEDGE.lines().all(|line| OcamlParser.parse(0, line).is_none()));
+    }
+
+    #[test]
+    fn json() {
+        let logs = parse_all(&JsonParser, JSON);
+        assert_eq!(logs[0].level, "info");
+        assert_eq!(logs[0].section, "shell");
+        assert_eq!(logs[0].message, "Block applied");
+        assert_eq!(logs[0].date, 1592987557026683000);
+        assert_eq!(logs[0].fields, fields(&[("level_number", "1132"), ("block", "BLockGenesisGenesisGenesisGenesisGenesisf79b5d1CoW2")]));
+        assert_eq!(logs[1].level, "warning");
+        assert_eq!(logs[1].section, "node.main");
+        assert_eq!(logs[1].date, 1592987557000000000);
+        assert_eq!(logs[2].level, "error");
+        assert_eq!(logs[2].date, 0);
+    }
+
+    #[test]
+    fn dates() {
+        // 2020-06-25T00:00:00Z
+        let captured = 1593043200_000_000_000;
+        assert_eq!(line_date(TEZEDGE.lines().next().unwrap(), captured), Some(1592987557026000000));
+        assert_eq!(line_date(OCAML.lines().next().unwrap(), captured), Some(1592987557026000000));
+        // the line of the last year, 2021-01-01T00:00:05Z
+        assert_eq!(line_date("Dec 31 23:59:59 message", 1609459205_000_000_000), Some(1609459199_000_000_000));
+        assert_eq!(line_date("Jun  4 08:32:37 message", captured), Some(1591259557_000_000_000));
+        assert_eq!(line_date("something unusual", captured), None);
+        assert_eq!(line_date(TEZEDGE.lines().next().unwrap(), 0), None);
+    }
+
+    #[test]
+    fn registry() {
+        let registry = LogParserRegistry::default();
+        assert_eq!(registry.parse(0, TEZEDGE.lines().next().unwrap()).fields.len(), 1);
+        assert_eq!(registry.parse(0, OCAML.lines().next().unwrap()).section, "node.main");
+        assert_eq!(registry.parse(0, JSON.lines().next().unwrap()).section, "shell");
+
+        let linked = registry.parse(0, "connection to [::1]:9732 (peer idtJunqYgD1M6r6o2qvGpiD5xKZWRu) closed, from 51.15.220.7:9732.");
+        assert_eq!(linked.peer_ids, vec!["idtJunqYgD1M6r6o2qvGpiD5xKZWRu"]);
+        assert_eq!(linked.addresses, vec!["51.15.220.7", "51.15.220.7:9732", "::1", "[::1]:9732"]);
+
+        let unknown = registry.parse(7, "something unusual\n");
+        assert_eq!(unknown.level, "info");
+        assert_eq!(unknown.message, "something unusual");
+        assert_eq!(unknown.date, 7);
+    }
+}
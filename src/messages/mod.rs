@@ -3,7 +3,9 @@
 
 pub mod p2p_message;
 pub mod log_message;
+pub mod log_parser;
 pub mod rpc_message;
+pub mod alert_message;
 
 pub mod prelude {
     pub use super::p2p_message::{P2pMessage, SourceType, TezosPeerMessage};
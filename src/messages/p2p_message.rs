@@ -50,6 +50,8 @@ pub struct P2pMessage {
     pub message: Vec<TezosPeerMessage>,
     #[serde(skip_serializing_if = "Option::is_none", default)]
     pub ordinal_id: Option<u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub violations: Vec<Violation>,
 }
 
 impl Decoder for P2pMessage {
@@ -98,6 +100,7 @@ impl P2pMessage {
             error,
             message,
             ordinal_id: None,
+            violations: Vec::new(),
         }
     }
 
@@ -117,6 +120,35 @@ impl P2pMessage {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+/// Kind of the violation of the Tezos P2P protocol
+pub enum ViolationKind {
+    HandshakeOrder,
+    MessageAfterNack,
+    MessageAfterDisconnect,
+    ChunkTooBig,
+    ChunkTooSmall,
+    UnrequestedResponse,
+    WrongNetwork,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Violation of the Tezos P2P protocol found in the message
+pub struct Violation {
+    pub kind: ViolationKind,
+    pub description: String,
+}
+
+impl Violation {
+    pub fn new<D: ToString>(kind: ViolationKind, description: D) -> Self {
+        Violation {
+            kind,
+            description: description.to_string(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(untagged)]
 /// Detailed representation of peer messages mapped from
@@ -0,0 +1,58 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use storage::{StorageError, persistent::{KeyValueStoreWithSchema, KeyValueSchema}, IteratorMode, Direction};
+use rocksdb::DB;
+use std::sync::{
+    Arc, atomic::{AtomicU64, Ordering},
+};
+use crate::messages::alert_message::Alert;
+
+/// Defined Key Value store for Alert storage
+pub type AlertStorageKV = dyn KeyValueStoreWithSchema<AlertStore> + Sync + Send;
+
+#[derive(Clone)]
+/// Store of the raised alerts
+pub struct AlertStore {
+    kv: Arc<AlertStorageKV>,
+    seq: Arc<AtomicU64>,
+}
+
+impl AlertStore {
+    /// Create new store on top of the RocksDB, the indexes continue after the stored alerts
+    pub fn new(kv: Arc<DB>) -> Result<Self, StorageError> {
+        let kv: Arc<AlertStorageKV> = kv;
+        let seq = super::next_index(kv.as_ref())?;
+        Ok(Self {
+            kv,
+            seq: Arc::new(AtomicU64::new(seq)),
+        })
+    }
+
+    /// Store alert at the end of the store. Return ID of newly inserted value
+    pub fn store_alert(&self, alert: &mut Alert) -> Result<u64, StorageError> {
+        let index = self.seq.fetch_add(1, Ordering::SeqCst);
+        alert.id = Some(index);
+        self.kv.put(&index, &alert)?;
+        Ok(index)
+    }
+
+    /// Create cursor into the database, values are sorted by the index in descending order.
+    /// * Arguments:
+    /// - cursor_index: Index of start of the sequence (if no value provided, start at the end)
+    /// - limit: Limit result to maximum of specified value
+    /// - rule: Show only alerts raised by the rule
+    pub fn get_cursor(&self, cursor_index: Option<u64>, limit: usize, rule: Option<String>) -> Result<Vec<Alert>, StorageError> {
+        let iter = self.kv.iterator(IteratorMode::From(&cursor_index.unwrap_or(std::u64::MAX), Direction::Reverse))?
+            .filter_map(|(_, v)| v.ok())
+            .filter(|alert| rule.as_ref().map(|rule| &alert.rule == rule).unwrap_or(true));
+        Ok(iter.take(limit).collect())
+    }
+}
+
+impl KeyValueSchema for AlertStore {
+    type Key = u64;
+    type Value = Alert;
+
+    fn name() -> &'static str { "alert_storage" }
+}
@@ -0,0 +1,230 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use storage::{StorageError, persistent::{KeyValueStoreWithSchema, KeyValueSchema}, IteratorMode, Direction};
+use rocksdb::DB;
+use std::{
+    sync::{
+        Arc, atomic::{AtomicU64, Ordering},
+    }, net::SocketAddr,
+};
+use crate::system::{P2pConnectionReport, P2pParserError};
+
+/// Defined Key Value store for Connection report storage
+pub type ConnectionReportStorageKV = dyn KeyValueStoreWithSchema<ConnectionStore> + Sync + Send;
+
+#[derive(Debug, Default, Clone)]
+/// Allowed filters for connection report store
+pub struct ConnectionFilters {
+    pub peer_id: Option<String>,
+    pub remote_addr: Option<SocketAddr>,
+    pub error: Option<P2pParserError>,
+    // the connection was alive at some moment between `from` and `to`, unix nanos
+    pub from: Option<u128>,
+    pub to: Option<u128>,
+}
+
+impl ConnectionFilters {
+    /// Check, if there are no set filters
+    pub fn empty(&self) -> bool {
+        self.peer_id.is_none() && self.remote_addr.is_none() && self.error.is_none()
+            && self.from.is_none() && self.to.is_none()
+    }
+
+    /// Check, if the report satisfies all set filters
+    pub fn matches(&self, report: &P2pConnectionReport) -> bool {
+        if let Some(ref peer_id) = self.peer_id {
+            if report.peer_id.as_ref() != Some(peer_id) {
+                return false;
+            }
+        }
+        if let Some(remote_addr) = self.remote_addr {
+            if report.remote_address != remote_addr.to_string() {
+                return false;
+            }
+        }
+        if let Some(error) = self.error {
+            if report.error_report.as_ref().map(|e| e.error) != Some(error) {
+                return false;
+            }
+        }
+        if let Some(from) = self.from {
+            if report.closed_timestamp.map(|closed| closed < from).unwrap_or(false) {
+                return false;
+            }
+        }
+        if let Some(to) = self.to {
+            if report.opened_timestamp > to {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[derive(Clone)]
+/// Store of reports of closed connections
+pub struct ConnectionStore {
+    kv: Arc<ConnectionReportStorageKV>,
+    seq: Arc<AtomicU64>,
+}
+
+impl ConnectionStore {
+    /// Create new store on top of the RocksDB, the indexes continue after the stored reports
+    pub fn new(kv: Arc<DB>) -> Result<Self, StorageError> {
+        let kv: Arc<ConnectionReportStorageKV> = kv;
+        let seq = super::next_index(kv.as_ref())?;
+        Ok(Self {
+            kv,
+            seq: Arc::new(AtomicU64::new(seq)),
+        })
+    }
+
+    /// Reserve the ID for the connection when it is opened, so the working connection has it too
+    pub fn reserve_index(&self) -> u64 {
+        self.seq.fetch_add(1, Ordering::SeqCst)
+    }
+
+    /// Store report under its reserved ID, or at the end of the store. Return ID of the report
+    pub fn store_report(&self, report: &mut P2pConnectionReport) -> Result<u64, StorageError> {
+        let index = match report.id {
+            Some(index) => index,
+            None => self.reserve_index(),
+        };
+        report.id = Some(index);
+        self.kv.put(&index, &report)?;
+        Ok(index)
+    }
+
+    /// Get the report by its ID
+    pub fn get_report(&self, index: u64) -> Result<Option<P2pConnectionReport>, StorageError> {
+        self.kv.get(&index)
+    }
+
+    /// Reports satisfying the predicate, sorted by the index in descending order
+    pub fn find<F>(&self, limit: usize, predicate: F) -> Result<Vec<P2pConnectionReport>, StorageError>
+    where
+        F: Fn(&P2pConnectionReport) -> bool,
+    {
+        let iter = self.kv.iterator(IteratorMode::From(&std::u64::MAX, Direction::Reverse))?
+            .filter_map(|(_, v)| v.ok())
+            .filter(|report| predicate(report));
+        Ok(iter.take(limit).collect())
+    }
+
+    /// Create cursor into the database, allowing iteration over reports matching given filters.
+    /// Values are sorted by the index in descending order.
+    /// * Arguments:
+    /// - cursor_index: Index of start of the sequence (if no value provided, start at the end)
+    /// - limit: Limit result to maximum of specified value
+    /// - filters: Specified filters for values
+    pub fn get_cursor(&self, cursor_index: Option<u64>, limit: usize, filters: ConnectionFilters) -> Result<Vec<P2pConnectionReport>, StorageError> {
+        let iter = self.kv.iterator(IteratorMode::From(&cursor_index.unwrap_or(std::u64::MAX), Direction::Reverse))?
+            .filter_map(|(_, v)| v.ok())
+            .filter(|report| filters.matches(report));
+        Ok(iter.take(limit).collect())
+    }
+}
+
+impl KeyValueSchema for ConnectionStore {
+    type Key = u64;
+    type Value = P2pConnectionReport;
+
+    fn name() -> &'static str { "p2p_connection_storage" }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use super::*;
+    use crate::{
+        messages::p2p_message::SourceType,
+        system::{P2pMisbehaviour, P2pParserErrorReport},
+        storage::{open_temp_db, remove_temp_db},
+    };
+
+    fn connection(peer_id: Option<&str>, opened: u128, closed: Option<u128>) -> P2pConnectionReport {
+        P2pConnectionReport {
+            id: None,
+            opened_timestamp: opened,
+            closed_timestamp: closed,
+            remote_address: "51.15.220.7:9732".to_string(),
+            source_type: SourceType::Remote,
+            peer_id: peer_id.map(str::to_string),
+            sent_bytes: 0,
+            received_bytes: 0,
+            incomplete_dropped_messages: 0,
+            total_chunks: 0,
+            decrypted_chunks: 0,
+            error_report: None,
+            violations: BTreeMap::new(),
+            misbehaviour: P2pMisbehaviour::default(),
+            handshake: Default::default(),
+            gap: Default::default(),
+            metadata: None,
+        }
+    }
+
+    #[test]
+    fn filters() {
+        let mut report = connection(Some("idtJunqYgD1M6r6o2qvGpiD5xKZWRu"), 10, Some(20));
+        report.error_report = Some(P2pParserErrorReport { position: 0, error: P2pParserError::WrongProofOfWork });
+        assert!(ConnectionFilters::default().empty());
+        assert!(ConnectionFilters::default().matches(&report));
+
+        let filters = ConnectionFilters { peer_id: Some("idtJunqYgD1M6r6o2qvGpiD5xKZWRu".to_string()), ..ConnectionFilters::default() };
+        assert!(filters.matches(&report));
+        let filters = ConnectionFilters { peer_id: Some("other".to_string()), ..ConnectionFilters::default() };
+        assert!(!filters.matches(&report));
+
+        let filters = ConnectionFilters { remote_addr: "51.15.220.7:9732".parse().ok(), ..ConnectionFilters::default() };
+        assert!(filters.matches(&report));
+        let filters = ConnectionFilters { remote_addr: "51.15.220.7:9733".parse().ok(), ..ConnectionFilters::default() };
+        assert!(!filters.matches(&report));
+
+        let filters = ConnectionFilters { error: Some(P2pParserError::WrongProofOfWork), ..ConnectionFilters::default() };
+        assert!(filters.matches(&report));
+        let filters = ConnectionFilters { error: Some(P2pParserError::NoDecipher), ..ConnectionFilters::default() };
+        assert!(!filters.matches(&report));
+
+        // the connection was alive between 10 and 20
+        let window = |from, to| ConnectionFilters { from, to, ..ConnectionFilters::default() };
+        assert!(window(Some(15), Some(30)).matches(&report));
+        assert!(window(Some(20), None).matches(&report));
+        assert!(!window(Some(21), None).matches(&report));
+        assert!(!window(None, Some(9)).matches(&report));
+        // the working connection is alive until now
+        assert!(window(Some(100), None).matches(&connection(None, 10, None)));
+    }
+
+    #[test]
+    fn pagination() {
+        let db = open_temp_db("connections");
+        let store = ConnectionStore::new(db.clone()).unwrap();
+        for i in 0..5 {
+            let peer_id = if i % 2 == 0 { Some("even") } else { None };
+            assert_eq!(store.store_report(&mut connection(peer_id, i, Some(i + 1))).unwrap(), i as u64);
+        }
+
+        let ids = |reports: Vec<P2pConnectionReport>| reports.into_iter().map(|r| r.id.unwrap()).collect::<Vec<_>>();
+        assert_eq!(ids(store.get_cursor(None, 2, ConnectionFilters::default()).unwrap()), vec![4, 3]);
+        assert_eq!(ids(store.get_cursor(Some(2), 2, ConnectionFilters::default()).unwrap()), vec![2, 1]);
+        assert_eq!(ids(store.get_cursor(Some(0), 2, ConnectionFilters::default()).unwrap()), vec![0]);
+        let filters = ConnectionFilters { peer_id: Some("even".to_string()), ..ConnectionFilters::default() };
+        assert_eq!(ids(store.get_cursor(Some(3), 10, filters).unwrap()), vec![2, 0]);
+
+        // the sequence continues after the restart, the stored reports are not overwritten
+        let store = ConnectionStore::new(db.clone()).unwrap();
+        assert_eq!(store.store_report(&mut connection(None, 5, None)).unwrap(), 5);
+        assert_eq!(store.get_report(0).unwrap().unwrap().opened_timestamp, 0);
+
+        // the ID reserved when the connection opened is kept, even if other connections closed before it
+        let reserved = store.reserve_index();
+        assert_eq!(store.store_report(&mut connection(None, 7, Some(9))).unwrap(), 7);
+        let mut report = connection(None, 6, Some(10));
+        report.id = Some(reserved);
+        assert_eq!(store.store_report(&mut report).unwrap(), 6);
+        assert_eq!(ids(store.get_cursor(None, 3, ConnectionFilters::default()).unwrap()), vec![7, 6, 5]);
+        remove_temp_db(db);
+    }
+}
@@ -6,9 +6,10 @@ use std::sync::Arc;
 use rocksdb::{DB};
 use tracing::{info, error};
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::collections::BTreeSet;
 use crate::messages::log_message::LogMessage;
 use storage::{StorageError, IteratorMode, Direction};
-use crate::storage::log_storage::secondary_indexes::{LevelIndex, LogLevel, TimestampIndex};
+use crate::storage::log_storage::secondary_indexes::{LevelIndex, LogLevel, TimestampIndex, DateIndex, TokenIndex, SectionIndex, ReferenceIndex, tokenize, hash_token};
 use crate::storage::secondary_index::SecondaryIndex;
 use crate::storage::sorted_intersect::sorted_intersect;
 use itertools::Itertools;
@@ -20,13 +21,22 @@ pub type LogStorageKV = dyn KeyValueStoreWithSchema<LogStore> + Sync + Send;
 /// Allowed filters for log message store
 pub struct LogFilters {
     pub level: Vec<LogLevel>,
-    pub date: Option<u128>,
+    // time window, unix nanos
+    pub from: Option<u128>,
+    pub to: Option<u128>,
+    // every token of the query must be in the message or in the values of the fields
+    pub query: Option<String>,
+    pub section: Option<String>,
+    // mentioned in the log
+    pub peer_id: Option<String>,
+    pub remote_addr: Option<String>,
 }
 
 impl LogFilters {
-    /// Check, if there are no set filters
+    /// Check, if there are no set filters, the time window is not a filter, it limits the cursor
     pub fn empty(&self) -> bool {
-        self.level.is_empty() && self.date.is_none()
+        self.level.is_empty() && self.query.is_none() && self.section.is_none()
+            && self.peer_id.is_none() && self.remote_addr.is_none()
     }
 }
 
@@ -36,6 +46,10 @@ pub struct LogStore {
     kv: Arc<LogStorageKV>,
     level_index: LevelIndex,
     timestamp_index: TimestampIndex,
+    date_index: DateIndex,
+    token_index: TokenIndex,
+    section_index: SectionIndex,
+    reference_index: ReferenceIndex,
     count: Arc<AtomicU64>,
     seq: Arc<AtomicU64>,
 }
@@ -47,7 +61,11 @@ impl LogStore {
         Self {
             kv: kv.clone(),
             level_index: LevelIndex::new(kv.clone()),
-            timestamp_index: TimestampIndex::new(kv),
+            timestamp_index: TimestampIndex::new(kv.clone()),
+            date_index: DateIndex::new(kv.clone()),
+            token_index: TokenIndex::new(kv.clone()),
+            section_index: SectionIndex::new(kv.clone()),
+            reference_index: ReferenceIndex::new(kv),
             count: Arc::new(AtomicU64::new(0)),
             seq: Arc::new(AtomicU64::new(0)),
         }
@@ -72,13 +90,21 @@ impl LogStore {
     /// Create all indexes for given value
     pub fn make_indexes(&self, primary_index: u64, value: &LogMessage) -> Result<(), StorageError> {
         self.level_index.store_index(&primary_index, value)?;
-        self.timestamp_index.store_index(&primary_index, value)
+        self.timestamp_index.store_index(&primary_index, value)?;
+        self.date_index.store_index(&primary_index, value)?;
+        self.token_index.store_index(&primary_index, value)?;
+        self.reference_index.store_index(&primary_index, value)?;
+        self.section_index.store_index(&primary_index, value)
     }
 
     /// Delete all indexes for given value
     pub fn delete_indexes(&self, primary_index: u64, value: &LogMessage) -> Result<(), StorageError> {
         self.level_index.delete_index(&primary_index, value)?;
-        self.timestamp_index.delete_index(&primary_index, value)
+        self.timestamp_index.delete_index(&primary_index, value)?;
+        self.date_index.delete_index(&primary_index, value)?;
+        self.token_index.delete_index(&primary_index, value)?;
+        self.reference_index.delete_index(&primary_index, value)?;
+        self.section_index.delete_index(&primary_index, value)
     }
 
     /// Put messages onto specific index
@@ -104,29 +130,82 @@ impl LogStore {
         Ok(index)
     }
 
+    /// Get the message by its ID
+    pub fn get_message(&self, index: u64) -> Result<Option<LogMessage>, StorageError> {
+        self.kv.get(&index)
+    }
+
     /// Create cursor into the database, allowing iteration over values matching given filters.
     /// Values are sorted by the index in descending order.
     /// * Arguments:
     /// - cursor_index: Index of start of the sequence (if no value provided, start at the end)
     /// - limit: Limit result to maximum of specified value
     /// - filters: Specified filters for values
-    pub fn get_cursor(&self, cursor_index: Option<u64>, limit: usize, filters: LogFilters) -> Result<Vec<LogMessage>, StorageError> {
+    pub fn get_cursor(&self, cursor_index: Option<u64>, limit: usize, mut filters: LogFilters) -> Result<Vec<LogMessage>, StorageError> {
         let mut ret = Vec::with_capacity(limit);
+        // the query without tokens matches everything
+        let phrase = filters.query.as_ref()
+            .map(|query| tokenize(query).collect::<Vec<_>>())
+            .filter(|phrase| !phrase.is_empty());
+        if phrase.is_none() {
+            filters.query = None;
+        }
         if filters.empty() {
-            ret.extend(self.cursor_iterator(cursor_index)?.map(|(_, v)| v).take(limit));
-        } else {
-            let mut iters: Vec<Box<dyn Iterator<Item=u64>>> = Default::default();
-            if !filters.level.is_empty() {
-                iters.push(self.level_iterator(cursor_index, filters.level)?);
+            match (filters.from, filters.to) {
+                (None, None) => ret.extend(self.cursor_iterator(cursor_index)?.map(|(_, v)| v).take(limit)),
+                (from, to) => {
+                    let indexes = self.window_iterator(cursor_index, from, to)?.take(limit).collect::<Vec<_>>();
+                    ret.extend(self.load_indexes(indexes.into_iter()))
+                },
             }
-            if let Some(timestamp) = filters.date {
-                iters.push(self.timestamp_iterator(cursor_index, timestamp)?);
+            return Ok(ret);
+        }
+
+        // the index only tells every token is somewhere in the log, or some token of the same hash,
+        // so the loaded logs are checked for the phrase, and more candidates are loaded if some do not match
+        let mut cursor_index = cursor_index;
+        while ret.len() < limit {
+            let candidates = sorted_intersect(self.filter_iterators(cursor_index, &filters)?, limit);
+            let exhausted = candidates.len() < limit;
+            let next = candidates.last().and_then(|&index| index.checked_sub(1));
+            let room = limit - ret.len();
+            ret.extend(self.load_indexes(candidates.into_iter())
+                .filter(|log| phrase.as_ref().map(|phrase| contains_phrase(log, phrase)).unwrap_or(true))
+                .take(room));
+            match next {
+                Some(next) if !exhausted => cursor_index = Some(next),
+                _ => break,
             }
-            ret.extend(self.load_indexes(sorted_intersect(iters, limit).into_iter()));
         }
         Ok(ret)
     }
 
+    /// Iterators of the indexes matching each of the set filters, starting at the cursor
+    fn filter_iterators<'a>(&'a self, cursor_index: Option<u64>, filters: &LogFilters) -> Result<Vec<Box<dyn 'a + Iterator<Item=u64>>>, StorageError> {
+        let mut iters: Vec<Box<dyn 'a + Iterator<Item=u64>>> = Default::default();
+        if filters.from.is_some() || filters.to.is_some() {
+            iters.push(self.window_iterator(cursor_index, filters.from, filters.to)?);
+        }
+        if !filters.level.is_empty() {
+            iters.push(self.level_iterator(cursor_index, filters.level.clone())?);
+        }
+        if let Some(ref query) = filters.query {
+            for token in tokenize(query).collect::<BTreeSet<_>>() {
+                iters.push(self.token_iterator(cursor_index, &token)?);
+            }
+        }
+        if let Some(ref section) = filters.section {
+            iters.push(self.section_iterator(cursor_index, section)?);
+        }
+        if let Some(ref peer_id) = filters.peer_id {
+            iters.push(self.reference_iterator(cursor_index, peer_id)?);
+        }
+        if let Some(ref remote_addr) = filters.remote_addr {
+            iters.push(self.reference_iterator(cursor_index, remote_addr)?);
+        }
+        Ok(iters)
+    }
+
     /// Create iterator ending on given index. If no value is provided
     /// start at the end
     fn cursor_iterator<'a>(&'a self, cursor_index: Option<u64>) -> Result<Box<dyn 'a + Iterator<Item=(u64, LogMessage)>>, StorageError> {
@@ -136,6 +215,26 @@ impl LogStore {
             })))
     }
 
+    /// Create iterator with at maximum given index, dated between `from` and `to`, both inclusive.
+    /// The dates come from the log lines, they do not grow with the index, so the date index is walked
+    /// lazily from the cursor down, the caller bounds it by its limit. The timestamp index tells
+    /// beforehand if the window is empty, so the empty window does not walk the whole store
+    pub fn window_iterator<'a>(&'a self, cursor_index: Option<u64>, from: Option<u128>, to: Option<u128>) -> Result<Box<dyn 'a + Iterator<Item=u64>>, StorageError> {
+        let (from, to) = (from.unwrap_or(0), to.unwrap_or(std::u128::MAX));
+        let empty = self.timestamp_index.get_iterator(&0, from, Direction::Forward)?
+            .filter_map(|(k, _)| k.ok())
+            .next()
+            .map(|key| key.timestamp > to)
+            .unwrap_or(true);
+        if empty {
+            return Ok(Box::new(std::iter::empty()));
+        }
+        Ok(Box::new(self.date_index.get_iterator(&cursor_index.unwrap_or(std::u64::MAX), std::u128::MAX, Direction::Reverse)?
+            .filter_map(|(k, _)| k.ok())
+            .filter(move |key| from <= key.timestamp && key.timestamp <= to)
+            .map(|key| key.index)))
+    }
+
     /// Create iterator with at maximum given index, having specified log level
     pub fn level_iterator<'a>(&'a self, cursor_index: Option<u64>, level: Vec<LogLevel>) -> Result<Box<dyn 'a + Iterator<Item=u64>>, StorageError> {
         let mut iterators = Vec::with_capacity(level.len());
@@ -148,15 +247,44 @@ impl LogStore {
         Ok(Box::new(iterators.into_iter().kmerge_by(|x, y| x > y)))
     }
 
-    /// Create iterator with at maximum given index, having specified log level
-    pub fn timestamp_iterator<'a>(&'a self, cursor_index: Option<u64>, timestamp: u128) -> Result<Box<dyn 'a + Iterator<Item=u64>>, StorageError> {
-        println!("Getting the timestamp iterator");
-        Ok(Box::new(self.timestamp_index.get_iterator(&cursor_index.unwrap_or(std::u64::MAX), timestamp, Direction::Reverse)?
+    /// Create iterator with at maximum given index, having specified token
+    pub fn token_iterator<'a>(&'a self, cursor_index: Option<u64>, token: &str) -> Result<Box<dyn 'a + Iterator<Item=u64>>, StorageError> {
+        Ok(Box::new(self.token_index.get_concrete_prefix_iterator(&cursor_index.unwrap_or(std::u64::MAX), hash_token(token))?
             .filter_map(|(_, value)| {
                 value.ok()
             })))
     }
 
+    /// Create iterator with at maximum given index, having specified section
+    pub fn section_iterator<'a>(&'a self, cursor_index: Option<u64>, section: &str) -> Result<Box<dyn 'a + Iterator<Item=u64>>, StorageError> {
+        Ok(Box::new(self.section_index.get_concrete_prefix_iterator(&cursor_index.unwrap_or(std::u64::MAX), hash_token(section))?
+            .filter_map(|(_, value)| {
+                value.ok()
+            })))
+    }
+
+    /// Create iterator with at maximum given index, mentioning specified peer id or address
+    pub fn reference_iterator<'a>(&'a self, cursor_index: Option<u64>, reference: &str) -> Result<Box<dyn 'a + Iterator<Item=u64>>, StorageError> {
+        Ok(Box::new(self.reference_index.get_concrete_prefix_iterator(&cursor_index.unwrap_or(std::u64::MAX), hash_token(reference))?
+            .filter_map(|(_, value)| {
+                value.ok()
+            })))
+    }
+
+    /// Logs mentioning any of the references (peer ids or addresses), sorted by the index in descending order
+    pub fn get_related(&self, references: &[String], limit: usize) -> Result<Vec<LogMessage>, StorageError> {
+        let mut iters = Vec::with_capacity(references.len());
+        for reference in references {
+            iters.push(self.reference_iterator(None, reference)?);
+        }
+        let indexes = iters.into_iter()
+            .kmerge_by(|x, y| x > y)
+            .dedup()
+            .take(limit)
+            .collect::<Vec<_>>();
+        Ok(self.load_indexes(indexes.into_iter()).collect())
+    }
+
     /// Load all values for indexes given.
     fn load_indexes<Iter: 'static + Iterator<Item=u64>>(&self, indexes: Iter) -> impl Iterator<Item=LogMessage> + 'static {
         let kv = self.kv.clone();
@@ -178,6 +306,13 @@ impl LogStore {
     }
 }
 
+/// Whether the tokens of the query follow each other in the message or in the value of some field
+fn contains_phrase(log: &LogMessage, phrase: &[String]) -> bool {
+    Some(&log.message).into_iter()
+        .chain(log.fields.values())
+        .any(|text| tokenize(text).collect::<Vec<_>>().windows(phrase.len()).any(|window| window == phrase))
+}
+
 impl KeyValueSchema for LogStore {
     type Key = u64;
     type Value = LogMessage;
@@ -190,7 +325,9 @@ pub(crate) mod secondary_indexes {
     use std::{
         sync::Arc,
         str::FromStr,
+        collections::BTreeSet,
     };
+    use storage::StorageError;
     use tracing::warn;
     use rocksdb::{DB, ColumnFamilyDescriptor, Options, SliceTransform, Cache};
     use crate::storage::LogStore;
@@ -489,4 +626,472 @@ pub(crate) mod secondary_indexes {
             }
         }
     }
-}
\ No newline at end of file
+
+    // Date by index, the dates of the logs do not grow with the index,
+    // this index gives them in the order of the index, so the time window can be walked from the cursor
+    pub type DateIndexKV = dyn KeyValueStoreWithSchema<DateIndex> + Sync + Send;
+
+    #[derive(Clone)]
+    pub struct DateIndex {
+        kv: Arc<DateIndexKV>,
+    }
+
+    impl AsRef<(dyn KeyValueStoreWithSchema<DateIndex> + 'static)> for DateIndex {
+        fn as_ref(&self) -> &(dyn KeyValueStoreWithSchema<DateIndex> + 'static) {
+            self.kv.as_ref()
+        }
+    }
+
+    impl KeyValueSchema for DateIndex {
+        type Key = DateKey;
+        type Value = <LogStore as KeyValueSchema>::Key;
+
+        fn name() -> &'static str {
+            "log_date_index"
+        }
+    }
+
+    impl DateIndex {
+        pub fn new(kv: Arc<DB>) -> Self {
+            Self { kv }
+        }
+    }
+
+    impl SecondaryIndex<LogStore> for DateIndex {
+        type FieldType = u128;
+
+        fn accessor(value: &<LogStore as KeyValueSchema>::Value) -> Option<Self::FieldType> {
+            Some(value.date)
+        }
+
+        fn make_index(key: &<LogStore as KeyValueSchema>::Key, value: Self::FieldType) -> DateKey {
+            DateKey::new(key.clone(), value)
+        }
+
+        fn make_prefix_index(value: Self::FieldType) -> DateKey {
+            DateKey::new(0, value)
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct DateKey {
+        pub index: u64,
+        pub timestamp: u128,
+    }
+
+    impl DateKey {
+        pub fn new(index: u64, timestamp: u128) -> Self {
+            Self {
+                index,
+                timestamp,
+            }
+        }
+    }
+
+    /// * bytes layout: `[index(8)][timestamp(16)]`
+    impl Decoder for DateKey {
+        fn decode(bytes: &[u8]) -> Result<Self, SchemaError> {
+            if bytes.len() != 24 {
+                return Err(SchemaError::DecodeError);
+            }
+
+            let mut index = [0u8; 8];
+            index.copy_from_slice(&bytes[0..8]);
+            let mut timestamp = [0u8; 16];
+            timestamp.copy_from_slice(&bytes[8..]);
+
+            Ok(Self {
+                index: u64::from_be_bytes(index),
+                timestamp: u128::from_be_bytes(timestamp),
+            })
+        }
+    }
+
+    /// * bytes layout: `[index(8)][timestamp(16)]`
+    impl Encoder for DateKey {
+        fn encode(&self) -> Result<Vec<u8>, SchemaError> {
+            let mut buf = Vec::with_capacity(24);
+            buf.extend_from_slice(&self.index.to_be_bytes());
+            buf.extend_from_slice(&self.timestamp.to_be_bytes());
+            Ok(buf)
+        }
+    }
+
+    /// Hash of the token or the section, FNV-1a, stable across restarts
+    pub fn hash_token(token: &str) -> u64 {
+        token.bytes().fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+    }
+
+    /// Split the text into lowercase alphanumeric tokens
+    pub fn tokenize(text: &str) -> impl Iterator<Item=String> + '_ {
+        text.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .filter(|token| token.len() >= TokenIndex::MIN_TOKEN_LENGTH)
+            .map(str::to_lowercase)
+    }
+
+    // Token
+
+    pub type TokenIndexKV = dyn KeyValueStoreWithSchema<TokenIndex> + Sync + Send;
+
+    /// Inverted index of the tokens of the message and the values of the fields
+    #[derive(Clone)]
+    pub struct TokenIndex {
+        kv: Arc<TokenIndexKV>,
+    }
+
+    impl TokenIndex {
+        const MIN_TOKEN_LENGTH: usize = 2;
+        // do not let some huge log line flood the index
+        const MAX_TOKENS: usize = 0x100;
+
+        pub fn new(kv: Arc<DB>) -> Self {
+            Self { kv }
+        }
+
+        /// Hashes of the distinct tokens of the log
+        fn tokens(value: &<LogStore as KeyValueSchema>::Value) -> BTreeSet<u64> {
+            tokenize(&value.message)
+                .chain(value.fields.values().flat_map(|value| tokenize(value)))
+                .map(|token| hash_token(&token))
+                .take(Self::MAX_TOKENS)
+                .collect()
+        }
+    }
+
+    impl AsRef<(dyn KeyValueStoreWithSchema<TokenIndex> + 'static)> for TokenIndex {
+        fn as_ref(&self) -> &(dyn KeyValueStoreWithSchema<TokenIndex> + 'static) {
+            self.kv.as_ref()
+        }
+    }
+
+    impl KeyValueSchema for TokenIndex {
+        type Key = HashKey;
+        type Value = <LogStore as KeyValueSchema>::Key;
+
+        fn descriptor(_cache: &Cache) -> ColumnFamilyDescriptor {
+            let mut cf_opts = Options::default();
+            cf_opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(std::mem::size_of::<u64>()));
+            cf_opts.set_memtable_prefix_bloom_ratio(0.2);
+            ColumnFamilyDescriptor::new(Self::name(), cf_opts)
+        }
+
+        fn name() -> &'static str {
+            "log_token_index"
+        }
+    }
+
+    impl SecondaryIndex<LogStore> for TokenIndex {
+        type FieldType = u64;
+
+        // the log has many tokens, they are handled in `store_index` and `delete_index`
+        fn accessor(_value: &<LogStore as KeyValueSchema>::Value) -> Option<Self::FieldType> {
+            None
+        }
+
+        fn make_index(key: &<LogStore as KeyValueSchema>::Key, value: Self::FieldType) -> HashKey {
+            HashKey::new(value, key.clone())
+        }
+
+        fn make_prefix_index(value: Self::FieldType) -> HashKey {
+            HashKey::prefix(value)
+        }
+
+        fn store_index(&self, key: &<LogStore as KeyValueSchema>::Key, value: &<LogStore as KeyValueSchema>::Value) -> Result<(), StorageError> {
+            for token in Self::tokens(value) {
+                self.kv.put(&Self::make_index(key, token), key)?;
+            }
+            Ok(())
+        }
+
+        fn delete_index(&self, key: &<LogStore as KeyValueSchema>::Key, value: &<LogStore as KeyValueSchema>::Value) -> Result<(), StorageError> {
+            for token in Self::tokens(value) {
+                self.kv.delete(&Self::make_index(key, token))?;
+            }
+            Ok(())
+        }
+    }
+
+    // Reference
+
+    pub type ReferenceIndexKV = dyn KeyValueStoreWithSchema<ReferenceIndex> + Sync + Send;
+
+    /// Index of the peer ids and the addresses mentioned in the log
+    #[derive(Clone)]
+    pub struct ReferenceIndex {
+        kv: Arc<ReferenceIndexKV>,
+    }
+
+    impl ReferenceIndex {
+        pub fn new(kv: Arc<DB>) -> Self {
+            Self { kv }
+        }
+
+        /// Hashes of the peer ids and the addresses of the log
+        fn references(value: &<LogStore as KeyValueSchema>::Value) -> BTreeSet<u64> {
+            value.peer_ids.iter()
+                .chain(value.addresses.iter())
+                .map(|reference| hash_token(reference))
+                .collect()
+        }
+    }
+
+    impl AsRef<(dyn KeyValueStoreWithSchema<ReferenceIndex> + 'static)> for ReferenceIndex {
+        fn as_ref(&self) -> &(dyn KeyValueStoreWithSchema<ReferenceIndex> + 'static) {
+            self.kv.as_ref()
+        }
+    }
+
+    impl KeyValueSchema for ReferenceIndex {
+        type Key = HashKey;
+        type Value = <LogStore as KeyValueSchema>::Key;
+
+        fn descriptor(_cache: &Cache) -> ColumnFamilyDescriptor {
+            let mut cf_opts = Options::default();
+            cf_opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(std::mem::size_of::<u64>()));
+            cf_opts.set_memtable_prefix_bloom_ratio(0.2);
+            ColumnFamilyDescriptor::new(Self::name(), cf_opts)
+        }
+
+        fn name() -> &'static str {
+            "log_reference_index"
+        }
+    }
+
+    impl SecondaryIndex<LogStore> for ReferenceIndex {
+        type FieldType = u64;
+
+        // the log might mention many peers, they are handled in `store_index` and `delete_index`
+        fn accessor(_value: &<LogStore as KeyValueSchema>::Value) -> Option<Self::FieldType> {
+            None
+        }
+
+        fn make_index(key: &<LogStore as KeyValueSchema>::Key, value: Self::FieldType) -> HashKey {
+            HashKey::new(value, key.clone())
+        }
+
+        fn make_prefix_index(value: Self::FieldType) -> HashKey {
+            HashKey::prefix(value)
+        }
+
+        fn store_index(&self, key: &<LogStore as KeyValueSchema>::Key, value: &<LogStore as KeyValueSchema>::Value) -> Result<(), StorageError> {
+            for reference in Self::references(value) {
+                self.kv.put(&Self::make_index(key, reference), key)?;
+            }
+            Ok(())
+        }
+
+        fn delete_index(&self, key: &<LogStore as KeyValueSchema>::Key, value: &<LogStore as KeyValueSchema>::Value) -> Result<(), StorageError> {
+            for reference in Self::references(value) {
+                self.kv.delete(&Self::make_index(key, reference))?;
+            }
+            Ok(())
+        }
+    }
+
+    // Section
+
+    pub type SectionIndexKV = dyn KeyValueStoreWithSchema<SectionIndex> + Sync + Send;
+
+    #[derive(Clone)]
+    pub struct SectionIndex {
+        kv: Arc<SectionIndexKV>,
+    }
+
+    impl SectionIndex {
+        pub fn new(kv: Arc<DB>) -> Self {
+            Self { kv }
+        }
+    }
+
+    impl AsRef<(dyn KeyValueStoreWithSchema<SectionIndex> + 'static)> for SectionIndex {
+        fn as_ref(&self) -> &(dyn KeyValueStoreWithSchema<SectionIndex> + 'static) {
+            self.kv.as_ref()
+        }
+    }
+
+    impl KeyValueSchema for SectionIndex {
+        type Key = HashKey;
+        type Value = <LogStore as KeyValueSchema>::Key;
+
+        fn descriptor(_cache: &Cache) -> ColumnFamilyDescriptor {
+            let mut cf_opts = Options::default();
+            cf_opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(std::mem::size_of::<u64>()));
+            cf_opts.set_memtable_prefix_bloom_ratio(0.2);
+            ColumnFamilyDescriptor::new(Self::name(), cf_opts)
+        }
+
+        fn name() -> &'static str {
+            "log_section_index"
+        }
+    }
+
+    impl SecondaryIndex<LogStore> for SectionIndex {
+        type FieldType = u64;
+
+        fn accessor(value: &<LogStore as KeyValueSchema>::Value) -> Option<Self::FieldType> {
+            if value.section.is_empty() {
+                None
+            } else {
+                Some(hash_token(&value.section))
+            }
+        }
+
+        fn make_index(key: &<LogStore as KeyValueSchema>::Key, value: Self::FieldType) -> HashKey {
+            HashKey::new(value, key.clone())
+        }
+
+        fn make_prefix_index(value: Self::FieldType) -> HashKey {
+            HashKey::prefix(value)
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct HashKey {
+        pub hash: u64,
+        pub index: u64,
+    }
+
+    impl HashKey {
+        pub fn new(hash: u64, index: u64) -> Self {
+            Self {
+                hash,
+                index: std::u64::MAX.saturating_sub(index),
+            }
+        }
+
+        pub fn prefix(hash: u64) -> Self {
+            Self {
+                hash,
+                index: 0,
+            }
+        }
+    }
+
+    /// * bytes layout: `[hash(8)][index(8)]`
+    impl Decoder for HashKey {
+        fn decode(bytes: &[u8]) -> Result<Self, SchemaError> {
+            if bytes.len() != 16 {
+                return Err(SchemaError::DecodeError);
+            }
+            let mut hash = [0u8; 8];
+            hash.copy_from_slice(&bytes[0..8]);
+            let mut index = [0u8; 8];
+            index.copy_from_slice(&bytes[8..]);
+            Ok(Self {
+                hash: u64::from_be_bytes(hash),
+                index: u64::from_be_bytes(index),
+            })
+        }
+    }
+
+    /// * bytes layout: `[hash(8)][index(8)]`
+    impl Encoder for HashKey {
+        fn encode(&self) -> Result<Vec<u8>, SchemaError> {
+            let mut buf = Vec::with_capacity(16);
+            buf.extend_from_slice(&self.hash.to_be_bytes());
+            buf.extend_from_slice(&self.index.to_be_bytes());
+            Ok(buf)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use crate::storage::{open_temp_db, remove_temp_db};
+
+    fn store_logs(store: &LogStore, logs: &[(u128, &str)]) {
+        for &(date, message) in logs {
+            store.store_message(&mut LogMessage::new(date, "info", "node.p2p", message, BTreeMap::new())).unwrap();
+        }
+    }
+
+    fn ids(logs: Vec<LogMessage>) -> Vec<u64> {
+        logs.into_iter().map(|log| log.id.unwrap()).collect()
+    }
+
+    #[test]
+    fn time_window() {
+        let db = open_temp_db("log_window");
+        let store = LogStore::new(db.clone());
+        // the dates parsed from the lines are not in the order of arrival
+        store_logs(&store, &[(30, "first"), (10, "second"), (50, "third"), (20, "fourth")]);
+
+        let window = |cursor, from, to, level: Vec<LogLevel>| {
+            let filters = LogFilters { from, to, level, ..LogFilters::default() };
+            ids(store.get_cursor(cursor, 10, filters).unwrap())
+        };
+        assert_eq!(window(None, Some(15), Some(35), vec![]), vec![3, 0]);
+        assert_eq!(window(None, Some(15), Some(35), vec![LogLevel::Info]), vec![3, 0]);
+        assert_eq!(window(None, Some(15), Some(35), vec![LogLevel::Error]), Vec::<u64>::new());
+        assert_eq!(window(Some(2), Some(15), None, vec![]), vec![2, 0]);
+        assert_eq!(window(None, None, Some(10), vec![]), vec![1]);
+        assert_eq!(window(None, Some(51), None, vec![]), Vec::<u64>::new());
+        // the window is walked from the cursor, only up to the limit
+        let filters = LogFilters { from: Some(15), to: Some(35), ..LogFilters::default() };
+        assert_eq!(ids(store.get_cursor(None, 1, filters).unwrap()), vec![3]);
+        assert_eq!(store.window_iterator(Some(2), Some(15), Some(35)).unwrap().collect::<Vec<_>>(), vec![0]);
+        remove_temp_db(db);
+    }
+
+    #[test]
+    fn tokens() {
+        let tokens = tokenize("Blacklisting IP 51.15.220.7:9732, peer_id=idtJunq a").collect::<Vec<_>>();
+        assert_eq!(tokens, vec!["blacklisting", "ip", "51", "15", "220", "9732", "peer_id", "idtjunq"]);
+        assert_eq!(hash_token("ip"), hash_token("ip"));
+        assert_ne!(hash_token("ip"), hash_token("pi"));
+    }
+
+    #[test]
+    fn token_index() {
+        let db = open_temp_db("log_tokens");
+        let store = LogStore::new(db.clone());
+        let mut fields = BTreeMap::new();
+        fields.insert("peer".to_string(), "idtJunqYgD1M6r6o2qvGpiD5xKZWRu".to_string());
+        let mut log = LogMessage::new(1, "warn", "node.p2p", "Blacklisting IP", fields);
+        let index = store.store_message(&mut log).unwrap();
+
+        let found = |token: &str| store.token_iterator(None, token).unwrap().collect::<Vec<_>>();
+        assert_eq!(found("blacklisting"), vec![index]);
+        assert_eq!(found("idtjunqygd1m6r6o2qvgpid5xkzwru"), vec![index]);
+        assert!(found("bootstrap").is_empty());
+        assert_eq!(store.section_iterator(None, "node.p2p").unwrap().collect::<Vec<_>>(), vec![index]);
+
+        store.delete_indexes(index, &log).unwrap();
+        assert!(found("blacklisting").is_empty());
+        assert!(store.section_iterator(None, "node.p2p").unwrap().next().is_none());
+        remove_temp_db(db);
+    }
+
+    #[test]
+    fn query() {
+        let db = open_temp_db("log_query");
+        let store = LogStore::new(db.clone());
+        store_logs(&store, &[
+            (1, "Blacklisting IP because peer failed at bootstrap"),
+            (2, "Bootstrap failed"),
+        ]);
+        // the tokens are in the log, but not next to each other
+        for date in 3..8 {
+            store_logs(&store, &[(date, "IP is not blacklisting")]);
+        }
+        let mut fields = BTreeMap::new();
+        fields.insert("reason".to_string(), "blacklisting ip".to_string());
+        store.store_message(&mut LogMessage::new(8, "info", "node.p2p", "peer disconnected", fields)).unwrap();
+
+        let query = |cursor, limit, query: &str| {
+            let filters = LogFilters { query: Some(query.to_string()), ..LogFilters::default() };
+            ids(store.get_cursor(cursor, limit, filters).unwrap())
+        };
+        assert_eq!(query(None, 10, "Blacklisting IP"), vec![7, 0]);
+        // the candidates which do not match are skipped until the page is full
+        assert_eq!(query(None, 2, "blacklisting ip"), vec![7, 0]);
+        assert_eq!(query(Some(6), 1, "blacklisting ip"), vec![0]);
+        assert_eq!(query(None, 10, "bootstrap"), vec![1, 0]);
+        assert_eq!(query(None, 10, "failed bootstrap"), Vec::<u64>::new());
+        // the query without tokens matches everything
+        assert_eq!(query(None, 2, "!"), vec![7, 6]);
+        remove_temp_db(db);
+    }
+}
@@ -5,22 +5,30 @@ mod p2p_storage;
 mod log_storage;
 mod rpc_storage;
 mod stat_storage;
+mod connection_storage;
+mod alert_storage;
+mod timeseries_storage;
 mod secondary_index;
 
 pub use p2p_storage::{P2pStore, P2pFilters, secondary_indexes::Type as P2pMessageType};
 pub use log_storage::{LogStore, LogFilters};
 pub use rpc_storage::{RpcStore, RpcFilters};
+pub use connection_storage::{ConnectionStore, ConnectionFilters};
+pub use alert_storage::AlertStore;
+pub use timeseries_storage::{TimeseriesStore, TimeseriesFilters, Resolution, Grouping, SampleKey, Counters, Point};
 pub(crate) use p2p_storage::secondary_indexes as p2p_indexes;
 pub(crate) use log_storage::secondary_indexes as log_indexes;
 pub(crate) use rpc_storage::secondary_indexes as rpc_indexes;
 
-use rocksdb::{DB, ColumnFamilyDescriptor, Cache};
+use rocksdb::{DB, ColumnFamilyDescriptor, Cache, Options};
 use std::{
     sync::Arc,
     time::{SystemTime, UNIX_EPOCH},
     net::IpAddr,
+    path::Path,
 };
-use storage::persistent::KeyValueSchema;
+use serde::Serialize;
+use storage::{StorageError, IteratorMode, Direction, persistent::{KeyValueSchema, KeyValueStoreWithSchema}};
 use crate::storage::stat_storage::StatStore;
 
 #[derive(Clone)]
@@ -29,22 +37,31 @@ pub struct MessageStore {
     p2p_db: P2pStore,
     log_db: LogStore,
     rpc_db: RpcStore,
+    connection_db: ConnectionStore,
+    alert_db: AlertStore,
+    timeseries_db: TimeseriesStore,
     stat_db: Arc<StatStore>,
     raw_db: Arc<DB>,
+    history_db: Arc<DB>,
     max_db_size: Option<u64>,
 }
 
 impl MessageStore {
-    /// Create new store onto given RocksDB database
-    pub fn new(db: Arc<DB>) -> Self {
-        Self {
+    /// Create new store onto given RocksDB databases, `db` keeps the captured messages,
+    /// `history` keeps the connection reports and the alerts, which survive the restart
+    pub fn new(db: Arc<DB>, history: Arc<DB>) -> Result<Self, StorageError> {
+        Ok(Self {
             p2p_db: P2pStore::new(db.clone()),
             log_db: LogStore::new(db.clone()),
             rpc_db: RpcStore::new(db.clone()),
+            connection_db: ConnectionStore::new(history.clone())?,
+            alert_db: AlertStore::new(history.clone())?,
+            timeseries_db: TimeseriesStore::new(db.clone()),
             stat_db: Arc::new(StatStore::new()),
             raw_db: db,
+            history_db: history,
             max_db_size: None,
-        }
+        })
     }
 
     /// Get p2p message store
@@ -62,29 +79,183 @@ impl MessageStore {
         &self.rpc_db
     }
 
+    /// Get p2p connection report store
+    pub fn connection(&self) -> &ConnectionStore {
+        &self.connection_db
+    }
+
+    /// Get alert store
+    pub fn alert(&self) -> &AlertStore {
+        &self.alert_db
+    }
+
+    /// Get traffic time series store
+    pub fn timeseries(&self) -> &TimeseriesStore {
+        &self.timeseries_db
+    }
+
     /// Get statistics store
     pub fn stat(&self) -> &StatStore {
         &self.stat_db
     }
+
+    /// Path of the RocksDB database of the captured messages
+    pub fn path(&self) -> &Path {
+        self.raw_db.path()
+    }
+
+    /// Path of the RocksDB database of the connection reports and the alerts
+    pub fn history_path(&self) -> &Path {
+        self.history_db.path()
+    }
+
+    /// Store the pending time series and flush the memtables of all column families to the disk
+    pub fn flush(&self) -> Result<(), failure::Error> {
+        self.timeseries_db.flush()?;
+        for db in [&self.raw_db, &self.history_db].iter() {
+            for name in DB::list_cf(&Options::default(), db.path())? {
+                if let Some(cf) = db.cf_handle(&name) {
+                    db.flush_cf(cf)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sizes of all column families of both databases, as estimated by RocksDB
+    pub fn column_families(&self) -> Result<Vec<ColumnFamilySize>, failure::Error> {
+        let mut sizes = Vec::new();
+        for db in [&self.raw_db, &self.history_db].iter() {
+            for name in DB::list_cf(&Options::default(), db.path())? {
+                let property = |property: &str| -> Result<u64, failure::Error> {
+                    match db.cf_handle(&name) {
+                        Some(cf) => Ok(db.property_int_value_cf(cf, property)?.unwrap_or(0)),
+                        None => Ok(0),
+                    }
+                };
+                sizes.push(ColumnFamilySize {
+                    disk_size: property("rocksdb.total-sst-files-size")?,
+                    memory_size: property("rocksdb.size-all-mem-tables")?,
+                    name,
+                });
+            }
+        }
+        Ok(sizes)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+/// Size of the column family in bytes
+pub struct ColumnFamilySize {
+    pub name: String,
+    pub disk_size: u64,
+    pub memory_size: u64,
 }
 
-/// Create list of all Column Family descriptors required for Message store
+/// Create list of all Column Family descriptors required for the captured messages of Message store
 pub fn cfs() -> Vec<ColumnFamilyDescriptor> {
     let cache = Cache::new_lru_cache(1).unwrap();
     vec![
         P2pStore::descriptor(&cache),
         LogStore::descriptor(&cache),
         RpcStore::descriptor(&cache),
+        TimeseriesStore::descriptor(&cache),
         p2p_indexes::RemoteAddrIndex::descriptor(&cache),
         p2p_indexes::TypeIndex::descriptor(&cache),
         p2p_indexes::IncomingIndex::descriptor(&cache),
         p2p_indexes::SourceTypeIndex::descriptor(&cache),
+        p2p_indexes::ViolationIndex::descriptor(&cache),
         log_indexes::LevelIndex::descriptor(&cache),
         log_indexes::TimestampIndex::descriptor(&cache),
+        log_indexes::DateIndex::descriptor(&cache),
+        log_indexes::TokenIndex::descriptor(&cache),
+        log_indexes::SectionIndex::descriptor(&cache),
+        log_indexes::ReferenceIndex::descriptor(&cache),
         rpc_indexes::RemoteAddrIndex::descriptor(&cache),
     ]
 }
 
+/// Create list of Column Family descriptors of the history, which is kept across restarts
+pub fn history_cfs() -> Vec<ColumnFamilyDescriptor> {
+    let cache = Cache::new_lru_cache(1).unwrap();
+    vec![
+        ConnectionStore::descriptor(&cache),
+        AlertStore::descriptor(&cache),
+    ]
+}
+
+/// The index following the last stored value, so the sequence continues after the restart
+pub(crate) fn next_index<S>(kv: &(dyn KeyValueStoreWithSchema<S> + Sync + Send)) -> Result<u64, StorageError>
+where
+    S: KeyValueSchema<Key = u64>,
+{
+    let last = kv.iterator(IteratorMode::From(&std::u64::MAX, Direction::Reverse))?
+        .filter_map(|(k, _)| k.ok())
+        .next();
+    Ok(last.map(|last| last + 1).unwrap_or(0))
+}
+
+/// Window of primary indexes `(lower, upper)`, both inclusive, of the values captured in the time window.
+/// Values must be stored in the order of their timestamps, so the window is found by binary search.
+/// It holds for the timestamps the debugger assigns on capture, the logs are dated by the node,
+/// see `LogStore::window_iterator`. Returns `None` if the window is empty.
+pub(crate) fn time_window<S, F>(
+    kv: &(dyn KeyValueStoreWithSchema<S> + Sync + Send),
+    from: Option<u128>,
+    to: Option<u128>,
+    timestamp: F,
+) -> Result<Option<(u64, u64)>, StorageError>
+where
+    S: KeyValueSchema<Key = u64>,
+    F: Fn(&S::Value) -> u128,
+{
+    // the first value at or after the index
+    let value_from = |index: u64| -> Result<Option<(u64, u128)>, StorageError> {
+        Ok(kv.iterator(IteratorMode::From(&index, Direction::Forward))?
+            .filter_map(|(k, v)| Some((k.ok()?, timestamp(&v.ok()?))))
+            .next())
+    };
+    // the index of the first value whose timestamp is not less than given
+    let lower_bound = |ts: u128| -> Result<Option<u64>, StorageError> {
+        let last = kv.iterator(IteratorMode::From(&std::u64::MAX, Direction::Reverse))?
+            .filter_map(|(k, _)| k.ok())
+            .next();
+        let (mut lo, mut hi) = match last {
+            Some(last) => (0, last.saturating_add(1)),
+            None => return Ok(None),
+        };
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            match value_from(mid)? {
+                Some((index, value_ts)) if value_ts < ts => lo = index + 1,
+                _ => hi = mid,
+            }
+        }
+        Ok(value_from(lo)?.map(|(index, _)| index))
+    };
+
+    let lower = match from {
+        Some(from) => match lower_bound(from)? {
+            Some(index) => index,
+            None => return Ok(None),
+        },
+        None => 0,
+    };
+    let upper = match to {
+        Some(to) => match lower_bound(to.saturating_add(1))? {
+            Some(0) => return Ok(None),
+            Some(index) => index - 1,
+            None => std::u64::MAX,
+        },
+        None => std::u64::MAX,
+    };
+    if lower > upper {
+        Ok(None)
+    } else {
+        Ok(Some((lower, upper)))
+    }
+}
+
 /// Create new UNIX timestamp
 pub fn get_ts() -> u128 {
     SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos()
@@ -230,4 +401,50 @@ pub mod sorted_intersect {
             false
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+/// Open the empty database with all column families in a new temporary directory
+pub(crate) fn open_temp_db(name: &str) -> Arc<DB> {
+    use storage::persistent::{open_kv, DbConfiguration};
+
+    let path = std::env::temp_dir().join(format!("debugger_{}_{}_{}", name, std::process::id(), get_ts()));
+    let schemas = cfs().into_iter().chain(history_cfs()).collect();
+    Arc::new(open_kv(&path, schemas, &DbConfiguration::default()).unwrap())
+}
+
+#[cfg(test)]
+/// Remove the directory of the database opened by `open_temp_db`
+pub(crate) fn remove_temp_db(db: Arc<DB>) {
+    let path = db.path().to_path_buf();
+    drop(db);
+    std::fs::remove_dir_all(path).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{messages::alert_message::Alert, storage::alert_storage::AlertStorageKV};
+
+    #[test]
+    fn time_window_bounds() {
+        let db = open_temp_db("time_window");
+        let kv: Arc<AlertStorageKV> = db.clone();
+        let window = |from, to| time_window(kv.as_ref(), from, to, |alert: &Alert| alert.timestamp).unwrap();
+        assert_eq!(window(Some(0), None), None);
+
+        for (index, &timestamp) in [10, 20, 20, 30, 50].iter().enumerate() {
+            kv.put(&(index as u64), &Alert::new(timestamp, "rule", String::new(), None)).unwrap();
+        }
+        assert_eq!(window(None, None), Some((0, std::u64::MAX)));
+        assert_eq!(window(Some(20), Some(30)), Some((1, 3)));
+        assert_eq!(window(Some(11), Some(29)), Some((1, 2)));
+        assert_eq!(window(None, Some(15)), Some((0, 0)));
+        assert_eq!(window(Some(30), None), Some((3, std::u64::MAX)));
+        assert_eq!(window(Some(31), Some(49)), None);
+        assert_eq!(window(Some(60), None), None);
+        assert_eq!(window(None, Some(5)), None);
+        drop(kv);
+        remove_temp_db(db);
+    }
+}
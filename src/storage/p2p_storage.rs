@@ -9,7 +9,7 @@ use std::{
         atomic::{Ordering, AtomicU64}, Arc,
     }, net::SocketAddr,
 };
-use crate::storage::{secondary_index::SecondaryIndex, dissect};
+use crate::storage::{secondary_index::SecondaryIndex, dissect, time_window};
 use crate::storage::sorted_intersect::sorted_intersect;
 use secondary_indexes::*;
 use itertools::Itertools;
@@ -26,14 +26,18 @@ pub struct P2pFilters {
     pub request_id: Option<u64>,
     pub incoming: Option<bool>,
     pub source_type: Option<bool>,
+    pub violations: bool,
+    // time window, unix nanos
+    pub from: Option<u128>,
+    pub to: Option<u128>,
 }
 
 impl P2pFilters {
-    /// Check, if there are no set filters
+    /// Check, if there are no set filters, the time window is not a filter, it limits the cursor
     pub fn empty(&self) -> bool {
         self.remote_addr.is_none() && self.types.is_none()
             && self.request_id.is_none() && self.incoming.is_none()
-            && self.source_type.is_none()
+            && self.source_type.is_none() && !self.violations
     }
 }
 
@@ -45,6 +49,7 @@ pub struct P2pStore {
     type_index: TypeIndex,
     incoming_index: IncomingIndex,
     source_type_index: SourceTypeIndex,
+    violation_index: ViolationIndex,
     count: Arc<AtomicU64>,
     seq: Arc<AtomicU64>,
 }
@@ -59,6 +64,7 @@ impl P2pStore {
             type_index: TypeIndex::new(kv.clone()),
             incoming_index: IncomingIndex::new(kv.clone()),
             source_type_index: SourceTypeIndex::new(kv.clone()),
+            violation_index: ViolationIndex::new(kv.clone()),
             count: Arc::new(AtomicU64::new(0)),
             seq: Arc::new(AtomicU64::new(0)),
         }
@@ -85,7 +91,8 @@ impl P2pStore {
         self.remote_addr_index.store_index(&primary_index, value)?;
         self.type_index.store_index(&primary_index, value)?;
         self.incoming_index.store_index(&primary_index, value)?;
-        self.source_type_index.store_index(&primary_index, value)
+        self.source_type_index.store_index(&primary_index, value)?;
+        self.violation_index.store_index(&primary_index, value)
     }
 
     /// Put messages onto specific index
@@ -93,7 +100,8 @@ impl P2pStore {
         self.remote_addr_index.delete_index(&primary_index, value)?;
         self.type_index.delete_index(&primary_index, value)?;
         self.incoming_index.delete_index(&primary_index, value)?;
-        self.source_type_index.delete_index(&primary_index, value)
+        self.source_type_index.delete_index(&primary_index, value)?;
+        self.violation_index.delete_index(&primary_index, value)
     }
 
     /// Store message at the end of the store. Return ID of newly inserted value
@@ -136,8 +144,13 @@ impl P2pStore {
     /// start at the end
     pub fn get_cursor(&self, cursor_index: Option<u64>, limit: usize, filters: P2pFilters) -> Result<Vec<P2pMessage>, StorageError> {
         let mut ret = Vec::with_capacity(limit);
+        let (lower, upper) = match time_window(self.kv.as_ref(), filters.from, filters.to, |msg: &P2pMessage| msg.timestamp)? {
+            Some(window) => window,
+            None => return Ok(ret),
+        };
+        let cursor_index = Some(cursor_index.unwrap_or(std::u64::MAX).min(upper));
         if filters.empty() {
-            ret.extend(self.cursor_iterator(cursor_index)?.take(limit).map(|(_, value)| value));
+            ret.extend(self.cursor_iterator(cursor_index)?.take_while(|&(index, _)| index >= lower).take(limit).map(|(_, value)| value));
         } else {
             let mut iters: Vec<Box<dyn Iterator<Item=u64>>> = Default::default();
             if let Some(remote_addr) = filters.remote_addr {
@@ -154,6 +167,10 @@ impl P2pStore {
             if let Some(source_type) = filters.source_type {
                 iters.push(self.source_type_iterator(cursor_index, source_type)?);
             }
+            if filters.violations {
+                iters.push(self.violation_iterator(cursor_index)?);
+            }
+            let iters = iters.into_iter().map(|iter| iter.take_while(move |&index| index >= lower)).collect::<Vec<_>>();
             ret.extend(self.load_indexes(sorted_intersect(iters, limit).into_iter()));
         }
         for (ordinal, message) in ret.iter_mut().enumerate() {
@@ -206,6 +223,14 @@ impl P2pStore {
             })))
     }
 
+    /// Create iterator with at maximum given index, over messages violating the protocol
+    pub fn violation_iterator<'a>(&'a self, cursor_index: Option<u64>) -> Result<Box<dyn 'a + Iterator<Item=u64>>, StorageError> {
+        Ok(Box::new(self.violation_index.get_concrete_prefix_iterator(&cursor_index.unwrap_or(std::u64::MAX), true)?
+            .filter_map(|(_, value)| {
+                value.ok()
+            })))
+    }
+
     /// Load all values for indexes given.
     pub fn load_indexes<Iter: 'static + Iterator<Item=u64>>(&self, indexes: Iter) -> impl Iterator<Item=P2pMessage> + 'static {
         let kv = self.kv.clone();
@@ -594,6 +619,40 @@ pub(crate) mod secondary_indexes {
     }
 
 
+    impl Type {
+        /// Name of the type, same as parsed by `FromStr`
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                Self::Tcp => "tcp",
+                Self::Metadata => "metadata",
+                Self::ConnectionMessage => "connection_message",
+                Self::RestMessage => "rest_message",
+                Self::P2PMessage => "p2p_message",
+                Self::Disconnect => "disconnect",
+                Self::Advertise => "advertise",
+                Self::SwapRequest => "swap_request",
+                Self::SwapAck => "swap_ack",
+                Self::Bootstrap => "bootstrap",
+                Self::GetCurrentBranch => "get_current_branch",
+                Self::CurrentBranch => "current_branch",
+                Self::Deactivate => "deactivate",
+                Self::GetCurrentHead => "get_current_head",
+                Self::CurrentHead => "current_head",
+                Self::GetBlockHeaders => "get_block_headers",
+                Self::BlockHeader => "block_header",
+                Self::GetOperations => "get_operations",
+                Self::Operation => "operation",
+                Self::GetProtocols => "get_protocols",
+                Self::Protocol => "protocol",
+                Self::GetOperationHashesForBlocks => "get_operation_hashes_for_blocks",
+                Self::OperationHashesForBlock => "operation_hashes_for_block",
+                Self::GetOperationsForBlocks => "get_operations_for_blocks",
+                Self::OperationsForBlocks => "operations_for_blocks",
+                Self::AckMessage => "ack_message",
+            }
+        }
+    }
+
     #[derive(Debug, Fail)]
     #[fail(display = "Invalid message type {}", _0)]
     pub struct ParseTypeError(String);
@@ -873,4 +932,121 @@ pub(crate) mod secondary_indexes {
             }
         }
     }
-}
\ No newline at end of file
+
+    // 6. Violation Index
+    pub type ViolationIndexKV = dyn KeyValueStoreWithSchema<ViolationIndex> + Sync + Send;
+
+    #[derive(Clone)]
+    pub struct ViolationIndex {
+        kv: Arc<ViolationIndexKV>,
+    }
+
+    impl ViolationIndex {
+        pub fn new(kv: Arc<DB>) -> Self {
+            Self { kv }
+        }
+    }
+
+    impl AsRef<(dyn KeyValueStoreWithSchema<ViolationIndex> + 'static)> for ViolationIndex {
+        fn as_ref(&self) -> &(dyn KeyValueStoreWithSchema<ViolationIndex> + 'static) {
+            self.kv.as_ref()
+        }
+    }
+
+    impl KeyValueSchema for ViolationIndex {
+        type Key = ViolationKey;
+        type Value = <P2pStore as KeyValueSchema>::Key;
+
+        fn descriptor(_cache: &Cache) -> ColumnFamilyDescriptor {
+            let mut cf_opts = Options::default();
+            cf_opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(std::mem::size_of::<bool>()));
+            cf_opts.set_memtable_prefix_bloom_ratio(0.2);
+            ColumnFamilyDescriptor::new(Self::name(), cf_opts)
+        }
+
+        fn name() -> &'static str {
+            "p2p_violation_index"
+        }
+    }
+
+    impl SecondaryIndex<P2pStore> for ViolationIndex {
+        type FieldType = bool;
+
+        /// Only messages violating the protocol are indexed
+        fn accessor(value: &<P2pStore as KeyValueSchema>::Value) -> Option<Self::FieldType> {
+            if value.violations.is_empty() {
+                None
+            } else {
+                Some(true)
+            }
+        }
+
+        fn make_index(key: &<P2pStore as KeyValueSchema>::Key, value: Self::FieldType) -> ViolationKey {
+            ViolationKey::new(value, key.clone())
+        }
+
+        fn make_prefix_index(value: Self::FieldType) -> ViolationKey {
+            ViolationKey::prefix(value)
+        }
+    }
+
+    #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+    pub struct ViolationKey {
+        pub has_violations: bool,
+        pub index: u64,
+    }
+
+    impl ViolationKey {
+        pub fn new(has_violations: bool, index: u64) -> Self {
+            Self { has_violations, index: std::u64::MAX.saturating_sub(index) }
+        }
+
+        pub fn prefix(has_violations: bool) -> Self {
+            Self { has_violations, index: 0 }
+        }
+    }
+
+    /// * bytes layout: `[has_violations(1)][padding(7)][index(8)]`
+    impl Decoder for ViolationKey {
+        #[inline]
+        fn decode(bytes: &[u8]) -> Result<Self, SchemaError> {
+            if bytes.len() != 16 {
+                return Err(SchemaError::DecodeError);
+            }
+            let has_violations_value = &bytes[0..1];
+            let _padding_value = &bytes[1..1 + 7];
+            let index_value = &bytes[1 + 7..];
+            // has_violations
+            let has_violations = if has_violations_value == &[true as u8] {
+                true
+            } else if has_violations_value == &[false as u8] {
+                false
+            } else {
+                return Err(SchemaError::DecodeError);
+            };
+            // index
+            let mut index = [0u8; 8];
+            for (x, y) in index.iter_mut().zip(index_value) {
+                *x = *y;
+            }
+            let index = u64::from_be_bytes(index);
+            Ok(Self {
+                has_violations,
+                index,
+            })
+        }
+    }
+
+    /// * bytes layout: `[has_violations(1)][padding(7)][index(8)]`
+    impl Encoder for ViolationKey {
+        #[inline]
+        fn encode(&self) -> Result<Vec<u8>, SchemaError> {
+            let mut buf = Vec::with_capacity(16);
+            buf.extend_from_slice(&[self.has_violations as u8]); // has_violations
+            buf.extend_from_slice(&[0u8; 7]); // padding
+            buf.extend_from_slice(&self.index.to_be_bytes()); // index
+
+            Ok(buf)
+        }
+    }
+}
@@ -12,6 +12,7 @@ use tracing::{info, warn};
 use secondary_indexes::RemoteAddrIndex;
 use crate::storage::secondary_index::SecondaryIndex;
 use crate::storage::sorted_intersect::sorted_intersect;
+use crate::storage::time_window;
 use crate::messages::rpc_message::RpcMessage;
 
 /// Defined Key Value store for Log storage
@@ -21,10 +22,13 @@ pub type RpcMessageStorageKV = dyn KeyValueStoreWithSchema<RpcStore> + Sync + Se
 /// Allowed filters for log message store
 pub struct RpcFilters {
     pub remote_addr: Option<SocketAddr>,
+    // time window, unix nanos
+    pub from: Option<u128>,
+    pub to: Option<u128>,
 }
 
 impl RpcFilters {
-    /// Check, if there are no set filters
+    /// Check, if there are no set filters, the time window is not a filter, it limits the cursor
     pub fn empty(&self) -> bool {
         self.remote_addr.is_none()
     }
@@ -108,13 +112,19 @@ impl RpcStore {
     /// - filters: Specified filters for values
     pub fn get_cursor(&self, cursor_index: Option<u64>, limit: usize, filters: RpcFilters) -> Result<Vec<RpcMessage>, StorageError> {
         let mut ret = Vec::with_capacity(limit);
+        let (lower, upper) = match time_window(self.kv.as_ref(), filters.from, filters.to, |msg: &RpcMessage| msg.timestamp)? {
+            Some(window) => window,
+            None => return Ok(ret),
+        };
+        let cursor_index = Some(cursor_index.unwrap_or(std::u64::MAX).min(upper));
         if filters.empty() {
-            ret.extend(self.cursor_iterator(cursor_index)?.take(limit).map(|(_key, value)| value));
+            ret.extend(self.cursor_iterator(cursor_index)?.take_while(|&(index, _)| index >= lower).take(limit).map(|(_key, value)| value));
         } else {
             let mut iters: Vec<Box<dyn Iterator<Item=u64>>> = Default::default();
             if let Some(remote_addr) = filters.remote_addr {
                 iters.push(self.remote_addr_iterator(cursor_index, remote_addr)?);
             }
+            let iters = iters.into_iter().map(|iter| iter.take_while(move |&index| index >= lower)).collect::<Vec<_>>();
             ret.extend(self.load_indexes(sorted_intersect(iters, limit).into_iter()));
         }
         Ok(ret)
@@ -1,9 +1,12 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use std::sync::{
-    Arc,
-    atomic::{AtomicUsize, Ordering},
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
 };
 use serde::{Serialize, Deserialize};
 
@@ -15,9 +18,25 @@ pub struct StatStore {
     processed_data: Arc<AtomicUsize>,
     captured_packets: Arc<AtomicUsize>,
     deciphered_packets: Arc<AtomicUsize>,
+    ring_buffer_drops: Arc<AtomicUsize>,
+    iov_limit_drops: Arc<AtomicUsize>,
+    sendfile_drops: Arc<AtomicUsize>,
+    lost_data: Arc<AtomicUsize>,
+    // the drops by the socket, `pid:fd`
+    socket_drops: Arc<Mutex<BTreeMap<String, SocketDrops>>>,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
+/// Data of the single socket which is not captured
+pub struct SocketDrops {
+    drops: usize,
+    lost_data: usize,
 }
 
 impl StatStore {
+    /// The drops of at most this many sockets are counted, the rest are counted only in total
+    const MAX_SOCKETS: usize = 0x1000;
+
     /// Build new statistics store
     pub fn new() -> Self {
         Default::default()
@@ -41,6 +60,36 @@ impl StatStore {
         self.processed_data(data_len);
     }
 
+    /// Count the data of the socket which did not fit in the ring buffer
+    pub fn ring_buffer_drop(&self, socket: String, data_len: usize) {
+        self.ring_buffer_drops.fetch_add(1, Ordering::SeqCst);
+        self.lost_data.fetch_add(data_len, Ordering::SeqCst);
+        self.socket_drop(socket, data_len);
+    }
+
+    /// Count the data of the socket in the buffers of the vectored syscalls beyond the captured ones
+    pub fn iov_limit_drop(&self, socket: String, data_len: usize) {
+        self.iov_limit_drops.fetch_add(1, Ordering::SeqCst);
+        self.lost_data.fetch_add(data_len, Ordering::SeqCst);
+        self.socket_drop(socket, data_len);
+    }
+
+    /// Count the data the socket sent by the sendfile syscall, it does not pass through the user space
+    pub fn sendfile_drop(&self, socket: String, data_len: usize) {
+        self.sendfile_drops.fetch_add(1, Ordering::SeqCst);
+        self.lost_data.fetch_add(data_len, Ordering::SeqCst);
+        self.socket_drop(socket, data_len);
+    }
+
+    fn socket_drop(&self, socket: String, data_len: usize) {
+        let mut socket_drops = self.socket_drops.lock().unwrap();
+        if socket_drops.len() < Self::MAX_SOCKETS || socket_drops.contains_key(&socket) {
+            let drops = socket_drops.entry(socket).or_default();
+            drops.drops += 1;
+            drops.lost_data += data_len;
+        }
+    }
+
     /// Create statistics snapshot
     pub fn snapshot(&self) -> StatSnapshot {
         StatSnapshot {
@@ -49,11 +98,16 @@ impl StatStore {
             deciphered_data: self.deciphered_data.load(Ordering::SeqCst),
             captured_packets: self.captured_packets.load(Ordering::SeqCst),
             deciphered_packets: self.deciphered_data.load(Ordering::SeqCst),
+            ring_buffer_drops: self.ring_buffer_drops.load(Ordering::SeqCst),
+            iov_limit_drops: self.iov_limit_drops.load(Ordering::SeqCst),
+            sendfile_drops: self.sendfile_drops.load(Ordering::SeqCst),
+            lost_data: self.lost_data.load(Ordering::SeqCst),
+            socket_drops: self.socket_drops.lock().unwrap().clone(),
         }
     }
 }
 
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 /// Statistics snapshot sent by the statistics endpoint
 pub struct StatSnapshot {
     captured_data: usize,
@@ -61,4 +115,29 @@ pub struct StatSnapshot {
     deciphered_data: usize,
     captured_packets: usize,
     deciphered_packets: usize,
-}
\ No newline at end of file
+    ring_buffer_drops: usize,
+    iov_limit_drops: usize,
+    sendfile_drops: usize,
+    lost_data: usize,
+    socket_drops: BTreeMap<String, SocketDrops>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn socket_drops() {
+        let stat = StatStore::new();
+        stat.ring_buffer_drop("10:3".to_string(), 0x100);
+        stat.iov_limit_drop("10:3".to_string(), 0x20);
+        stat.ring_buffer_drop("10:4".to_string(), 0x10);
+        stat.sendfile_drop("10:3".to_string(), 0x1000);
+
+        let snapshot = stat.snapshot();
+        assert_eq!((snapshot.ring_buffer_drops, snapshot.iov_limit_drops, snapshot.sendfile_drops), (2, 1, 1));
+        assert_eq!(snapshot.lost_data, 0x1130);
+        assert_eq!(snapshot.socket_drops["10:3"], SocketDrops { drops: 3, lost_data: 0x1120 });
+        assert_eq!(snapshot.socket_drops["10:4"], SocketDrops { drops: 1, lost_data: 0x10 });
+    }
+}
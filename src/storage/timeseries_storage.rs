@@ -0,0 +1,404 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use storage::{
+    StorageError, IteratorMode, Direction,
+    persistent::{KeyValueStoreWithSchema, KeyValueSchema, Decoder, Encoder, SchemaError},
+};
+use rocksdb::DB;
+use serde::{Serialize, Deserialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+/// Defined Key Value store for the time series
+pub type TimeseriesStorageKV = dyn KeyValueStoreWithSchema<TimeseriesStore> + Sync + Send;
+
+const NANOS: u128 = 1_000_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, strum::EnumString, strum::AsRefStr)]
+/// Length of the bucket of the time series
+pub enum Resolution {
+    #[strum(serialize = "1s")]
+    Second,
+    #[strum(serialize = "1m")]
+    Minute,
+    #[strum(serialize = "1h")]
+    Hour,
+}
+
+impl Resolution {
+    const ALL: [Resolution; 3] = [Resolution::Second, Resolution::Minute, Resolution::Hour];
+
+    /// Length of the bucket in seconds
+    pub fn seconds(&self) -> u64 {
+        match self {
+            Resolution::Second => 1,
+            Resolution::Minute => 60,
+            Resolution::Hour => 3600,
+        }
+    }
+
+    /// How long the buckets are retained, in seconds
+    fn retention(&self) -> u64 {
+        match self {
+            Resolution::Second => 3600,
+            Resolution::Minute => 24 * 3600,
+            Resolution::Hour => 30 * 24 * 3600,
+        }
+    }
+
+    fn tag(&self) -> u64 {
+        match self {
+            Resolution::Second => 1,
+            Resolution::Minute => 2,
+            Resolution::Hour => 3,
+        }
+    }
+
+    /// Start of the bucket containing the unix timestamp in seconds
+    pub fn bucket(&self, seconds: u64) -> u64 {
+        seconds - seconds % self.seconds()
+    }
+
+    // the resolution is in the highest byte, so buckets of the same resolution are sorted by time
+    fn key(&self, bucket: u64) -> u64 {
+        (self.tag() << 56) | bucket
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/// What the traffic is counted by
+pub struct SampleKey {
+    pub remote_addr: SocketAddr,
+    pub incoming: bool,
+    pub message_type: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+/// Traffic counted in the bucket
+pub struct Counters {
+    pub bytes: u64,
+    pub messages: u64,
+}
+
+impl Counters {
+    fn add(&mut self, other: &Counters) {
+        self.bytes += other.bytes;
+        self.messages += other.messages;
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+/// All traffic in the bucket, the value of the store
+pub struct TimeseriesBucket {
+    pub samples: Vec<(SampleKey, Counters)>,
+}
+
+impl Decoder for TimeseriesBucket {
+    fn decode(bytes: &[u8]) -> Result<Self, SchemaError> {
+        serde_cbor::from_slice(bytes)
+            .map_err(|_| SchemaError::DecodeError)
+    }
+}
+
+impl Encoder for TimeseriesBucket {
+    fn encode(&self) -> Result<Vec<u8>, SchemaError> {
+        serde_cbor::to_vec(self)
+            .map_err(|_| SchemaError::EncodeError)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// Which parts of the sample key the buckets are grouped by, the rest are summed up
+pub struct Grouping {
+    pub peer: bool,
+    pub direction: bool,
+    pub message_type: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+/// Allowed filters for the time series
+pub struct TimeseriesFilters {
+    pub remote_addr: Option<SocketAddr>,
+    pub incoming: Option<bool>,
+    pub message_type: Option<String>,
+}
+
+impl TimeseriesFilters {
+    fn matches(&self, key: &SampleKey) -> bool {
+        self.remote_addr.map(|addr| addr == key.remote_addr).unwrap_or(true)
+            && self.incoming.map(|incoming| incoming == key.incoming).unwrap_or(true)
+            && self.message_type.as_ref().map(|t| t == &key.message_type).unwrap_or(true)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+/// Traffic of the group in the bucket
+pub struct Group {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote_addr: Option<SocketAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub incoming: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_type: Option<String>,
+    pub bytes: u64,
+    pub messages: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+/// Single point of the time series
+pub struct Point {
+    // start of the bucket, unix timestamp in nanoseconds
+    pub timestamp: u128,
+    pub groups: Vec<Group>,
+}
+
+#[derive(Default)]
+struct Pending {
+    // the buckets still being filled, not stored yet
+    buckets: HashMap<(Resolution, u64), HashMap<SampleKey, Counters>>,
+}
+
+#[derive(Clone)]
+/// Rolling time series of the traffic in 1s, 1m and 1h buckets,
+/// the buckets are filled in memory and stored by a single writer when they end
+pub struct TimeseriesStore {
+    kv: Arc<TimeseriesStorageKV>,
+    pending: Arc<Mutex<Pending>>,
+}
+
+impl TimeseriesStore {
+    /// Create new store on top of the RocksDB
+    pub fn new(kv: Arc<DB>) -> Self {
+        Self {
+            kv,
+            pending: Arc::new(Mutex::new(Pending::default())),
+        }
+    }
+
+    /// Count the traffic in the buckets of all resolutions, in memory, nothing is stored here
+    pub fn record(&self, timestamp: u128, key: SampleKey, counters: Counters) {
+        let seconds = (timestamp / NANOS) as u64;
+        let mut pending = self.pending.lock().unwrap();
+        for resolution in Resolution::ALL.iter() {
+            pending.buckets.entry((*resolution, resolution.bucket(seconds)))
+                .or_default()
+                .entry(key.clone())
+                .or_default()
+                .add(&counters);
+        }
+    }
+
+    /// Store the buckets which ended before the unix timestamp in seconds and delete the buckets older
+    /// than the retention. Should be called by one writer, the queries wait until the buckets are stored
+    pub fn store_ended(&self, seconds: u64) -> Result<(), StorageError> {
+        let mut pending = self.pending.lock().unwrap();
+        let ended = pending.buckets.keys()
+            .filter(|(resolution, bucket)| bucket + resolution.seconds() <= seconds)
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut expired = Vec::new();
+        for (resolution, bucket) in ended {
+            let samples = pending.buckets.remove(&(resolution, bucket)).unwrap_or_default();
+            self.store_bucket(resolution, bucket, samples)?;
+            if !expired.contains(&resolution) {
+                expired.push(resolution);
+            }
+        }
+        for resolution in expired {
+            self.expire(resolution, seconds)?;
+        }
+        Ok(())
+    }
+
+    /// Store the buckets being filled, they are merged with the later messages if the recording continues
+    pub fn flush(&self) -> Result<(), StorageError> {
+        let mut pending = self.pending.lock().unwrap();
+        for ((resolution, bucket), samples) in pending.buckets.drain() {
+            self.store_bucket(resolution, bucket, samples)?;
+        }
+        Ok(())
+    }
+
+    fn store_bucket(&self, resolution: Resolution, bucket: u64, samples: HashMap<SampleKey, Counters>) -> Result<(), StorageError> {
+        // the bucket might be stored already, if a late message came after it ended
+        let mut stored = self.kv.get(&resolution.key(bucket))?
+            .map(|stored| stored.samples.into_iter().collect::<HashMap<_, _>>())
+            .unwrap_or_default();
+        for (key, counters) in samples {
+            stored.entry(key).or_default().add(&counters);
+        }
+        let value = TimeseriesBucket { samples: stored.into_iter().collect() };
+        self.kv.put(&resolution.key(bucket), &value)
+    }
+
+    /// Delete the buckets older than the retention
+    fn expire(&self, resolution: Resolution, now: u64) -> Result<(), StorageError> {
+        let oldest = now.saturating_sub(resolution.retention());
+        let expired = self.kv.iterator(IteratorMode::From(&resolution.key(0), Direction::Forward))?
+            .filter_map(|(k, _)| k.ok())
+            .take_while(|key| *key < resolution.key(oldest))
+            .collect::<Vec<_>>();
+        for key in expired {
+            self.kv.delete(&key)?;
+        }
+        Ok(())
+    }
+
+    /// Points of the time series between `from` and `to` in seconds, both inclusive, oldest first
+    pub fn get_range(
+        &self,
+        resolution: Resolution,
+        from: u64,
+        to: u64,
+        grouping: Grouping,
+        filters: &TimeseriesFilters,
+    ) -> Result<Vec<Point>, StorageError> {
+        let (from, to) = (resolution.bucket(from), resolution.bucket(to));
+        let mut buckets = self.kv.iterator(IteratorMode::From(&resolution.key(from), Direction::Forward))?
+            .filter_map(|(k, v)| Some((k.ok()?, v.ok()?)))
+            .take_while(|(key, _)| *key <= resolution.key(to))
+            .map(|(key, value)| (key & !(0xff_u64 << 56), value.samples))
+            .collect::<BTreeMap<_, _>>();
+        // the buckets being filled are not stored yet
+        let pending = self.pending.lock().unwrap();
+        for (&(r, bucket), samples) in &pending.buckets {
+            if r == resolution && bucket >= from && bucket <= to {
+                buckets.entry(bucket).or_default()
+                    .extend(samples.iter().map(|(key, counters)| (key.clone(), *counters)));
+            }
+        }
+        drop(pending);
+
+        let points = buckets.into_iter()
+            .map(|(bucket, samples)| {
+                let mut groups = BTreeMap::<(Option<SocketAddr>, Option<bool>, Option<String>), Counters>::new();
+                for (key, counters) in samples.iter().filter(|(key, _)| filters.matches(key)) {
+                    let group = (
+                        if grouping.peer { Some(key.remote_addr) } else { None },
+                        if grouping.direction { Some(key.incoming) } else { None },
+                        if grouping.message_type { Some(key.message_type.clone()) } else { None },
+                    );
+                    groups.entry(group).or_default().add(counters);
+                }
+                Point {
+                    timestamp: bucket as u128 * NANOS,
+                    groups: groups.into_iter()
+                        .map(|((remote_addr, incoming, message_type), counters)| Group {
+                            remote_addr,
+                            incoming,
+                            message_type,
+                            bytes: counters.bytes,
+                            messages: counters.messages,
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+        Ok(points)
+    }
+}
+
+impl KeyValueSchema for TimeseriesStore {
+    type Key = u64;
+    type Value = TimeseriesBucket;
+
+    fn name() -> &'static str { "timeseries_storage" }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{open_temp_db, remove_temp_db};
+
+    fn key(remote_addr: &str, incoming: bool, message_type: &str) -> SampleKey {
+        SampleKey {
+            remote_addr: remote_addr.parse().unwrap(),
+            incoming,
+            message_type: message_type.to_string(),
+        }
+    }
+
+    fn record(store: &TimeseriesStore, seconds: u64, key: SampleKey, bytes: u64) {
+        store.record(seconds as u128 * NANOS + 1, key, Counters { bytes, messages: 1 });
+    }
+
+    // bytes of each group of each point
+    fn bytes(points: Vec<Point>) -> Vec<(u64, Vec<u64>)> {
+        points.into_iter()
+            .map(|point| ((point.timestamp / NANOS) as u64, point.groups.iter().map(|g| g.bytes).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn rollover() {
+        let db = open_temp_db("timeseries_rollover");
+        let store = TimeseriesStore::new(db.clone());
+        let a = key("51.15.220.7:9732", true, "current_head");
+        record(&store, 10, a.clone(), 100);
+        record(&store, 10, a.clone(), 50);
+        record(&store, 11, a.clone(), 10);
+        store.store_ended(11).unwrap();
+        // the first bucket is stored, the second is still pending, both are visible
+        assert_eq!(store.pending.lock().unwrap().buckets.len(), 3);
+        let points = store.get_range(Resolution::Second, 0, 20, Grouping::default(), &TimeseriesFilters::default()).unwrap();
+        assert_eq!(points[0].groups[0].messages, 2);
+        assert_eq!(bytes(points), vec![(10, vec![150]), (11, vec![10])]);
+
+        // a late message is merged with the stored bucket
+        record(&store, 10, a.clone(), 1);
+        store.flush().unwrap();
+        assert!(store.pending.lock().unwrap().buckets.is_empty());
+        let points = store.get_range(Resolution::Second, 0, 20, Grouping::default(), &TimeseriesFilters::default()).unwrap();
+        assert_eq!(bytes(points), vec![(10, vec![151]), (11, vec![10])]);
+        let points = store.get_range(Resolution::Minute, 0, 20, Grouping::default(), &TimeseriesFilters::default()).unwrap();
+        assert_eq!(bytes(points), vec![(0, vec![161])]);
+        remove_temp_db(db);
+    }
+
+    #[test]
+    fn grouping_and_filters() {
+        let db = open_temp_db("timeseries_grouping");
+        let store = TimeseriesStore::new(db.clone());
+        record(&store, 0, key("51.15.220.7:9732", true, "current_head"), 1);
+        record(&store, 0, key("51.15.220.7:9732", false, "current_head"), 2);
+        record(&store, 0, key("51.15.220.8:9732", true, "operation"), 4);
+
+        let range = |grouping, filters: &TimeseriesFilters| {
+            bytes(store.get_range(Resolution::Second, 0, 0, grouping, filters).unwrap())
+        };
+        let all = TimeseriesFilters::default();
+        assert_eq!(range(Grouping::default(), &all), vec![(0, vec![7])]);
+        assert_eq!(range(Grouping { peer: true, ..Grouping::default() }, &all), vec![(0, vec![3, 4])]);
+        assert_eq!(range(Grouping { direction: true, ..Grouping::default() }, &all), vec![(0, vec![2, 5])]);
+        assert_eq!(range(Grouping { message_type: true, ..Grouping::default() }, &all), vec![(0, vec![3, 4])]);
+
+        let incoming = TimeseriesFilters { incoming: Some(true), ..TimeseriesFilters::default() };
+        assert_eq!(range(Grouping::default(), &incoming), vec![(0, vec![5])]);
+        let peer = TimeseriesFilters { remote_addr: "51.15.220.7:9732".parse().ok(), ..TimeseriesFilters::default() };
+        assert_eq!(range(Grouping::default(), &peer), vec![(0, vec![3])]);
+        let operation = TimeseriesFilters { message_type: Some("operation".to_string()), ..TimeseriesFilters::default() };
+        assert_eq!(range(Grouping::default(), &operation), vec![(0, vec![4])]);
+        remove_temp_db(db);
+    }
+
+    #[test]
+    fn retention() {
+        let db = open_temp_db("timeseries_retention");
+        let store = TimeseriesStore::new(db.clone());
+        let a = key("51.15.220.7:9732", true, "current_head");
+        record(&store, 0, a.clone(), 1);
+        store.store_ended(1).unwrap();
+        record(&store, 4000, a.clone(), 2);
+        store.store_ended(4001).unwrap();
+
+        let range = |resolution| bytes(store.get_range(resolution, 0, 5000, Grouping::default(), &TimeseriesFilters::default()).unwrap());
+        // the seconds are kept for an hour, the minutes for a day
+        assert_eq!(range(Resolution::Second), vec![(4000, vec![2])]);
+        assert_eq!(range(Resolution::Minute), vec![(0, vec![1]), (3960, vec![2])]);
+        remove_temp_db(db);
+    }
+}
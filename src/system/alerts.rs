@@ -0,0 +1,420 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{collections::VecDeque, fs, path::Path, time::Duration};
+use serde::Deserialize;
+use regex::Regex;
+use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
+use tracing::{error, info};
+use tezos_messages::p2p::encoding::ack::AckMessage;
+use crate::{
+    messages::{
+        p2p_message::{P2pMessage, TezosPeerMessage, HandshakeMessage},
+        log_message::LogMessage,
+        log_parser::normalize_level,
+        alert_message::Alert,
+    },
+    storage::{MessageStore, get_ts, p2p_indexes::Type},
+};
+
+/// How often the rules waiting for a message are checked
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+#[derive(Debug, Clone, Deserialize)]
+/// Alerting configuration, the json file given by `ALERT_RULES`
+pub struct AlertConfig {
+    /// Every alert is posted as json to the url, if set
+    #[serde(default)]
+    pub webhook: Option<String>,
+    pub rules: Vec<RuleConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+/// Named condition
+pub struct RuleConfig {
+    pub name: String,
+    pub condition: Condition,
+    /// The rule raises at most one alert during the cooldown, seconds
+    #[serde(default = "RuleConfig::default_cooldown")]
+    pub cooldown: u64,
+}
+
+impl RuleConfig {
+    fn default_cooldown() -> u64 {
+        60
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+/// Condition evaluated over the captured messages and logs
+pub enum Condition {
+    /// More than `threshold` chunks failed to decrypt during `window` seconds
+    DecryptFailures { threshold: usize, window: u64 },
+    /// The remote peer sent nack
+    PeerNack,
+    /// No message of the type was received for `timeout` seconds,
+    /// the type is one of the names accepted by `/v2/p2p?types=`
+    NoMessage { message_type: String, timeout: u64 },
+    /// The log of the level (of any level if not set) has the message matching the regular expression
+    LogMatch {
+        #[serde(default)]
+        level: Option<String>,
+        pattern: String,
+    },
+}
+
+impl AlertConfig {
+    /// Load the configuration from the json file
+    pub fn load(path: &Path) -> Result<Self, failure::Error> {
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+}
+
+/// Compiled condition with its state
+enum Matcher {
+    DecryptFailures {
+        threshold: usize,
+        window: u128,
+        failures: VecDeque<u128>,
+    },
+    PeerNack,
+    NoMessage {
+        name: String,
+        message_type: u32,
+        timeout: u128,
+        last_seen: u128,
+        raised: bool,
+    },
+    LogMatch {
+        level: Option<&'static str>,
+        pattern: Regex,
+    },
+}
+
+struct Rule {
+    name: String,
+    cooldown: u128,
+    last_alert: Option<u128>,
+    matcher: Matcher,
+}
+
+impl Rule {
+    fn new(config: &RuleConfig, now: u128) -> Result<Self, failure::Error> {
+        let matcher = match config.condition {
+            Condition::DecryptFailures { threshold, window } => Matcher::DecryptFailures {
+                threshold,
+                window: window as u128 * NANOS_PER_SEC,
+                failures: VecDeque::new(),
+            },
+            Condition::PeerNack => Matcher::PeerNack,
+            Condition::NoMessage { ref message_type, timeout } => Matcher::NoMessage {
+                name: message_type.clone(),
+                message_type: message_type.parse::<Type>()? as u32,
+                timeout: timeout as u128 * NANOS_PER_SEC,
+                last_seen: now,
+                raised: false,
+            },
+            Condition::LogMatch { ref level, ref pattern } => Matcher::LogMatch {
+                level: match level {
+                    Some(level) => Some(normalize_level(level).ok_or_else(|| failure::format_err!("unknown log level {}", level))?),
+                    None => None,
+                },
+                pattern: Regex::new(pattern)?,
+            },
+        };
+        Ok(Rule {
+            name: config.name.clone(),
+            cooldown: config.cooldown as u128 * NANOS_PER_SEC,
+            last_alert: None,
+            matcher,
+        })
+    }
+
+    /// Create the alert, unless the rule is cooling down
+    fn raise(&mut self, now: u128, message: String, remote_addr: Option<String>) -> Option<Alert> {
+        match self.last_alert {
+            Some(last) if now < last + self.cooldown => None,
+            _ => {
+                self.last_alert = Some(now);
+                Some(Alert::new(now, &self.name, message, remote_addr))
+            },
+        }
+    }
+
+    fn on_p2p(&mut self, message: &P2pMessage) -> Option<Alert> {
+        let now = message.timestamp;
+        let remote_addr = Some(message.remote_addr.to_string());
+        match self.matcher {
+            Matcher::DecryptFailures { threshold, window, ref mut failures } => {
+                // the connection message is not encrypted, other messages have empty decrypted bytes if failed
+                if !message.decrypted_bytes.is_empty() || message.original_bytes.is_empty() {
+                    return None;
+                }
+                failures.push_back(now);
+                while failures.front().map(|first| *first + window < now).unwrap_or(false) {
+                    failures.pop_front();
+                }
+                if failures.len() > threshold {
+                    let text = format!("{} chunks failed to decrypt in {} seconds", failures.len(), window / NANOS_PER_SEC);
+                    self.raise(now, text, remote_addr)
+                } else {
+                    None
+                }
+            },
+            Matcher::PeerNack => match message.message.first() {
+                Some(TezosPeerMessage::HandshakeMessage(HandshakeMessage::AckMessage(ack)))
+                    if message.incoming && !matches!(ack, AckMessage::Ack) =>
+                {
+                    self.raise(now, format!("peer {} sent nack", message.remote_addr), remote_addr)
+                },
+                _ => None,
+            },
+            Matcher::NoMessage { message_type, ref mut last_seen, ref mut raised, .. } => {
+                if message.incoming && Type::extract(message) as u32 == message_type {
+                    *last_seen = now;
+                    *raised = false;
+                }
+                None
+            },
+            Matcher::LogMatch { .. } => None,
+        }
+    }
+
+    fn on_log(&mut self, log: &LogMessage) -> Option<Alert> {
+        match self.matcher {
+            Matcher::LogMatch { level, ref pattern } => {
+                let level_matches = level.map(|level| level == log.level).unwrap_or(true);
+                if level_matches && pattern.is_match(&log.message) {
+                    let text = format!("{}: {}", log.level, log.message);
+                    self.raise(log.date, text, log.addresses.first().cloned())
+                } else {
+                    None
+                }
+            },
+            _ => None,
+        }
+    }
+
+    fn on_tick(&mut self, now: u128) -> Option<Alert> {
+        let text = match self.matcher {
+            Matcher::NoMessage { ref name, timeout, last_seen, raised, .. } if !raised && now > last_seen + timeout => {
+                format!("no {} received for {} seconds", name, (now - last_seen) / NANOS_PER_SEC)
+            },
+            _ => return None,
+        };
+        let alert = self.raise(now, text, None);
+        if alert.is_some() {
+            if let Matcher::NoMessage { ref mut raised, .. } = self.matcher {
+                *raised = true;
+            }
+        }
+        alert
+    }
+}
+
+/// Evaluates the rules, the caller is responsible for delivering the alerts
+pub struct AlertEngine {
+    rules: Vec<Rule>,
+}
+
+impl AlertEngine {
+    /// Compile the rules, `now` is the start of waiting for the messages
+    pub fn new(config: &AlertConfig, now: u128) -> Result<Self, failure::Error> {
+        let rules = config.rules.iter()
+            .map(|rule| Rule::new(rule, now).map_err(|err| failure::format_err!("rule {}: {}", rule.name, err)))
+            .collect::<Result<_, _>>()?;
+        Ok(AlertEngine { rules })
+    }
+
+    pub fn on_p2p(&mut self, message: &P2pMessage) -> Vec<Alert> {
+        self.rules.iter_mut().filter_map(|rule| rule.on_p2p(message)).collect()
+    }
+
+    pub fn on_log(&mut self, log: &LogMessage) -> Vec<Alert> {
+        self.rules.iter_mut().filter_map(|rule| rule.on_log(log)).collect()
+    }
+
+    pub fn on_tick(&mut self, now: u128) -> Vec<Alert> {
+        self.rules.iter_mut().filter_map(|rule| rule.on_tick(now)).collect()
+    }
+}
+
+/// Post the alert as json to the webhook
+pub async fn send_webhook(url: &str, alert: &Alert) -> Result<(), failure::Error> {
+    let request = hyper::Request::post(url)
+        .header("Content-Type", "application/json")
+        .body(hyper::Body::from(serde_json::to_vec(alert)?))?;
+    let response = hyper::Client::new().request(request).await?;
+    if !response.status().is_success() {
+        return Err(failure::format_err!("webhook responded with status {}", response.status()));
+    }
+    Ok(())
+}
+
+enum Input {
+    P2p(P2pMessage),
+    Log(LogMessage),
+}
+
+#[derive(Clone, Default)]
+/// Handle of the alerting task, does nothing if alerting is not configured
+pub struct Alerts {
+    sender: Option<UnboundedSender<Input>>,
+}
+
+impl Alerts {
+    /// Alerting is not configured
+    pub fn disabled() -> Self {
+        Alerts::default()
+    }
+
+    /// Spawn a (green)thread evaluating the rules, the alerts are stored and posted to the webhook
+    pub fn spawn(storage: MessageStore, config: AlertConfig) -> Result<Self, failure::Error> {
+        let mut engine = AlertEngine::new(&config, get_ts())?;
+        let webhook = config.webhook;
+        let (sender, mut receiver) = unbounded_channel();
+        info!(rules = config.rules.len(), "started alerting");
+        tokio::spawn(async move {
+            let mut tick = tokio::time::interval(TICK_INTERVAL);
+            loop {
+                let alerts = tokio::select! {
+                    input = receiver.recv() => match input {
+                        Some(Input::P2p(message)) => engine.on_p2p(&message),
+                        Some(Input::Log(log)) => engine.on_log(&log),
+                        None => break,
+                    },
+                    _ = tick.tick() => engine.on_tick(get_ts()),
+                };
+                for mut alert in alerts {
+                    info!(rule = tracing::field::display(&alert.rule), message = tracing::field::display(&alert.message), "alert raised");
+                    if let Err(err) = storage.alert().store_alert(&mut alert) {
+                        error!(error = tracing::field::display(&err), "failed to store alert");
+                    }
+                    if let Some(ref url) = webhook {
+                        let url = url.clone();
+                        tokio::spawn(async move {
+                            if let Err(err) = send_webhook(&url, &alert).await {
+                                error!(error = tracing::field::display(&err), "failed to send alert to the webhook");
+                            }
+                        });
+                    }
+                }
+            }
+        });
+        Ok(Alerts { sender: Some(sender) })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.sender.is_some()
+    }
+
+    /// Evaluate the rules for the captured message
+    pub fn p2p(&self, message: &P2pMessage) {
+        if let Some(ref sender) = self.sender {
+            let _ = sender.send(Input::P2p(message.clone()));
+        }
+    }
+
+    /// Evaluate the rules for the captured log
+    pub fn log(&self, log: &LogMessage) {
+        if let Some(ref sender) = self.sender {
+            let _ = sender.send(Input::Log(log.clone()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, sync::{Arc, Mutex}};
+    use warp::Filter;
+    use super::*;
+    use crate::messages::p2p_message::SourceType;
+
+    const RULES: &str = r#"{
+        "webhook": "http://localhost:8080/alerts",
+        "rules": [
+            { "name": "decrypt", "condition": { "type": "decrypt_failures", "threshold": 2, "window": 60 } },
+            { "name": "nack", "condition": { "type": "peer_nack" }, "cooldown": 0 },
+            { "name": "head", "condition": { "type": "no_message", "message_type": "current_head", "timeout": 60 } },
+            { "name": "errors", "condition": { "type": "log_match", "level": "error", "pattern": "^Failed to apply" } }
+        ]
+    }"#;
+
+    fn message(timestamp: u128, decrypted: bool, message: Option<TezosPeerMessage>) -> P2pMessage {
+        let mut message = P2pMessage::new(
+            "51.15.220.7:9732".parse().unwrap(),
+            true,
+            SourceType::Remote,
+            vec![0; 32],
+            if decrypted { vec![0; 16] } else { vec![] },
+            message.ok_or_else(|| "cannot decrypt".to_string()),
+        );
+        message.timestamp = timestamp;
+        message
+    }
+
+    #[test]
+    fn rules() {
+        let config: AlertConfig = serde_json::from_str(RULES).unwrap();
+        let mut engine = AlertEngine::new(&config, 0).unwrap();
+
+        // third failure within the window
+        assert!(engine.on_p2p(&message(1, false, None)).is_empty());
+        assert!(engine.on_p2p(&message(2, false, None)).is_empty());
+        let alerts = engine.on_p2p(&message(3, false, None));
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].rule, "decrypt");
+        assert_eq!(alerts[0].remote_addr.as_deref(), Some("51.15.220.7:9732"));
+        // cooling down
+        assert!(engine.on_p2p(&message(4, false, None)).is_empty());
+
+        let nack = TezosPeerMessage::HandshakeMessage(HandshakeMessage::AckMessage(AckMessage::NackV0));
+        assert_eq!(engine.on_p2p(&message(5, true, Some(nack.clone())))[0].rule, "nack");
+        assert_eq!(engine.on_p2p(&message(6, true, Some(nack)))[0].rule, "nack");
+
+        let error = LogMessage::new(7, "error", "", "Failed to apply block: storage error", BTreeMap::new());
+        assert_eq!(engine.on_log(&error)[0].rule, "errors");
+        let warning = LogMessage::new(8, "warning", "", "Failed to apply block: storage error", BTreeMap::new());
+        assert!(engine.on_log(&warning).is_empty());
+
+        assert!(engine.on_tick(60 * NANOS_PER_SEC).is_empty());
+        let alerts = engine.on_tick(61 * NANOS_PER_SEC);
+        assert_eq!(alerts[0].rule, "head");
+        assert_eq!(alerts[0].message, "no current_head received for 61 seconds");
+        // raised once for the silence
+        assert!(engine.on_tick(200 * NANOS_PER_SEC).is_empty());
+    }
+
+    #[test]
+    fn invalid_rule() {
+        let config = r#"{ "rules": [ { "name": "bad", "condition": { "type": "log_match", "pattern": "(" } } ] }"#;
+        let config: AlertConfig = serde_json::from_str(config).unwrap();
+        assert!(AlertEngine::new(&config, 0).is_err());
+    }
+
+    #[tokio::test]
+    async fn webhook() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let route = {
+            let received = received.clone();
+            warp::post()
+                .and(warp::path!("alerts"))
+                .and(warp::body::json())
+                .map(move |alert: Alert| {
+                    received.lock().unwrap().push(alert);
+                    "ok"
+                })
+        };
+        let (address, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let alert = Alert::new(1, "nack", "peer sent nack".to_string(), None);
+        send_webhook(&format!("http://{}/alerts", address), &alert).await.unwrap();
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].rule, "nack");
+        assert_eq!(received[0].message, "peer sent nack");
+    }
+}
@@ -0,0 +1,274 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
+use serde::Serialize;
+use tezos_messages::p2p::{
+    binary_message::BinaryMessage,
+    encoding::block_header::BlockHeader,
+};
+use crypto::{hash::HashType, blake2b};
+use crate::messages::p2p_message::{P2pMessage, TezosPeerMessage, FullPeerMessage};
+
+/// How many changes of the node's head are remembered
+const MAX_NODE_HISTORY: usize = 0x100;
+/// How many levels of the node's head are remembered to detect forks
+const MAX_NODE_LEVELS: usize = 0x1000;
+/// Heads older than this (in nanoseconds) are forgotten, the peer announces each new head,
+/// so it is likely disconnected
+const PEER_HEAD_TTL: u128 = 10 * 60 * 1_000_000_000;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+/// Head of the chain announced in `CurrentHead`, `CurrentBranch` or received in `BlockHeader`
+pub struct Head {
+    pub level: i32,
+    pub hash: String,
+    pub fitness: Vec<String>,
+    // when the head was captured
+    pub timestamp: u128,
+}
+
+/// Base58 encoded hash of the block header
+pub(crate) fn block_hash(header: &BlockHeader) -> Option<String> {
+    let hash = blake2b::digest_256(&header.as_bytes().ok()?);
+    Some(HashType::BlockHash.hash_to_b58check(&hash))
+}
+
+impl Head {
+    fn from_header(header: &BlockHeader, timestamp: u128) -> Option<Self> {
+        Some(Head {
+            level: header.level(),
+            hash: block_hash(header)?,
+            fitness: header.fitness().iter().map(hex::encode).collect(),
+            timestamp,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+/// The latest head announced by the peer, compared with the node's head
+pub struct PeerHead {
+    pub remote_addr: SocketAddr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer_id: Option<String>,
+    pub head: Head,
+    // the node's level minus the peer's level, positive if the peer is behind
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lag: Option<i64>,
+    // the node announced another block at the same level
+    pub forked: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+/// Overview of the chain synchronization
+pub struct ChainHeads {
+    // the latest head announced by the node
+    pub node: Option<Head>,
+    // the highest header the node received
+    pub fetched: Option<Head>,
+    // the highest head announced by any peer
+    pub best_peer: Option<PeerHead>,
+    // level of the best peer minus the node's level, positive if the node is behind
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lag: Option<i64>,
+    pub forked_peers: usize,
+    // changes of the node's head, newest first
+    pub node_history: Vec<Head>,
+}
+
+#[derive(Default)]
+struct ChainState {
+    node: Option<Head>,
+    node_history: VecDeque<Head>,
+    // hashes of the node's heads by the level
+    node_hashes: BTreeMap<i32, String>,
+    fetched: Option<Head>,
+    peers: HashMap<SocketAddr, Head>,
+}
+
+impl ChainState {
+    fn update(&mut self, message: &P2pMessage) {
+        let header = match message.message.first() {
+            Some(TezosPeerMessage::PeerMessage(FullPeerMessage::CurrentHead(m))) => m.current_block_header(),
+            Some(TezosPeerMessage::PeerMessage(FullPeerMessage::CurrentBranch(m))) => m.current_branch().current_head(),
+            Some(TezosPeerMessage::PeerMessage(FullPeerMessage::BlockHeader(m))) if message.incoming => {
+                if let Some(head) = Head::from_header(m.block_header(), message.timestamp) {
+                    self.update_fetched(head);
+                }
+                return;
+            },
+            _ => return,
+        };
+        if let Some(head) = Head::from_header(header, message.timestamp) {
+            if message.incoming {
+                self.update_peer(message.remote_addr, head);
+            } else {
+                self.update_node(head);
+            }
+        }
+    }
+
+    fn update_peer(&mut self, remote_addr: SocketAddr, head: Head) {
+        let now = head.timestamp;
+        self.peers.insert(remote_addr, head);
+        self.peers.retain(|_, head| head.timestamp.saturating_add(PEER_HEAD_TTL) >= now);
+    }
+
+    /// Forget the heads of the peers whose connection is closed
+    fn retain_working(&mut self, peer_ids: &HashMap<String, String>) {
+        self.peers.retain(|remote_addr, _| peer_ids.contains_key(&remote_addr.to_string()));
+    }
+
+    fn update_node(&mut self, head: Head) {
+        // the node announces its head to every peer, remember only the change
+        if self.node.as_ref().map(|node| node.hash != head.hash).unwrap_or(true) {
+            self.node_hashes.insert(head.level, head.hash.clone());
+            while self.node_hashes.len() > MAX_NODE_LEVELS {
+                let lowest = *self.node_hashes.keys().next().unwrap();
+                self.node_hashes.remove(&lowest);
+            }
+            self.node_history.push_front(head.clone());
+            self.node_history.truncate(MAX_NODE_HISTORY);
+        }
+        self.node = Some(head);
+    }
+
+    fn update_fetched(&mut self, head: Head) {
+        if self.fetched.as_ref().map(|fetched| fetched.level < head.level).unwrap_or(true) {
+            self.fetched = Some(head);
+        }
+    }
+
+    fn peer(&self, remote_addr: SocketAddr, head: &Head, peer_ids: &HashMap<String, String>) -> PeerHead {
+        PeerHead {
+            remote_addr,
+            peer_id: peer_ids.get(&remote_addr.to_string()).cloned(),
+            head: head.clone(),
+            lag: self.node.as_ref().map(|node| node.level as i64 - head.level as i64),
+            forked: self.node_hashes.get(&head.level).map(|hash| hash != &head.hash).unwrap_or(false),
+        }
+    }
+
+    fn peers(&self, peer_ids: &HashMap<String, String>) -> Vec<PeerHead> {
+        let mut peers = self.peers.iter()
+            .map(|(remote_addr, head)| self.peer(*remote_addr, head, peer_ids))
+            .collect::<Vec<_>>();
+        peers.sort_by(|a, b| b.head.level.cmp(&a.head.level).then(a.remote_addr.cmp(&b.remote_addr)));
+        peers
+    }
+
+    fn heads(&self, peer_ids: &HashMap<String, String>) -> ChainHeads {
+        let peers = self.peers(peer_ids);
+        let forked_peers = peers.iter().filter(|peer| peer.forked).count();
+        let best_peer = peers.into_iter().next();
+        ChainHeads {
+            lag: match (&self.node, &best_peer) {
+                (Some(node), Some(best)) => Some(best.head.level as i64 - node.level as i64),
+                _ => None,
+            },
+            node: self.node.clone(),
+            fetched: self.fetched.clone(),
+            best_peer,
+            forked_peers,
+            node_history: self.node_history.iter().cloned().collect(),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+/// Heads of the node and of the peers derived from the captured messages, shared with the endpoints
+pub struct ChainSnapshot {
+    state: Arc<RwLock<ChainState>>,
+}
+
+impl ChainSnapshot {
+    /// Track the heads in the captured message
+    pub fn update(&self, message: &P2pMessage) {
+        self.state.write().unwrap().update(message)
+    }
+
+    /// Overview, `peer_ids` maps the remote address to the peer id of the working connection,
+    /// the heads of other peers are dropped
+    pub fn heads(&self, peer_ids: &HashMap<String, String>) -> ChainHeads {
+        let mut state = self.state.write().unwrap();
+        state.retain_working(peer_ids);
+        state.heads(peer_ids)
+    }
+
+    /// Heads of the peers of the working connections, the highest first
+    pub fn peers(&self, peer_ids: &HashMap<String, String>) -> Vec<PeerHead> {
+        let mut state = self.state.write().unwrap();
+        state.retain_working(peer_ids);
+        state.peers(peer_ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn head(level: i32, hash: &str) -> Head {
+        Head {
+            level,
+            hash: hash.to_string(),
+            fitness: vec!["01".to_string()],
+            timestamp: 0,
+        }
+    }
+
+    #[test]
+    fn lag_and_fork() {
+        let mut state = ChainState::default();
+        let a: SocketAddr = "51.15.220.7:9732".parse().unwrap();
+        let b: SocketAddr = "51.15.220.8:9732".parse().unwrap();
+        state.update_node(head(10, "BLa"));
+        state.update_node(head(10, "BLa"));
+        state.update_node(head(11, "BLb"));
+        state.update_peer(a, head(13, "BLc"));
+        state.update_peer(b, head(10, "BLx"));
+
+        let mut peer_ids = HashMap::new();
+        peer_ids.insert(a.to_string(), "idtJunqYgD1M6r6o2qvGpiD5xKZWRu".to_string());
+        let heads = state.heads(&peer_ids);
+        assert_eq!(heads.node.unwrap().level, 11);
+        assert_eq!(heads.lag, Some(2));
+        assert_eq!(heads.forked_peers, 1);
+        assert_eq!(heads.node_history.len(), 2);
+        let best = heads.best_peer.unwrap();
+        assert_eq!(best.peer_id.as_deref(), Some("idtJunqYgD1M6r6o2qvGpiD5xKZWRu"));
+
+        let peers = state.peers(&peer_ids);
+        assert_eq!(peers[1].remote_addr, b);
+        assert_eq!(peers[1].lag, Some(1));
+        assert!(peers[1].forked);
+        assert!(!peers[0].forked);
+    }
+
+    #[test]
+    fn stale_peers() {
+        let mut state = ChainState::default();
+        let a: SocketAddr = "51.15.220.7:9732".parse().unwrap();
+        let b: SocketAddr = "51.15.220.8:9732".parse().unwrap();
+        let c: SocketAddr = "51.15.220.9:9732".parse().unwrap();
+        state.update_node(head(10, "BLa"));
+        state.update_peer(a, head(20, "BLc"));
+        // `a` announced nothing for too long
+        state.update_peer(b, Head { timestamp: PEER_HEAD_TTL + 1, ..head(12, "BLd") });
+        state.update_peer(c, Head { timestamp: PEER_HEAD_TTL + 1, ..head(11, "BLe") });
+        let peers = state.peers(&HashMap::new());
+        assert_eq!(peers.iter().map(|peer| peer.remote_addr).collect::<Vec<_>>(), vec![b, c]);
+
+        // the connection of `b` is closed
+        let mut peer_ids = HashMap::new();
+        peer_ids.insert(c.to_string(), "idtJunqYgD1M6r6o2qvGpiD5xKZWRu".to_string());
+        state.retain_working(&peer_ids);
+        let heads = state.heads(&peer_ids);
+        assert_eq!(heads.best_peer.unwrap().remote_addr, c);
+        assert_eq!(heads.lag, Some(1));
+        assert_eq!(heads.forked_peers, 0);
+    }
+}
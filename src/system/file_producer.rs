@@ -0,0 +1,302 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{
+    io::SeekFrom,
+    os::unix::fs::MetadataExt,
+    path::PathBuf,
+    time::Duration,
+};
+use tracing::{error, info, warn};
+use tokio::{
+    io::{self, AsyncBufReadExt, AsyncSeekExt, BufReader},
+    fs::File,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use crate::system::SystemSettings;
+use crate::messages::{log_message::LogMessage, log_parser::{LogParserRegistry, line_date}};
+use crate::storage::get_ts;
+
+/// How long to wait for new data at the end of file, or for the file to appear
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Format of the lines in the tailed file
+pub enum FileFormat {
+    /// Lines written by the node as is
+    Plain,
+    /// Docker json-file logging driver, each line is a json object
+    DockerJson,
+}
+
+#[derive(Deserialize)]
+/// Line of the Docker json-file log
+struct DockerLine {
+    log: String,
+    time: DateTime<Utc>,
+}
+
+/// Spawn new file tailing task, for processing logs written into the file.
+/// The file might not exist yet, might be truncated or rotated.
+pub fn file_producer(settings: SystemSettings, path: PathBuf, format: FileFormat) {
+    info!(path = tracing::field::debug(&path), format = tracing::field::debug(&format), "started tailing log file");
+    tokio::spawn(async move {
+        let mut tail = Tail::new(path.clone());
+        loop {
+            let line = match tail.next_line().await {
+                Ok(line) => line,
+                Err(err) => {
+                    warn!(path = tracing::field::debug(&path), error = tracing::field::display(&err), "failed to read log file");
+                    tail.reset();
+                    tokio::time::delay_for(POLL_INTERVAL).await;
+                    continue;
+                },
+            };
+            if let Some(mut log_msg) = parse_line(&settings.log_parsers, &line, format) {
+                if let Err(err) = settings.storage.log().store_message(&mut log_msg) {
+                    error!(error = tracing::field::display(&err), "failed to store log");
+                }
+                settings.alerts.log(&log_msg);
+            }
+        }
+    });
+}
+
+/// Parse the line according to the file format, returns `None` if the line should be skipped
+fn parse_line(log_parsers: &LogParserRegistry, line: &str, format: FileFormat) -> Option<LogMessage> {
+    match format {
+        FileFormat::Plain => {
+            let line = line.trim_end();
+            if line.is_empty() {
+                None
+            } else {
+                // the line might be written long before it is read, like the lines of the rotated file
+                let captured = get_ts();
+                Some(log_parsers.parse(line_date(line, captured).unwrap_or(captured), line))
+            }
+        },
+        FileFormat::DockerJson => match serde_json::from_str::<DockerLine>(line) {
+            Ok(DockerLine { log, time }) => {
+                let log = log.trim_end();
+                if log.is_empty() {
+                    None
+                } else {
+                    Some(log_parsers.parse(time.timestamp_nanos() as u128, log))
+                }
+            },
+            Err(err) => {
+                warn!(error = tracing::field::display(&err), "failed to parse docker log line");
+                Some(LogMessage::raw(line.trim_end().to_string()))
+            },
+        },
+    }
+}
+
+/// Follows the file like `tail -F`
+struct Tail {
+    path: PathBuf,
+    reader: Option<BufReader<File>>,
+    // inode of the opened file, if the file at the path has another inode, the file was rotated
+    inode: u64,
+    position: u64,
+    // do not read the data which was in the file before the debugger started
+    skip_existing: bool,
+    // incomplete line, the rest is not written yet
+    buffer: Vec<u8>,
+}
+
+impl Tail {
+    fn new(path: PathBuf) -> Self {
+        Tail {
+            path,
+            reader: None,
+            inode: 0,
+            position: 0,
+            skip_existing: true,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Forget the opened file, it will be opened again after the last complete line
+    fn reset(&mut self) {
+        self.reader = None;
+        self.position -= self.buffer.len() as u64;
+        self.buffer.clear();
+    }
+
+    /// Open the file at the path, continue at the position if it is the file which was opened before
+    async fn open(&self) -> io::Result<(BufReader<File>, u64, u64)> {
+        let mut file = File::open(&self.path).await?;
+        let metadata = file.metadata().await?;
+        let inode = metadata.ino();
+        let position = if self.skip_existing {
+            file.seek(SeekFrom::End(0)).await?
+        } else if inode == self.inode && metadata.len() >= self.position {
+            file.seek(SeekFrom::Start(self.position)).await?
+        } else {
+            0
+        };
+        Ok((BufReader::new(file), inode, position))
+    }
+
+    /// Check whether the file at the path was replaced by the new one or truncated
+    async fn rotated(&self) -> bool {
+        match tokio::fs::metadata(&self.path).await {
+            Ok(metadata) => metadata.ino() != self.inode || metadata.len() < self.position,
+            // the file was moved away, but the new one is not created yet
+            Err(_) => false,
+        }
+    }
+
+    /// Wait for the next complete line
+    async fn next_line(&mut self) -> io::Result<String> {
+        loop {
+            let reader = match self.reader {
+                Some(ref mut reader) => reader,
+                None => {
+                    match self.open().await {
+                        Ok((reader, inode, position)) => {
+                            self.inode = inode;
+                            self.position = position;
+                            self.reader = Some(reader);
+                        },
+                        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                            tokio::time::delay_for(POLL_INTERVAL).await;
+                        },
+                        Err(err) => return Err(err),
+                    }
+                    // the file which appears later, or the rotated file, should be read from the beginning
+                    self.skip_existing = false;
+                    continue;
+                },
+            };
+
+            // the invalid utf-8 is replaced, it must not fail the reading
+            let read = reader.read_until(b'\n', &mut self.buffer).await?;
+            self.position += read as u64;
+            if self.buffer.ends_with(b"\n") {
+                let line = String::from_utf8_lossy(&self.buffer).into_owned();
+                self.buffer.clear();
+                return Ok(line);
+            }
+            if read == 0 {
+                if self.rotated().await {
+                    info!(path = tracing::field::debug(&self.path), "log file rotated");
+                    // the rest of the old file is already read, keep an incomplete line as is
+                    let line = std::mem::replace(&mut self.buffer, Vec::new());
+                    self.reader = None;
+                    self.position = 0;
+                    if !line.is_empty() {
+                        return Ok(String::from_utf8_lossy(&line).into_owned());
+                    }
+                } else {
+                    tokio::time::delay_for(POLL_INTERVAL).await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path, time::Duration};
+    use tokio::{io::AsyncWriteExt, sync::mpsc, time::{self, Instant}};
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("debugger_tail_{}_{}", std::process::id(), get_ts()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(name)
+    }
+
+    async fn append(path: &Path, data: &str) {
+        let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(path).await.unwrap();
+        file.write_all(data.as_bytes()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn tail_with_rotation() {
+        let path = temp_path("node.log");
+        append(&path, "existing line\n").await;
+
+        let mut tail = Tail::new(path.clone());
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while sender.send(tail.next_line().await.unwrap()).is_ok() {}
+        });
+        let deadline = Instant::now() + Duration::from_secs(10);
+
+        // the existing data is skipped when the file is opened, probe until the appended line arrives
+        loop {
+            append(&path, "probe\n").await;
+            match time::timeout(POLL_INTERVAL * 2, receiver.recv()).await {
+                Ok(line) => {
+                    assert_eq!(line.as_deref(), Some("probe\n"));
+                    break;
+                },
+                Err(_) => assert!(Instant::now() < deadline, "the file is not opened"),
+            }
+        }
+
+        append(&path, "first\nsec").await;
+        append(&path, "ond\n").await;
+        // the rest of the old file is read before the new one is opened
+        fs::rename(&path, path.with_extension("log.1")).unwrap();
+        append(&path, "after rotation\n").await;
+
+        let mut lines = Vec::new();
+        while lines.len() < 3 {
+            match time::timeout_at(deadline, receiver.recv()).await {
+                // the late answers to the probes
+                Ok(Some(line)) if line == "probe\n" => (),
+                Ok(Some(line)) => lines.push(line),
+                _ => panic!("the lines are missing, got {:?}", lines),
+            }
+        }
+        assert_eq!(lines, vec!["first\n", "second\n", "after rotation\n"]);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn invalid_utf8_and_reset() {
+        let path = temp_path("node.log");
+        fs::write(&path, b"first \xff\nsecond\n").unwrap();
+
+        let mut tail = Tail { skip_existing: false, ..Tail::new(path.clone()) };
+        assert_eq!(tail.next_line().await.unwrap(), "first \u{fffd}\n");
+        // the file is opened again after the error, but the lines are not read twice
+        tail.reset();
+        assert_eq!(tail.next_line().await.unwrap(), "second\n");
+        append(&path, "third\n").await;
+        tail.reset();
+        assert_eq!(tail.next_line().await.unwrap(), "third\n");
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn parse_plain_line() {
+        let log_parsers = LogParserRegistry::default();
+        let line = "Jun 24 08:32:37.026 INFO Blacklisting IP because peer failed at bootstrap process\n";
+        let log = parse_line(&log_parsers, line, FileFormat::Plain).unwrap();
+        // the date of the line, not when it was read, the year is guessed
+        assert_eq!(log.date % (24 * 60 * 60 * 1_000_000_000), 30757026000000);
+        assert!(log.date < get_ts() + 24 * 60 * 60 * 1_000_000_000);
+        let log = parse_line(&log_parsers, "no date\n", FileFormat::Plain).unwrap();
+        assert!(log.date > get_ts() - 1_000_000_000);
+        assert!(parse_line(&log_parsers, "\n", FileFormat::Plain).is_none());
+    }
+
+    #[test]
+    fn parse_docker_line() {
+        let line = r#"{"log":"Jun 24 08:32:37.026 INFO Blacklisting IP because peer failed at bootstrap process\n","stream":"stdout","time":"2020-06-24T08:32:37.026683Z"}"#;
+        let log_parsers = LogParserRegistry::default();
+        let log = parse_line(&log_parsers, line, FileFormat::DockerJson).unwrap();
+        assert_eq!(log.level, "info");
+        assert_eq!(log.message, "Blacklisting IP because peer failed at bootstrap process");
+        assert_eq!(log.date, 1592987557026683000);
+
+        let line = r#"{"log":"\n","stream":"stdout","time":"2020-06-24T08:32:37.026683Z"}"#;
+        assert!(parse_line(&log_parsers, line, FileFormat::DockerJson).is_none());
+    }
+}
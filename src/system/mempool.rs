@@ -0,0 +1,251 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
+use serde::Serialize;
+use tezos_messages::p2p::binary_message::BinaryMessage;
+use crypto::{hash::HashType, blake2b};
+use crate::messages::p2p_message::{P2pMessage, TezosPeerMessage, FullPeerMessage};
+
+/// How many recent operations are observed
+const MAX_OPERATIONS: usize = 0x2000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// How the operation was gossiped
+pub enum Gossip {
+    /// The whole operation in `Operation` message
+    Operation,
+    /// Hash in the `known_valid` list of the mempool in `CurrentHead`
+    KnownValid,
+    /// Hash in the `pending` list of the mempool in `CurrentHead`
+    Pending,
+}
+
+#[derive(Debug, Clone, Serialize)]
+/// The peer sent the operation, or announced it in its mempool
+pub struct PeerGossip {
+    pub remote_addr: SocketAddr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer_id: Option<String>,
+    pub gossip: Gossip,
+    pub timestamp: u128,
+}
+
+#[derive(Debug, Clone, Serialize)]
+/// Everything observed about the single operation, newest first
+pub struct MempoolOperation {
+    pub hash: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    pub first_seen: u128,
+    // every gossip of every peer in order, includes the transitions between `pending` and `known_valid`
+    pub peers: Vec<PeerGossip>,
+    // the node asked a peer for the operation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requested: Option<u128>,
+    // the node sent the operation, or announced it in its own mempool
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forwarded: Option<u128>,
+}
+
+#[derive(Default, Debug, Clone)]
+/// Filters of the mempool view
+pub struct MempoolFilters {
+    pub operation_hash: Option<String>,
+    pub remote_addr: Option<SocketAddr>,
+    pub peer_id: Option<String>,
+}
+
+struct Operation {
+    branch: Option<String>,
+    first_seen: u128,
+    gossips: Vec<(SocketAddr, Gossip, u128)>,
+    requested: Option<u128>,
+    forwarded: Option<u128>,
+}
+
+impl Operation {
+    fn new(first_seen: u128) -> Self {
+        Operation {
+            branch: None,
+            first_seen,
+            gossips: Vec::new(),
+            requested: None,
+            forwarded: None,
+        }
+    }
+
+    /// Remember the gossip if it is the first one of the peer, or the peer changed its mind
+    fn gossip(&mut self, remote_addr: SocketAddr, gossip: Gossip, timestamp: u128) {
+        let last = self.gossips.iter().rev()
+            .find(|&&(addr, g, _)| addr == remote_addr && g != Gossip::Operation)
+            .map(|&(_, g, _)| g);
+        let known = match gossip {
+            Gossip::Operation => self.gossips.iter().any(|&(addr, g, _)| addr == remote_addr && g == Gossip::Operation),
+            _ => last == Some(gossip),
+        };
+        if !known {
+            self.gossips.push((remote_addr, gossip, timestamp));
+        }
+    }
+}
+
+#[derive(Default)]
+struct MempoolState {
+    operations: HashMap<String, Operation>,
+    // hashes in the order of the first appearance, to forget the oldest operations
+    order: VecDeque<String>,
+}
+
+impl MempoolState {
+    fn update(&mut self, message: &P2pMessage) {
+        let timestamp = message.timestamp;
+        let incoming = message.incoming;
+        let remote_addr = message.remote_addr;
+        let op_hash = |hash: &Vec<u8>| HashType::OperationHash.hash_to_b58check(hash);
+        match message.message.first() {
+            Some(TezosPeerMessage::PeerMessage(FullPeerMessage::CurrentHead(m))) => {
+                let mempool = m.current_mempool();
+                let lists = [(Gossip::KnownValid, mempool.known_valid()), (Gossip::Pending, mempool.pending())];
+                for &(gossip, hashes) in lists.iter() {
+                    for hash in hashes {
+                        self.observed(op_hash(hash), incoming, remote_addr, gossip, timestamp);
+                    }
+                }
+            },
+            Some(TezosPeerMessage::PeerMessage(FullPeerMessage::Operation(m))) => {
+                let operation = m.operation();
+                if let Ok(bytes) = operation.as_bytes() {
+                    let hash = op_hash(&blake2b::digest_256(&bytes));
+                    self.observed(hash.clone(), incoming, remote_addr, Gossip::Operation, timestamp);
+                    self.operation(hash, timestamp).branch
+                        .get_or_insert_with(|| HashType::BlockHash.hash_to_b58check(operation.branch()));
+                }
+            },
+            Some(TezosPeerMessage::PeerMessage(FullPeerMessage::GetOperations(m))) if !incoming => {
+                for hash in m.get_operations() {
+                    self.operation(op_hash(hash), timestamp).requested.get_or_insert(timestamp);
+                }
+            },
+            _ => (),
+        }
+    }
+
+    fn operation(&mut self, hash: String, timestamp: u128) -> &mut Operation {
+        if !self.operations.contains_key(&hash) {
+            self.order.push_back(hash.clone());
+            while self.order.len() > MAX_OPERATIONS {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.operations.remove(&oldest);
+                }
+            }
+        }
+        self.operations.entry(hash).or_insert_with(|| Operation::new(timestamp))
+    }
+
+    fn observed(&mut self, hash: String, incoming: bool, remote_addr: SocketAddr, gossip: Gossip, timestamp: u128) {
+        let operation = self.operation(hash, timestamp);
+        if incoming {
+            operation.gossip(remote_addr, gossip, timestamp);
+        } else {
+            operation.forwarded.get_or_insert(timestamp);
+        }
+    }
+
+    fn view(&self, hash: &str, operation: &Operation, peer_ids: &HashMap<String, String>) -> MempoolOperation {
+        MempoolOperation {
+            hash: hash.to_string(),
+            branch: operation.branch.clone(),
+            first_seen: operation.first_seen,
+            peers: operation.gossips.iter()
+                .map(|&(remote_addr, gossip, timestamp)| PeerGossip {
+                    remote_addr,
+                    peer_id: peer_ids.get(&remote_addr.to_string()).cloned(),
+                    gossip,
+                    timestamp,
+                })
+                .collect(),
+            requested: operation.requested,
+            forwarded: operation.forwarded,
+        }
+    }
+
+    fn operations(&self, limit: usize, filters: &MempoolFilters, peer_ids: &HashMap<String, String>) -> Vec<MempoolOperation> {
+        self.order.iter().rev()
+            .filter(|hash| filters.operation_hash.as_ref().map(|h| h == *hash).unwrap_or(true))
+            .filter_map(|hash| Some(self.view(hash, self.operations.get(hash)?, peer_ids)))
+            .filter(|operation| {
+                operation.peers.iter().any(|peer| {
+                    filters.remote_addr.map(|addr| addr == peer.remote_addr).unwrap_or(true)
+                        && filters.peer_id.as_ref().map(|id| peer.peer_id.as_ref() == Some(id)).unwrap_or(true)
+                }) || (filters.remote_addr.is_none() && filters.peer_id.is_none())
+            })
+            .take(limit)
+            .collect()
+    }
+}
+
+#[derive(Clone, Default)]
+/// Gossiped operations derived from the captured messages, shared with the endpoints
+pub struct MempoolSnapshot {
+    state: Arc<RwLock<MempoolState>>,
+}
+
+impl MempoolSnapshot {
+    /// Observe the operations in the captured message
+    pub fn update(&self, message: &P2pMessage) {
+        self.state.write().unwrap().update(message)
+    }
+
+    /// The recent operations matching the filters, newest first,
+    /// `peer_ids` maps the remote address to the peer id of the working connection
+    pub fn operations(&self, limit: usize, filters: &MempoolFilters, peer_ids: &HashMap<String, String>) -> Vec<MempoolOperation> {
+        self.state.read().unwrap().operations(limit, filters, peer_ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gossip() {
+        let mut state = MempoolState::default();
+        let a: SocketAddr = "51.15.220.7:9732".parse().unwrap();
+        let b: SocketAddr = "51.15.220.8:9732".parse().unwrap();
+        let node: SocketAddr = "51.15.220.9:9732".parse().unwrap();
+        let hash = "onvN8U6QJ6DGJKVYkHXYRtFm3tgBJScj9P5bbPjSZUuFaGzwFuJ".to_string();
+
+        state.observed(hash.clone(), true, a, Gossip::Pending, 1);
+        // the same announcement is not repeated
+        state.observed(hash.clone(), true, a, Gossip::Pending, 2);
+        state.observed(hash.clone(), true, b, Gossip::Operation, 3);
+        state.observed(hash.clone(), true, a, Gossip::KnownValid, 4);
+        state.observed(hash.clone(), false, node, Gossip::KnownValid, 5);
+        state.observed("other".to_string(), true, b, Gossip::Pending, 6);
+
+        let mut peer_ids = HashMap::new();
+        peer_ids.insert(a.to_string(), "idtJunqYgD1M6r6o2qvGpiD5xKZWRu".to_string());
+        let all = state.operations(10, &MempoolFilters::default(), &peer_ids);
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].hash, "other");
+
+        let filters = MempoolFilters { operation_hash: Some(hash.clone()), ..MempoolFilters::default() };
+        let operation = state.operations(10, &filters, &peer_ids).remove(0);
+        assert_eq!(operation.first_seen, 1);
+        assert_eq!(operation.forwarded, Some(5));
+        let gossips = operation.peers.iter().map(|p| (p.remote_addr, p.gossip)).collect::<Vec<_>>();
+        assert_eq!(gossips, vec![(a, Gossip::Pending), (b, Gossip::Operation), (a, Gossip::KnownValid)]);
+        assert_eq!(operation.peers[0].peer_id.as_deref(), Some("idtJunqYgD1M6r6o2qvGpiD5xKZWRu"));
+
+        let filters = MempoolFilters { peer_id: Some("idtJunqYgD1M6r6o2qvGpiD5xKZWRu".to_string()), ..MempoolFilters::default() };
+        assert_eq!(state.operations(10, &filters, &peer_ids).len(), 1);
+        let filters = MempoolFilters { remote_addr: Some(b), ..MempoolFilters::default() };
+        assert_eq!(state.operations(10, &filters, &peer_ids).len(), 2);
+    }
+}
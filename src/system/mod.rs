@@ -2,6 +2,8 @@
 // SPDX-License-Identifier: MIT
 
 pub mod syslog_producer;
+pub mod file_producer;
+pub mod alerts;
 //pub mod rpc_parser;
 //pub mod replayer;
 
@@ -9,17 +11,64 @@ pub mod syslog_producer;
 mod parser;
 mod reporter;
 mod p2p;
+mod chain;
+mod propagation;
+mod mempool;
+mod network;
+mod status;
 
 pub use self::{
-    parser::Parser,
+    parser::{Parser, ParserHandle},
     reporter::Reporter,
     p2p::Report as P2pReport,
+    p2p::ConnectionReport as P2pConnectionReport,
+    p2p::ParserError as P2pParserError,
+    p2p::{ParserErrorReport as P2pParserErrorReport, Misbehaviour as P2pMisbehaviour},
+    p2p::Comparison as P2pComparison,
+    p2p::{PeerScore, PeerMetric, scores as peer_scores},
+    p2p::{HandshakeInfo, HandshakeStep, HandshakeSummary},
+    chain::{ChainHeads, PeerHead},
+    propagation::{BlockPropagation, PropagationSummary},
+    mempool::{MempoolOperation, MempoolFilters},
+    network::{NetworkProfile, PowTarget},
+    status::{SystemStatus, KprobeStatus, Health},
 };
 
 mod processor;
 
 mod system_settings {
-    use crate::storage::MessageStore;
+    use std::{path::PathBuf, str::FromStr, sync::Arc};
+    use crate::{storage::MessageStore, messages::log_parser::LogParserRegistry};
+    use super::{alerts::Alerts, network::NetworkProfile, status::SystemStatus};
+
+    #[derive(Debug, Clone, PartialEq)]
+    /// Where the logs of the node are captured from
+    pub enum LogSource {
+        /// UDP syslog server on `syslog_port`
+        Syslog,
+        /// Tail the file written by the node
+        File(PathBuf),
+        /// Tail the file written by Docker json-file logging driver
+        Docker(PathBuf),
+    }
+
+    impl FromStr for LogSource {
+        type Err = String;
+
+        /// Parse `syslog`, `file:<path>` or `docker:<path>`
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let s = s.trim();
+            if s == "syslog" {
+                Ok(LogSource::Syslog)
+            } else if let Some(path) = s.strip_prefix("file:") {
+                Ok(LogSource::File(PathBuf::from(path)))
+            } else if let Some(path) = s.strip_prefix("docker:") {
+                Ok(LogSource::Docker(PathBuf::from(path)))
+            } else {
+                Err(format!("unknown log source: {}", s))
+            }
+        }
+    }
 
     #[derive(Clone)]
     /// System settings describing the running system
@@ -29,8 +78,14 @@ mod system_settings {
         pub syslog_port: u16,
         pub rpc_port: u16,
         pub node_p2p_port: u16,
+        pub node_rpc_host: String,
         pub node_rpc_port: u16,
         pub max_message_number: u64,
+        pub log_sources: Vec<LogSource>,
+        pub log_parsers: Arc<LogParserRegistry>,
+        pub alerts: Alerts,
+        pub network: NetworkProfile,
+        pub status: SystemStatus,
     }
 }
-pub use self::system_settings::SystemSettings;
+pub use self::system_settings::{SystemSettings, LogSource};
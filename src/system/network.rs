@@ -0,0 +1,157 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{fs, path::Path, str::FromStr};
+use serde::{Serialize, Deserialize};
+use tezos_messages::p2p::encoding::version::NetworkVersion;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+/// Proof of work the peers must achieve, the connection is not decrypted if the peer is below the target
+pub enum PowTarget {
+    /// Leading zero bits of the hash, same as the target of the node
+    Bits(f64),
+    /// Do not check the proof of work, for sandbox, `null` in the profile
+    Disabled,
+}
+
+impl Default for PowTarget {
+    fn default() -> Self {
+        PowTarget::Bits(26.0)
+    }
+}
+
+impl PowTarget {
+    /// Target passed to the conversation, zero bits are always achieved
+    pub fn bits(&self) -> f64 {
+        match self {
+            PowTarget::Bits(bits) => *bits,
+            PowTarget::Disabled => 0.0,
+        }
+    }
+}
+
+impl FromStr for PowTarget {
+    type Err = String;
+
+    /// Parse the number of bits, or `none` to disable the check
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s == "none" {
+            return Ok(PowTarget::Disabled);
+        }
+        match s.parse::<f64>() {
+            Ok(bits) if bits >= 0.0 && bits <= 256.0 => Ok(PowTarget::Bits(bits)),
+            _ => Err(format!("invalid proof of work target: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// Network the debugged node is running on
+pub struct NetworkProfile {
+    pub name: String,
+    // chain name advertised in the connection message
+    pub chain_name: String,
+    // base58 encoded chain id, absent if it depends on the genesis, like in the sandbox
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub chain_id: Option<String>,
+    pub distributed_db_versions: Vec<u16>,
+    pub p2p_versions: Vec<u16>,
+    pub pow_target: PowTarget,
+    pub p2p_port: u16,
+    pub rpc_port: u16,
+}
+
+impl Default for NetworkProfile {
+    fn default() -> Self {
+        Self::preset("mainnet").unwrap()
+    }
+}
+
+impl NetworkProfile {
+    /// Names of the built in profiles
+    pub const PRESETS: [&'static str; 4] = ["mainnet", "delphinet", "edonet", "sandbox"];
+
+    /// Built in profile by its name
+    pub fn preset(name: &str) -> Option<Self> {
+        let (chain_name, chain_id, pow_target, p2p_port, rpc_port) = match name {
+            "mainnet" => ("TEZOS_MAINNET", Some("NetXdQprcVkpaWU"), PowTarget::default(), 9732, 8732),
+            "delphinet" => ("TEZOS_DELPHINET_2020-09-04T07:08:53Z", Some("NetXm8tYqnMWky1"), PowTarget::default(), 9732, 8732),
+            "edonet" => ("TEZOS_EDONET_2020-11-30T12:00:00Z", Some("NetXSgo1ZT2DRUG"), PowTarget::default(), 9732, 8732),
+            "sandbox" => ("SANDBOXED_TEZOS", None, PowTarget::Disabled, 19732, 18732),
+            _ => return None,
+        };
+        Some(NetworkProfile {
+            name: name.to_string(),
+            chain_name: chain_name.to_string(),
+            chain_id: chain_id.map(str::to_string),
+            distributed_db_versions: vec![0, 1],
+            p2p_versions: vec![0, 1],
+            pow_target,
+            p2p_port,
+            rpc_port,
+        })
+    }
+
+    /// Load the profile from the json file
+    pub fn load(path: &Path) -> Result<Self, failure::Error> {
+        let content = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// The highest version of the network, as the node advertises it
+    pub fn version(&self) -> NetworkVersion {
+        NetworkVersion::new(
+            self.chain_name.clone(),
+            self.distributed_db_versions.iter().max().cloned().unwrap_or(0),
+            self.p2p_versions.iter().max().cloned().unwrap_or(0),
+        )
+    }
+
+    /// Whether the version advertised in the connection message belongs to the network
+    pub fn accepts(&self, version: &NetworkVersion) -> bool {
+        version.chain_name() == &self.chain_name
+    }
+}
+
+impl FromStr for NetworkProfile {
+    type Err = String;
+
+    /// Built in profile by its name
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::preset(s.trim())
+            .ok_or_else(|| format!("unknown network: {}, expected one of {}", s, Self::PRESETS.join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile() {
+        for name in NetworkProfile::PRESETS.iter() {
+            assert_eq!(&name.parse::<NetworkProfile>().unwrap().name, name);
+        }
+        assert!("babylonnet".parse::<NetworkProfile>().is_err());
+
+        let json = r#"{
+            "name": "custom",
+            "chain_name": "TEZOS_CUSTOM",
+            "distributed_db_versions": [0],
+            "p2p_versions": [1],
+            "pow_target": null,
+            "p2p_port": 9733,
+            "rpc_port": 8733
+        }"#;
+        let profile = serde_json::from_str::<NetworkProfile>(json).unwrap();
+        assert_eq!(profile.pow_target, PowTarget::Disabled);
+        assert_eq!(profile.chain_id, None);
+        let profile = serde_json::from_str::<NetworkProfile>(&json.replace("null", "24")).unwrap();
+        assert_eq!(profile.pow_target, PowTarget::Bits(24.0));
+
+        assert_eq!("none".parse::<PowTarget>(), Ok(PowTarget::Disabled));
+        assert!("-1".parse::<PowTarget>().is_err());
+    }
+}
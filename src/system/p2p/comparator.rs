@@ -0,0 +1,121 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use crate::system::{SystemSettings, Reporter};
+use super::compare::{NodePeer, Comparison};
+
+/// How often the debugger asks the node for its peers
+const COMPARE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Fetch the peers known by the node from its RPC
+pub async fn fetch_node_peers(host: &str, port: u16) -> Result<Vec<NodePeer>, failure::Error> {
+    let uri = format!("http://{}:{}/network/peers", host, port).parse::<hyper::Uri>()?;
+    let response = hyper::Client::new().get(uri).await?;
+    if !response.status().is_success() {
+        return Err(failure::format_err!("node responded with status {}", response.status()));
+    }
+    let body = hyper::body::to_bytes(response.into_body()).await?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// Spawn a (green)thread which periodically compares the counts of the debugger
+/// with the peer metadata reported by the node, the latest result is written in `comparison`
+pub fn spawn_comparator(
+    settings: SystemSettings,
+    reporter: Reporter,
+    comparison: Arc<RwLock<Option<Comparison>>>,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(COMPARE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let node_peers = match fetch_node_peers(&settings.node_rpc_host, settings.node_rpc_port).await {
+                Ok(node_peers) => node_peers,
+                Err(error) => {
+                    tracing::warn!(
+                        error = tracing::field::display(&error),
+                        msg = "P2P failed to fetch peers from the node",
+                    );
+                    continue;
+                },
+            };
+            let report = reporter.get_p2p_report();
+            let new = Comparison::new(&node_peers, report.working_connections());
+            *comparison.write().unwrap() = Some(new);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, convert::Infallible};
+    use warp::Filter;
+    use super::*;
+    use crate::{
+        messages::p2p_message::SourceType,
+        system::p2p::{compare::PeerMetadata, report::ConnectionReport},
+    };
+
+    const NETWORK_PEERS: &str = include_str!("../../../tests/network_peers.json");
+
+    fn connection(peer_id: &str, metadata: PeerMetadata) -> ConnectionReport {
+        ConnectionReport {
+            id: None,
+            opened_timestamp: 0,
+            closed_timestamp: None,
+            remote_address: "51.15.220.7:9732".to_string(),
+            source_type: SourceType::Local,
+            peer_id: Some(peer_id.to_string()),
+            sent_bytes: 0,
+            received_bytes: 0,
+            incomplete_dropped_messages: 0,
+            total_chunks: 0,
+            decrypted_chunks: 0,
+            error_report: None,
+            violations: BTreeMap::new(),
+            misbehaviour: Default::default(),
+            handshake: Default::default(),
+            gap: Default::default(),
+            metadata: Some(metadata),
+        }
+    }
+
+    #[tokio::test]
+    async fn fetch_from_mock_node() {
+        let route = warp::path!("network" / "peers")
+            .and_then(|| async { Ok::<_, Infallible>(warp::reply::with_header(NETWORK_PEERS, "Content-Type", "application/json")) });
+        let (address, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let peers = fetch_node_peers(&address.ip().to_string(), address.port()).await.unwrap();
+        assert_eq!(peers.len(), 2);
+        assert_eq!(peers[0].0, "idtJunqYgD1M6r6o2qvGpiD5xKZWRu");
+        assert_eq!(peers[0].1.state, "running");
+    }
+
+    #[test]
+    fn compare_with_node() {
+        let node_peers: Vec<NodePeer> = serde_json::from_str(NETWORK_PEERS).unwrap();
+        let same = node_peers[0].1.peer_metadata.clone();
+        let working = vec![
+            connection("idtJunqYgD1M6r6o2qvGpiD5xKZWRu", PeerMetadata::default()),
+            connection("idrdoT9g6YwELhUQyshCcHwAzBS9zA", PeerMetadata::default()),
+        ];
+
+        let comparison = serde_json::to_value(Comparison::new(&node_peers, &working)).unwrap();
+        // the debugger counted nothing, the node counted some messages
+        assert_eq!(comparison["peers"][0]["peer_id"], "idtJunqYgD1M6r6o2qvGpiD5xKZWRu");
+        assert_eq!(comparison["peers"][0]["positive"], true);
+        assert_eq!(comparison["missing_in_node"][0], "idrdoT9g6YwELhUQyshCcHwAzBS9zA");
+        // disconnected peer is not expected to have a connection
+        assert_eq!(comparison["missing_in_debugger"].as_array().unwrap().len(), 0);
+
+        let working = vec![connection("idtJunqYgD1M6r6o2qvGpiD5xKZWRu", same)];
+        let comparison = serde_json::to_value(Comparison::new(&node_peers, &working)).unwrap();
+        assert_eq!(comparison["peers"].as_array().unwrap().len(), 0);
+    }
+}
@@ -3,10 +3,11 @@
 
 // incomplete
 
-use std::{fmt, string::ToString, ops::{Add, Sub, Range}};
+use std::{fmt, string::ToString, ops::{Add, Sub, Range}, collections::HashMap};
 use chrono::{DateTime, Utc};
 use serde::{Serialize, Deserialize, ser, de};
-use crate::messages::p2p_message::FullPeerMessage;
+use crate::{messages::p2p_message::FullPeerMessage, storage::get_ts};
+use super::report::ConnectionReport;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Peer {
@@ -41,6 +42,79 @@ pub struct PeerMetadata {
     //advertisements: (),
 }
 
+/// Item of the list returned by the node at `/network/peers`, it is a pair `[peer_id, info]`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NodePeer(pub String, pub NodePeerInfo);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NodePeerInfo {
+    pub state: String,
+    pub peer_metadata: PeerMetadata,
+}
+
+/// Discrepancies between the node and the debugger
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Comparison {
+    last_updated_timestamp: u128,
+    peers: Vec<PeerComparison>,
+    // the debugger has a working connection, but the node does not report the peer as running
+    missing_in_node: Vec<String>,
+    // the node reports the peer as running, but the debugger has no connection with it
+    missing_in_debugger: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PeerComparison {
+    peer_id: String,
+    remote_address: String,
+    // the node counted more messages than the debugger, so the debugger lost some
+    positive: bool,
+    diff: PeerMetadataDiff,
+}
+
+impl Comparison {
+    /// Compare metadata reported by the node with metadata counted by the debugger for each working connection
+    pub fn new(node_peers: &[NodePeer], working_connections: &[ConnectionReport]) -> Self {
+        let mut node_peers = node_peers.iter()
+            .filter(|NodePeer(_, info)| info.state == "running")
+            .map(|NodePeer(peer_id, info)| (peer_id.as_str(), &info.peer_metadata))
+            .collect::<HashMap<_, _>>();
+
+        let mut peers = Vec::new();
+        let mut missing_in_node = Vec::new();
+        for report in working_connections {
+            let (peer_id, metadata) = match (&report.peer_id, &report.metadata) {
+                (&Some(ref peer_id), &Some(ref metadata)) => (peer_id, metadata),
+                // handshake is not done yet, nothing to compare
+                _ => continue,
+            };
+            match node_peers.remove(peer_id.as_str()) {
+                Some(node_metadata) => {
+                    if let Some(diff) = (node_metadata - metadata).none() {
+                        peers.push(PeerComparison {
+                            peer_id: peer_id.clone(),
+                            remote_address: report.remote_address.clone(),
+                            positive: diff.positive(),
+                            diff,
+                        });
+                    }
+                },
+                None => missing_in_node.push(peer_id.clone()),
+            }
+        }
+        missing_in_node.sort();
+        let mut missing_in_debugger = node_peers.keys().map(ToString::to_string).collect::<Vec<_>>();
+        missing_in_debugger.sort();
+
+        Comparison {
+            last_updated_timestamp: get_ts(),
+            peers,
+            missing_in_node,
+            missing_in_debugger,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct PeerMetadataDiff {
     responses: Option<CountsByGroupsDiff>,
@@ -5,7 +5,7 @@ use tokio::{
 };
 use futures::future::Either;
 
-use super::{connection_parser::Parser, parser::{Command, Message}, report::ConnectionReport};
+use super::{connection_parser::Parser, parser::{Command, Message}, report::{ConnectionReport, GapCause}};
 use crate::messages::p2p_message::SourceType;
 
 pub struct Connection {
@@ -29,14 +29,11 @@ enum ConnectionState {
 }
 
 impl Connection {
-    pub fn spawn(
-        tx_report: mpsc::Sender<ConnectionReport>,
-        parser: Parser,
-    ) -> Self {
+    pub fn spawn(parser: Parser) -> Self {
         let (tx, rx) = mpsc::unbounded_channel();
         let source_type = parser.source_type.clone();
         let remote_address = parser.remote_address.clone();
-        let handle = tokio::spawn(parser.run(rx, tx_report));
+        let handle = tokio::spawn(parser.run(rx));
         Connection {
             state: ConnectionState::Initial,
             tx,
@@ -117,10 +114,15 @@ impl Connection {
         self.send(Either::Left(message))
     }
 
-    pub fn send_command(&mut self, command: Command) {
+    fn send_command(&mut self, command: Command) {
         self.send(Either::Right(command))
     }
 
+    /// The data of the connection is lost, the parser must know it to not decode the rest of the stream
+    pub fn gap(&mut self, incoming: bool, lost: usize, cause: GapCause) {
+        self.send_command(Command::Gap { incoming, lost, cause })
+    }
+
     pub async fn join(mut self) -> Result<ConnectionReport, JoinError> {
         self.send_command(Command::Terminate);
         self.handle.await
@@ -1,9 +1,9 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use std::{fmt, net::SocketAddr};
+use std::{fmt, net::SocketAddr, collections::BTreeMap, time::{Duration, Instant}};
 use futures::future::Either;
-use tokio::{stream::StreamExt, sync::mpsc};
+use tokio::{stream::StreamExt, sync::mpsc, time};
 use tracing::field::DisplayValue;
 use tezos_messages::p2p::{
     encoding::{
@@ -20,13 +20,16 @@ use tezos_conversation::{Identity, Conversation, Packet, ConsumeResult, ChunkMet
 use sniffer::{SocketId, EventId};
 
 use super::{
-    report::{ConnectionReport, ParserError, ParserErrorReport},
+    report::{ConnectionReport, Misbehaviour, Gap, GapCause, ParserError, ParserErrorReport, ReportSnapshot},
     parser::{Message, Command},
     compare::PeerMetadata,
+    validation::Validator,
+    handshake::HandshakeInfo,
 };
 
 use crate::{
-    system::SystemSettings,
+    system::{SystemSettings, PowTarget},
+    storage::get_ts,
     messages::p2p_message::{
         P2pMessage,
         SourceType,
@@ -36,13 +39,19 @@ use crate::{
     },
 };
 
+/// The report of the working connection is published at most this often
+const SNAPSHOT_INTERVAL: Duration = Duration::from_millis(200);
+
 pub struct Parser {
     pub identity: Identity,
     pub settings: SystemSettings,
     pub source_type: SourceType,
     pub remote_address: SocketAddr,
     pub id: SocketId,
+    // the ID of the connection report, reserved when the connection opened
+    pub connection_id: u64,
     pub db: mpsc::UnboundedSender<P2pMessage>,
+    pub snapshot: ReportSnapshot,
 }
 
 struct State {
@@ -52,6 +61,10 @@ struct State {
     buffer: Vec<u8>,
     statistics: ConnectionReport,
     metadata: PeerMetadata,
+    validator: Validator,
+    // when the report was published to the snapshot, and whether it changed since
+    published: Instant,
+    unpublished: bool,
 }
 
 struct ErrorContext {
@@ -75,29 +88,33 @@ impl fmt::Display for ErrorContext {
 }
 
 impl Parser {
-    const DEFAULT_POW_TARGET: f64 = 26.0;
-
-    pub async fn run<S>(self, events: S, tx_report: mpsc::Sender<ConnectionReport>) -> ConnectionReport
+    pub async fn run<S>(self, events: S) -> ConnectionReport
     where
         S: Unpin + StreamExt<Item = Either<Message, Command>>,
     {
-        match self.run_inner(events, tx_report).await {
+        let mut report = match self.run_inner(events).await {
             Ok(report) => report,
             Err(report) => report,
-        }
+        };
+        report.closed_timestamp = Some(get_ts());
+        report.handshake.finish(report.error_report.as_ref().map(|e| e.error), true);
+        report
     }
 
     // TODO: split
-    async fn run_inner<S>(self, mut events: S, mut tx_report: mpsc::Sender<ConnectionReport>) -> Result<ConnectionReport, ConnectionReport>
+    async fn run_inner<S>(self, mut events: S) -> Result<ConnectionReport, ConnectionReport>
     where
         S: Unpin + StreamExt<Item = Either<Message, Command>>,
     {
         let mut state = State {
-            conversation: Conversation::new(Self::DEFAULT_POW_TARGET),
+            conversation: Conversation::new(self.settings.network.pow_target.bits()),
             chunk_incoming_counter: 0,
             chunk_outgoing_counter: 0,
             buffer: vec![],
             statistics: ConnectionReport {
+                id: Some(self.connection_id),
+                opened_timestamp: get_ts(),
+                closed_timestamp: None,
                 remote_address: self.remote_address.to_string(),
                 source_type: self.source_type.clone(),
                 peer_id: None,
@@ -107,9 +124,22 @@ impl Parser {
                 total_chunks: 0,
                 decrypted_chunks: 0,
                 error_report: None,
+                violations: BTreeMap::new(),
+                misbehaviour: Misbehaviour::default(),
+                handshake: HandshakeInfo {
+                    proof_of_work_target: match self.settings.network.pow_target {
+                        PowTarget::Bits(bits) => Some(bits),
+                        PowTarget::Disabled => None,
+                    },
+                    ..HandshakeInfo::default()
+                },
+                gap: Gap::default(),
                 metadata: None,
             },
             metadata: PeerMetadata::default(),
+            validator: Validator::new(&self.settings.network),
+            published: Instant::now(),
+            unpublished: false,
         };
 
         // the local socket identifier is pair (pid, fd), but `Conversation` requires the packet
@@ -117,17 +147,39 @@ impl Parser {
         // let's use fake socket address
         let fake_local = "0.0.0.0:54321".parse::<SocketAddr>().unwrap();
 
-        while let Some(event) = events.next().await {
+        self.publish(&mut state, true);
+        loop {
+            let event = match time::timeout(SNAPSHOT_INTERVAL, events.next()).await {
+                Ok(Some(event)) => event,
+                Ok(None) => break,
+                // the connection is idle, publish the latest changes
+                Err(_) => {
+                    if state.unpublished {
+                        self.publish(&mut state, true);
+                    }
+                    continue;
+                },
+            };
             let Message { payload, incoming, counter, event_id } = match event {
                 Either::Left(message) => message,
-                Either::Right(Command::GetReport) => {
-                    let report = state.statistics.clone();
-                    tx_report.send(report).await.unwrap();
+                Either::Right(Command::Gap { incoming, lost, cause }) => {
+                    tracing::error!(
+                        address = tracing::field::display(&self.remote_address),
+                        incoming = incoming,
+                        lost = lost,
+                        cause = tracing::field::debug(&cause),
+                        msg = "P2P data is not captured, the stream has a gap",
+                    );
+                    state.gap(incoming, lost, cause);
+                    self.publish(&mut state, false);
                     continue;
                 },
-                // TODO:
                 Either::Right(Command::Terminate) => break,
             };
+            if state.after_gap(incoming) {
+                state.statistics.gap.skipped_bytes += payload.len() as u64;
+                continue;
+            }
             let packet = Packet {
                 source: if incoming { self.remote_address.clone() } else { fake_local.clone() },
                 destination: if incoming { fake_local.clone() } else { self.remote_address.clone() },
@@ -156,6 +208,7 @@ impl Parser {
             match result {
                 ConsumeResult::Pending => (),
                 ConsumeResult::ConnectionMessage(chunk_info) => {
+                    state.statistics.handshake.connection_message(incoming, chunk_info.data());
                     let message = ConnectionMessage::from_bytes(&chunk_info.data()[2..])
                         .map(|cm: ConnectionMessage| {
                             if incoming {
@@ -175,7 +228,7 @@ impl Parser {
                         chunk_info.data().to_vec(),
                         message,
                     );
-                    state.inc(incoming, true, chunk_info.data().len());
+                    state.inc(&p2p_msg, true, chunk_info.data().len());
                     let error_context = self.error_context(&state, incoming, &event_id);
                     self.store_db(&mut state, p2p_msg, error_context)?;
                     tracing::info!(
@@ -195,7 +248,7 @@ impl Parser {
                             decrypted.data().to_vec(),
                             message,
                         );
-                        state.inc(incoming, true, decrypted.data().len());
+                        state.inc(&p2p_msg, true, decrypted.data().len());
                         let error_context = self.error_context(&state, incoming, &event_id);
                         self.store_db(&mut state, p2p_msg, error_context)?;
                     }
@@ -214,7 +267,7 @@ impl Parser {
                             vec![],
                             Err("cannot decrypt".to_string()),
                         );
-                        state.inc(incoming, false, chunk.data().len());
+                        state.inc(&p2p_msg, false, chunk.data().len());
                         let error_context = self.error_context(&state, incoming, &event_id);
                         self.store_db(&mut state, p2p_msg, error_context)?;
                     }
@@ -239,6 +292,7 @@ impl Parser {
                     } else {
                         tracing::error!(context = context, payload = payload, msg = "wrong pow");
                     }
+                    state.statistics.handshake.connection_message(incoming, packet.payload.as_slice());
                     state.report_error(ParserError::WrongProofOfWork);
                 },
                 ConsumeResult::UnexpectedChunks => {
@@ -248,12 +302,23 @@ impl Parser {
                     state.report_error(ParserError::Unknown);
                 },
             }
+            self.publish(&mut state, false);
         }
 
-        let metadata = state.metadata;
-        let mut statistics = state.statistics;
-        statistics.metadata = Some(metadata);
-        Ok(statistics)
+        Ok(state.report())
+    }
+
+    /// Publish the report to the snapshot, unless it was published less than `SNAPSHOT_INTERVAL` ago,
+    /// building the report on every chunk is too expensive
+    fn publish(&self, state: &mut State, force: bool) {
+        let now = Instant::now();
+        if force || now.duration_since(state.published) >= SNAPSHOT_INTERVAL {
+            self.snapshot.update(&self.id, state.report());
+            state.published = now;
+            state.unpublished = false;
+        } else {
+            state.unpublished = true;
+        }
     }
 
     fn error_context(&self, state: &State, is_incoming: bool, event_id: &EventId) -> DisplayValue<ErrorContext> {
@@ -266,7 +331,18 @@ impl Parser {
         tracing::field::display(ctx)
     }
 
-    fn store_db(&self, state: &mut State, message: P2pMessage, error_context: DisplayValue<ErrorContext>) -> Result<(), ConnectionReport> {
+    fn store_db(&self, state: &mut State, mut message: P2pMessage, error_context: DisplayValue<ErrorContext>) -> Result<(), ConnectionReport> {
+        message.violations = state.validator.validate(&message);
+        state.statistics.handshake.update(&message);
+        for violation in &message.violations {
+            tracing::warn!(
+                address = tracing::field::display(&self.remote_address),
+                incoming = message.incoming,
+                violation = tracing::field::debug(&violation.kind),
+                msg = tracing::field::display(&violation.description),
+            );
+            *state.statistics.violations.entry(violation.kind).or_insert(0) += 1;
+        }
         self.db.send(message)
             .map_err(|err| {
                 tracing::error!(
@@ -275,12 +351,47 @@ impl Parser {
                     msg = "db channel closed abruptly",
                 );
                 state.report_error(ParserError::FailedToWriteInDatabase);
-                state.statistics.clone()
+                state.report()
             })
     }
 }
 
 impl State {
+    fn report(&self) -> ConnectionReport {
+        let mut report = self.statistics.clone();
+        report.metadata = Some(self.metadata.clone());
+        report.misbehaviour = self.validator.misbehaviour();
+        report.handshake.finish(report.error_report.as_ref().map(|e| e.error), false);
+        report
+    }
+
+    /// The data is lost, the direction cannot be decoded anymore
+    fn gap(&mut self, incoming: bool, lost: usize, cause: GapCause) {
+        let position = self.statistics.total_chunks;
+        let gap = &mut self.statistics.gap;
+        gap.drops += 1;
+        match cause {
+            GapCause::RingBufferOverflow => gap.ring_buffer_drops += 1,
+            GapCause::Sendfile => gap.sendfile_drops += 1,
+            GapCause::IovLimit => gap.iov_limit_drops += 1,
+        }
+        gap.lost_bytes += lost as u64;
+        if incoming {
+            gap.incoming_at.get_or_insert(position);
+        } else {
+            gap.outgoing_at.get_or_insert(position);
+        }
+        self.report_error(cause.error());
+    }
+
+    fn after_gap(&self, incoming: bool) -> bool {
+        if incoming {
+            self.statistics.gap.incoming_at.is_some()
+        } else {
+            self.statistics.gap.outgoing_at.is_some()
+        }
+    }
+
     fn report_error(&mut self, error: ParserError) {
         if self.statistics.error_report.is_none() {
             self.statistics.error_report = Some(ParserErrorReport {
@@ -290,7 +401,8 @@ impl State {
         }
     }
 
-    fn inc(&mut self, incoming: bool, decrypted: bool, length: usize) {
+    fn inc(&mut self, message: &P2pMessage, decrypted: bool, length: usize) {
+        let incoming = message.incoming;
         self.statistics.total_chunks += 1;
         if decrypted {
             self.statistics.decrypted_chunks += 1;
@@ -0,0 +1,252 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use tezos_messages::p2p::{
+    encoding::{connection::ConnectionMessage, ack::AckMessage},
+    binary_message::BinaryMessage,
+};
+use crypto::blake2b;
+use super::report::{ConnectionReport, ParserError};
+use crate::messages::p2p_message::{P2pMessage, TezosPeerMessage, HandshakeMessage};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// Step of the handshake
+pub enum HandshakeStep {
+    ConnectionMessage,
+    Metadata,
+    Ack,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/// Network version advertised in the connection message
+pub struct Version {
+    pub chain_name: String,
+    pub distributed_db_version: u16,
+    pub p2p_version: u16,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+/// What one side of the connection sent during the handshake
+pub struct HandshakeSide {
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub port: Option<u16>,
+    pub versions: Vec<Version>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub public_key: Option<String>,
+    // leading zero bits of the hash of the public key and the proof of work stamp
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub proof_of_work_bits: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub nonce: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub disable_mempool: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub private_node: Option<bool>,
+    // the last step of the handshake this side made
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub step: Option<HandshakeStep>,
+    pub nack: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+/// Handshake of the connection as seen by both sides
+pub struct HandshakeInfo {
+    pub local: HandshakeSide,
+    pub remote: HandshakeSide,
+    // the target the peers were checked against, absent if not checked
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub proof_of_work_target: Option<f64>,
+    pub completed: bool,
+    // the first step some side did not make, or made wrong
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub failed_at: Option<HandshakeStep>,
+    // the first step some side did not make yet, the connection is still open
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub pending: Option<HandshakeStep>,
+}
+
+/// Number of leading zero bits of the hash of the public key and the stamp, as checked by the node
+pub fn proof_of_work_bits(public_key: &[u8], stamp: &[u8]) -> u32 {
+    let hash = blake2b::digest_256(&[public_key, stamp].concat());
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+impl HandshakeInfo {
+    // the chunk starts with 2 bytes of its length, then 2 bytes of port, 32 bytes of public key,
+    // 24 bytes of proof of work stamp and 24 bytes of nonce
+    const PORT: std::ops::Range<usize> = 2..4;
+    const PUBLIC_KEY: std::ops::Range<usize> = 4..36;
+    const STAMP: std::ops::Range<usize> = 36..60;
+    const NONCE: std::ops::Range<usize> = 60..84;
+
+    fn side(&mut self, incoming: bool) -> &mut HandshakeSide {
+        if incoming {
+            &mut self.remote
+        } else {
+            &mut self.local
+        }
+    }
+
+    /// Analyse the chunk of the connection message, it is analysed even if the proof of work is wrong
+    pub fn connection_message(&mut self, incoming: bool, chunk: &[u8]) {
+        if chunk.len() < Self::NONCE.end {
+            return;
+        }
+        let side = self.side(incoming);
+        side.port = Some(u16::from_be_bytes([chunk[Self::PORT.start], chunk[Self::PORT.start + 1]]));
+        side.public_key = Some(hex::encode(&chunk[Self::PUBLIC_KEY]));
+        side.proof_of_work_bits = Some(proof_of_work_bits(&chunk[Self::PUBLIC_KEY], &chunk[Self::STAMP]));
+        side.nonce = Some(hex::encode(&chunk[Self::NONCE]));
+        if let Ok(message) = ConnectionMessage::from_bytes(&chunk[2..]) {
+            side.versions = message.versions()
+                .iter()
+                .map(|version| Version {
+                    chain_name: version.chain_name().clone(),
+                    distributed_db_version: version.distributed_db_version().clone(),
+                    p2p_version: version.p2p_version().clone(),
+                })
+                .collect();
+        }
+        side.step = Some(HandshakeStep::ConnectionMessage);
+    }
+
+    /// Analyse the decoded metadata and ack messages
+    pub fn update(&mut self, message: &P2pMessage) {
+        let side = self.side(message.incoming);
+        match message.message.first() {
+            Some(TezosPeerMessage::HandshakeMessage(HandshakeMessage::MetadataMessage(m))) => {
+                side.disable_mempool = Some(m.disable_mempool());
+                side.private_node = Some(m.private_node());
+                side.step = Some(HandshakeStep::Metadata);
+            },
+            Some(TezosPeerMessage::HandshakeMessage(HandshakeMessage::AckMessage(m))) => {
+                side.nack = !matches!(m, AckMessage::Ack);
+                side.step = Some(HandshakeStep::Ack);
+            },
+            _ => (),
+        }
+    }
+
+    /// Decide whether the handshake completed, `error` is the error of the connection, if any;
+    /// the missing step of the open connection without error is pending, it is not failed yet
+    pub fn finish(&mut self, error: Option<ParserError>, closed: bool) {
+        let made = |side: &HandshakeSide, step: HandshakeStep| side.step.map(|s| s >= step).unwrap_or(false);
+        let missing = [HandshakeStep::ConnectionMessage, HandshakeStep::Metadata, HandshakeStep::Ack]
+            .iter()
+            .cloned()
+            .find(|&step| !made(&self.local, step) || !made(&self.remote, step));
+        let nack = self.local.nack || self.remote.nack;
+        self.pending = None;
+        self.failed_at = match error {
+            Some(ParserError::WrongProofOfWork) | Some(ParserError::NoDecipher) => Some(HandshakeStep::ConnectionMessage),
+            None if !closed && !nack => {
+                self.pending = missing;
+                None
+            },
+            _ => missing.or_else(|| if nack { Some(HandshakeStep::Ack) } else { None }),
+        };
+        self.completed = self.failed_at.is_none() && self.pending.is_none();
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+/// Handshakes of the connections summed up, to see quickly why the peers refuse the node
+pub struct HandshakeSummary {
+    pub total: u64,
+    pub completed: u64,
+    pub failed_at: BTreeMap<HandshakeStep, u64>,
+    pub pending: BTreeMap<HandshakeStep, u64>,
+    pub nack: u64,
+    // how many peers advertised the version, `chain_name/distributed_db_version/p2p_version`
+    pub versions: BTreeMap<String, u64>,
+    // how many peers achieved the proof of work of the bits
+    pub proof_of_work_bits: BTreeMap<u32, u64>,
+    pub disable_mempool: u64,
+    pub private_node: u64,
+}
+
+impl HandshakeSummary {
+    pub fn new<'a, I>(reports: I) -> Self
+    where
+        I: IntoIterator<Item = &'a ConnectionReport>,
+    {
+        let mut summary = HandshakeSummary::default();
+        for handshake in reports.into_iter().map(|report| &report.handshake) {
+            let remote = &handshake.remote;
+            summary.total += 1;
+            summary.completed += handshake.completed as u64;
+            if let Some(step) = handshake.failed_at {
+                *summary.failed_at.entry(step).or_default() += 1;
+            }
+            if let Some(step) = handshake.pending {
+                *summary.pending.entry(step).or_default() += 1;
+            }
+            summary.nack += remote.nack as u64;
+            for version in &remote.versions {
+                let key = format!("{}/{}/{}", version.chain_name, version.distributed_db_version, version.p2p_version);
+                *summary.versions.entry(key).or_default() += 1;
+            }
+            if let Some(bits) = remote.proof_of_work_bits {
+                *summary.proof_of_work_bits.entry(bits).or_default() += 1;
+            }
+            summary.disable_mempool += remote.disable_mempool.unwrap_or(false) as u64;
+            summary.private_node += remote.private_node.unwrap_or(false) as u64;
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps() {
+        let mut info = HandshakeInfo::default();
+        let mut chunk = vec![0, 82, 0x26, 0x0c];
+        chunk.extend_from_slice(&[1; 80]);
+        info.connection_message(false, &chunk);
+        info.connection_message(true, &chunk);
+        assert_eq!(info.remote.port, Some(9740));
+        assert_eq!(info.remote.nonce.as_deref(), Some(hex::encode(&[1; 24]).as_str()));
+        info.local.step = Some(HandshakeStep::Metadata);
+        info.finish(None, false);
+        assert_eq!((info.completed, info.failed_at, info.pending), (false, None, Some(HandshakeStep::Metadata)));
+        info.finish(None, true);
+        assert_eq!((info.completed, info.failed_at, info.pending), (false, Some(HandshakeStep::Metadata), None));
+        info.finish(Some(ParserError::FailedToDecrypt), false);
+        assert_eq!((info.failed_at, info.pending), (Some(HandshakeStep::Metadata), None));
+
+        info.remote.step = Some(HandshakeStep::Ack);
+        info.local.step = Some(HandshakeStep::Ack);
+        info.finish(None, false);
+        assert!(info.completed);
+        info.finish(None, true);
+        assert!(info.completed);
+        info.remote.nack = true;
+        info.finish(None, false);
+        assert_eq!((info.completed, info.failed_at), (false, Some(HandshakeStep::Ack)));
+
+        info.finish(Some(ParserError::WrongProofOfWork), false);
+        assert_eq!(info.failed_at, Some(HandshakeStep::ConnectionMessage));
+    }
+
+    #[test]
+    fn proof_of_work() {
+        let public_key = (0..32).collect::<Vec<u8>>();
+        let mut stamp = [0; 24];
+        stamp[23] = 237;
+        // the hash is 00033dac...
+        assert_eq!(proof_of_work_bits(&public_key, &stamp), 14);
+    }
+}
@@ -3,8 +3,16 @@ mod connection;
 mod connection_parser;
 mod report;
 mod compare;
+mod comparator;
+mod validation;
+mod score;
+mod handshake;
 
 pub use self::{
-    parser::{Command, Parser, Message},
-    report::Report,
+    parser::{Parser, Message},
+    report::{Report, ReportSnapshot, ConnectionReport, ParserError, ParserErrorReport, Misbehaviour, GapCause},
+    compare::Comparison,
+    comparator::spawn_comparator,
+    score::{PeerScore, PeerMetric, scores},
+    handshake::{HandshakeInfo, HandshakeStep, HandshakeSummary},
 };
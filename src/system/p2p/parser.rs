@@ -1,4 +1,5 @@
 use std::{
+    mem,
     path::Path,
     net::SocketAddr,
     collections::HashMap,
@@ -10,11 +11,12 @@ use sniffer::{EventId, SocketId};
 use crate::{
     messages::p2p_message::{SourceType, P2pMessage},
     system::SystemSettings,
+    storage::MessageStore,
 };
 use super::{
     connection::Connection,
     connection_parser,
-    report::{Report, ConnectionReport},
+    report::{ReportSnapshot, GapCause},
 };
 
 pub struct Message {
@@ -26,8 +28,9 @@ pub struct Message {
 
 #[derive(Debug, Clone, Copy)]
 pub enum Command {
-    GetReport,
     Terminate,
+    /// The data is not captured, `lost` bytes are missing from the stream
+    Gap { incoming: bool, lost: usize, cause: GapCause },
 }
 
 pub struct ProcessingConnectionResult {
@@ -35,54 +38,19 @@ pub struct ProcessingConnectionResult {
 }
 
 pub struct Parser {
+    storage: MessageStore,
     identity_cache: Option<Identity>,
-    tx_report: mpsc::Sender<Report>,
-    rx_connection_report: mpsc::Receiver<ConnectionReport>,
-    tx_connection_report: mpsc::Sender<ConnectionReport>,
+    snapshot: ReportSnapshot,
     working_connections: HashMap<SocketId, Connection>,
-    closed_connections: Vec<ConnectionReport>,
 }
 
 impl Parser {
-    pub fn new(tx_report: mpsc::Sender<Report>) -> Self {
-        let (tx_connection_report, rx_connection_report) = mpsc::channel(0x100);
+    pub fn new(storage: MessageStore, snapshot: ReportSnapshot) -> Self {
         Parser {
+            storage,
             identity_cache: None,
-            tx_report,
-            rx_connection_report,
-            tx_connection_report,
+            snapshot,
             working_connections: HashMap::new(),
-            closed_connections: Vec::new(),
-        }
-    }
-
-    pub async fn execute(&mut self, command: Command) {
-        let report = self.execute_inner(command).await;
-        match self.tx_report.send(report).await {
-            Ok(()) => (),
-            Err(_) => (),
-        }
-    }
-
-    async fn execute_inner(&mut self, command: Command) -> Report {
-        self.working_connections.iter_mut().for_each(|(_, c)| c.send_command(command));
-
-        match command {
-            Command::GetReport => {
-                let mut working_connections = Vec::new();
-                while let Ok(report) = self.rx_connection_report.try_recv() {
-                    working_connections.push(report);
-                }
-                let mut closed_connections = self.closed_connections.clone();
-                closed_connections.iter_mut().for_each(|report| report.metadata = None);
-        
-                Report::prepare(closed_connections, working_connections)        
-            },
-            Command::Terminate => {
-                debug_assert!(self.rx_connection_report.try_recv().is_err(), "should not have reports to receive");
-                // TODO: this is the final report, compare it with ocaml report
-                Report::prepare(self.closed_connections.clone(), Vec::new())        
-            }
         }
     }
 
@@ -127,25 +95,25 @@ impl Parser {
         db: &mpsc::UnboundedSender<P2pMessage>,
         source_type: SourceType,
     ) -> ProcessingConnectionResult {
-        let have_identity = if let Some(identity) = self.try_load_identity() {
+        let identity = self.try_load_identity();
+        settings.status.set_identity_loaded(identity.is_some());
+        let have_identity = if let Some(identity) = identity {
+            // the socket is reused, finish the old connection before the new one publish its statistics
+            if let Some(old) = self.working_connections.remove(&id.socket_id) {
+                self.join(&id.socket_id, old).await;
+            }
             let parser = connection_parser::Parser {
                 identity,
                 settings: settings.clone(),
                 source_type,
                 remote_address,
                 id: id.socket_id.clone(),
+                connection_id: self.storage.connection().reserve_index(),
                 db: db.clone(),
+                snapshot: self.snapshot.clone(),
             };
-            let connection = Connection::spawn(self.tx_connection_report.clone(), parser);
-            if let Some(old) = self.working_connections.insert(id.socket_id, connection) {
-                match old.join().await {
-                    Ok(report) => self.closed_connections.push(report),
-                    Err(error) => tracing::error!(
-                        error = tracing::field::display(&error),
-                        msg = "P2P failed to join task which was processing the connection",
-                    ),
-                }
-            }
+            let connection = Connection::spawn(parser);
+            self.working_connections.insert(id.socket_id, connection);
             true
         } else {
             false
@@ -153,16 +121,48 @@ impl Parser {
         ProcessingConnectionResult { have_identity }
     }
 
+    /// Join all working connections, their reports are stored
+    pub async fn terminate(&mut self) {
+        for (socket_id, connection) in mem::take(&mut self.working_connections) {
+            self.join(&socket_id, connection).await;
+        }
+    }
+
     pub async fn process_close(&mut self, event_id: EventId) {
         // can safely drop the old connection
         if let Some(old) = self.working_connections.remove(&event_id.socket_id) {
-            match old.join().await {
-                Ok(report) => self.closed_connections.push(report),
-                Err(error) => tracing::error!(
-                    error = tracing::field::display(&error),
-                    msg = "P2P failed to join task which was processing the connection",
-                ),
-            }
+            self.join(&event_id.socket_id, old).await;
+        }
+    }
+
+    /// Wait until the connection is processed, persist its report
+    /// and keep it in memory among the latest closed connections
+    async fn join(&mut self, socket_id: &SocketId, connection: Connection) {
+        match connection.join().await {
+            Ok(mut report) => {
+                if let Err(error) = self.storage.connection().store_report(&mut report) {
+                    tracing::error!(
+                        error = tracing::field::display(&error),
+                        msg = "P2P failed to store connection report",
+                    );
+                }
+                self.snapshot.close(socket_id, report);
+            },
+            Err(error) => tracing::error!(
+                error = tracing::field::display(&error),
+                msg = "P2P failed to join task which was processing the connection",
+            ),
+        }
+    }
+
+    pub fn process_gap(&mut self, event_id: EventId, incoming: bool, lost: usize, cause: GapCause) {
+        match self.working_connections.get_mut(&event_id.socket_id) {
+            Some(connection) => connection.gap(incoming, lost, cause),
+            None => tracing::warn!(
+                id = tracing::field::display(&event_id),
+                cause = tracing::field::debug(&cause),
+                msg = "P2P data is not captured for absent connection",
+            ),
         }
     }
 
@@ -1,13 +1,23 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{
+    time::{SystemTime, UNIX_EPOCH},
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{Arc, RwLock},
+    net::SocketAddr,
+};
 use serde::{Serialize, Deserialize};
-use super::compare::PeerMetadata;
-use crate::messages::p2p_message::SourceType;
+use storage::persistent::{Decoder, SchemaError, Encoder};
+use sniffer::SocketId;
+use super::{compare::PeerMetadata, handshake::HandshakeInfo};
+use crate::messages::p2p_message::{SourceType, ViolationKind};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Report {
     last_updated_timestamp: u128,
     total_chunks: u64,
     decrypted_chunks: u64,
+    // how many times the data of the connections was lost because the ring buffer was full
+    #[serde(default)]
+    ring_buffer_drops: u64,
     closed_connections: Vec<ConnectionReport>,
     working_connections: Vec<ConnectionReport>,
 }
@@ -20,18 +30,72 @@ impl Report {
         let decrypted_chunks =
             working_connections.iter().map(|report| report.decrypted_chunks).sum::<u64>() +
             closed_connections.iter().map(|report| report.decrypted_chunks).sum::<u64>();
+        let ring_buffer_drops = working_connections.iter().chain(closed_connections.iter())
+            .map(|report| report.gap.ring_buffer_drops)
+            .sum();
         Report {
             last_updated_timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos(),
             total_chunks,
             decrypted_chunks,
+            ring_buffer_drops,
             closed_connections,
             working_connections,
         }
     }
+
+    pub fn working_connections(&self) -> &[ConnectionReport] {
+        &self.working_connections
+    }
+}
+
+/// Latest statistics of all connections, connection tasks publish their statistics here,
+/// so the report can be taken at any moment without waiting for them
+#[derive(Clone, Default)]
+pub struct ReportSnapshot {
+    working_connections: Arc<RwLock<HashMap<SocketId, ConnectionReport>>>,
+    // only the latest closed connections, the rest are in the database
+    closed_connections: Arc<RwLock<VecDeque<ConnectionReport>>>,
+}
+
+impl ReportSnapshot {
+    const MAX_CLOSED_CONNECTIONS: usize = 0x100;
+
+    /// Replace the statistics of the working connection
+    pub fn update(&self, id: &SocketId, report: ConnectionReport) {
+        self.working_connections.write().unwrap().insert(id.clone(), report);
+    }
+
+    /// Move the connection to the closed connections with its final statistics
+    pub fn close(&self, id: &SocketId, report: ConnectionReport) {
+        self.working_connections.write().unwrap().remove(id);
+        let mut closed_connections = self.closed_connections.write().unwrap();
+        if closed_connections.len() >= Self::MAX_CLOSED_CONNECTIONS {
+            closed_connections.pop_front();
+        }
+        closed_connections.push_back(report);
+    }
+
+    pub fn report(&self) -> Report {
+        let working_connections = self.working_connections.read().unwrap().values().cloned().collect();
+        let closed_connections = self.closed_connections.read().unwrap()
+            .iter()
+            .cloned()
+            .map(|mut report| {
+                report.metadata = None;
+                report
+            })
+            .collect();
+        Report::prepare(closed_connections, working_connections)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConnectionReport {
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub id: Option<u64>,
+    pub opened_timestamp: u128,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub closed_timestamp: Option<u128>,
     pub remote_address: String,
     pub source_type: SourceType,
     pub peer_id: Option<String>,
@@ -41,22 +105,119 @@ pub struct ConnectionReport {
     pub total_chunks: u64,
     pub decrypted_chunks: u64,
     pub error_report: Option<ParserErrorReport>,
+    pub violations: BTreeMap<ViolationKind, u64>,
+    #[serde(default)]
+    pub misbehaviour: Misbehaviour,
+    #[serde(default)]
+    pub handshake: HandshakeInfo,
+    #[serde(default)]
+    pub gap: Gap,
     pub metadata: Option<PeerMetadata>,
 }
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+/// Data of the connection which is not captured, because the ring buffer was full, or the kernel sent it
+/// from a file by `sendfile`, the stream cannot be resynchronized
+/// after the loss, the chunk boundaries and the nonces are unknown, so the direction is not decoded anymore
+pub struct Gap {
+    // how many times the data was lost, in total and by the cause
+    pub drops: u64,
+    #[serde(default)]
+    pub ring_buffer_drops: u64,
+    #[serde(default)]
+    pub sendfile_drops: u64,
+    #[serde(default)]
+    pub iov_limit_drops: u64,
+    pub lost_bytes: u64,
+    // position of the first loss in each direction, counted in chunks, like the position of the error
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub incoming_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub outgoing_at: Option<u64>,
+    // captured after the loss, but not decoded
+    pub skipped_bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+/// Misbehaviour of the peer found in the decoded messages
+pub struct Misbehaviour {
+    // the peer's message could not be decoded, the chunks which could not be decrypted are not counted
+    pub malformed_messages: u64,
+    // the node's requests for block headers and operations the peer did not answer
+    pub unanswered_requests: u64,
+    // the peer sent the same `CurrentHead` or the same operation again
+    pub duplicate_announcements: u64,
+    // the peer sent `Disconnect`
+    pub disconnects: u64,
+}
+
+impl ConnectionReport {
+    /// Peer id and addresses by which logs might mention the connection,
+    /// same form as [crate::messages::log_message::LogMessage::peer_ids] and `addresses`
+    pub fn references(&self) -> Vec<String> {
+        let mut references = Vec::with_capacity(3);
+        references.extend(self.peer_id.clone());
+        if let Ok(address) = self.remote_address.parse::<SocketAddr>() {
+            references.push(address.to_string());
+            references.push(address.ip().to_string());
+        }
+        references
+    }
+}
+
+impl Decoder for ConnectionReport {
+    fn decode(bytes: &[u8]) -> Result<Self, SchemaError> {
+        serde_cbor::from_slice(bytes)
+            .map_err(|_| SchemaError::DecodeError)
+    }
+}
+
+impl Encoder for ConnectionReport {
+    fn encode(&self) -> Result<Vec<u8>, SchemaError> {
+        serde_cbor::to_vec(self)
+            .map_err(|_| SchemaError::EncodeError)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ParserErrorReport {
     pub position: u64,
     pub error: ParserError,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, strum::EnumString)]
 #[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
 pub enum ParserError {
     FailedToWriteInDatabase,
     FailedToDecrypt,
     FirstPacketContainMultipleChunks,
     WrongProofOfWork,
     NoDecipher,
+    RingBufferOverflow,
+    Sendfile,
+    IovLimit,
     Unknown,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Why the data of the connection is not captured
+pub enum GapCause {
+    // the ring buffer was full
+    RingBufferOverflow,
+    // the kernel sent the data from a file, it is not in the node's memory
+    Sendfile,
+    // the vectored syscall passed more buffers than the bpf code captures
+    IovLimit,
+}
+
+impl GapCause {
+    /// The connection fails with this error
+    pub fn error(self) -> ParserError {
+        match self {
+            GapCause::RingBufferOverflow => ParserError::RingBufferOverflow,
+            GapCause::Sendfile => ParserError::Sendfile,
+            GapCause::IovLimit => ParserError::IovLimit,
+        }
+    }
+}
@@ -0,0 +1,176 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::collections::{BTreeSet, HashMap};
+use serde::Serialize;
+use super::report::{ConnectionReport, ParserError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::EnumString)]
+#[strum(serialize_all = "snake_case")]
+/// Metric the scorecards can be sorted by, in descending order
+pub enum PeerMetric {
+    Connections,
+    WrongProofOfWork,
+    NoDecipher,
+    DecryptFailures,
+    Violations,
+    MalformedMessages,
+    UnansweredRequests,
+    DuplicateAnnouncements,
+    Disconnects,
+    SentBytes,
+    ReceivedBytes,
+    ConnectedTime,
+    LastSeen,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+/// Evidence of the peer's behaviour summed over all its connections
+pub struct PeerScore {
+    // peer id, or the remote address, if the handshake failed before the peer id was known
+    pub peer: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer_id: Option<String>,
+    pub addresses: BTreeSet<String>,
+    pub connections: u64,
+    pub working_connections: u64,
+    pub wrong_proof_of_work: u64,
+    pub no_decipher: u64,
+    pub decrypt_failures: u64,
+    pub violations: u64,
+    pub malformed_messages: u64,
+    pub unanswered_requests: u64,
+    pub duplicate_announcements: u64,
+    pub disconnects: u64,
+    pub sent_bytes: u128,
+    pub received_bytes: u128,
+    // total duration of the connections in nanoseconds, the working connections until now
+    pub connected_time: u128,
+    pub last_seen: u128,
+}
+
+impl PeerScore {
+    fn add(&mut self, report: &ConnectionReport, now: u128) {
+        self.addresses.insert(report.remote_address.clone());
+        self.connections += 1;
+        let closed = match report.closed_timestamp {
+            Some(closed) => closed,
+            None => {
+                self.working_connections += 1;
+                now
+            },
+        };
+        match report.error_report.as_ref().map(|e| e.error) {
+            Some(ParserError::WrongProofOfWork) => self.wrong_proof_of_work += 1,
+            Some(ParserError::NoDecipher) => self.no_decipher += 1,
+            Some(ParserError::FailedToDecrypt) => self.decrypt_failures += 1,
+            _ => (),
+        }
+        self.violations += report.violations.values().sum::<u64>();
+        self.malformed_messages += report.misbehaviour.malformed_messages;
+        self.unanswered_requests += report.misbehaviour.unanswered_requests;
+        self.duplicate_announcements += report.misbehaviour.duplicate_announcements;
+        self.disconnects += report.misbehaviour.disconnects;
+        self.sent_bytes += report.sent_bytes;
+        self.received_bytes += report.received_bytes;
+        self.connected_time += closed.saturating_sub(report.opened_timestamp);
+        self.last_seen = self.last_seen.max(closed);
+    }
+
+    fn metric(&self, metric: PeerMetric) -> u128 {
+        match metric {
+            PeerMetric::Connections => self.connections as u128,
+            PeerMetric::WrongProofOfWork => self.wrong_proof_of_work as u128,
+            PeerMetric::NoDecipher => self.no_decipher as u128,
+            PeerMetric::DecryptFailures => self.decrypt_failures as u128,
+            PeerMetric::Violations => self.violations as u128,
+            PeerMetric::MalformedMessages => self.malformed_messages as u128,
+            PeerMetric::UnansweredRequests => self.unanswered_requests as u128,
+            PeerMetric::DuplicateAnnouncements => self.duplicate_announcements as u128,
+            PeerMetric::Disconnects => self.disconnects as u128,
+            PeerMetric::SentBytes => self.sent_bytes,
+            PeerMetric::ReceivedBytes => self.received_bytes,
+            PeerMetric::ConnectedTime => self.connected_time,
+            PeerMetric::LastSeen => self.last_seen,
+        }
+    }
+}
+
+/// Scorecards of the peers of the connections sorted by the metric, `now` is the end of the working connections
+pub fn scores<'a, I>(reports: I, sort_by: PeerMetric, now: u128) -> Vec<PeerScore>
+where
+    I: IntoIterator<Item = &'a ConnectionReport>,
+{
+    let mut peers = HashMap::<String, PeerScore>::new();
+    for report in reports {
+        let peer = report.peer_id.clone().unwrap_or_else(|| report.remote_address.clone());
+        peers.entry(peer.clone())
+            .or_insert_with(|| PeerScore {
+                peer,
+                peer_id: report.peer_id.clone(),
+                ..PeerScore::default()
+            })
+            .add(report, now);
+    }
+    let mut peers = peers.into_iter().map(|(_, score)| score).collect::<Vec<_>>();
+    peers.sort_by(|a, b| b.metric(sort_by).cmp(&a.metric(sort_by)).then(a.peer.cmp(&b.peer)));
+    peers
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use super::*;
+    use crate::messages::p2p_message::{SourceType, ViolationKind};
+    use super::super::report::{Misbehaviour, ParserErrorReport};
+
+    fn connection(peer_id: Option<&str>, opened: u128, closed: Option<u128>) -> ConnectionReport {
+        ConnectionReport {
+            id: None,
+            opened_timestamp: opened,
+            closed_timestamp: closed,
+            remote_address: "51.15.220.7:9732".to_string(),
+            source_type: SourceType::Remote,
+            peer_id: peer_id.map(str::to_string),
+            sent_bytes: 10,
+            received_bytes: 20,
+            incomplete_dropped_messages: 0,
+            total_chunks: 0,
+            decrypted_chunks: 0,
+            error_report: None,
+            violations: BTreeMap::new(),
+            misbehaviour: Misbehaviour::default(),
+            handshake: Default::default(),
+            gap: Default::default(),
+            metadata: None,
+        }
+    }
+
+    #[test]
+    fn scorecard() {
+        let peer_id = "idtJunqYgD1M6r6o2qvGpiD5xKZWRu";
+        let mut first = connection(Some(peer_id), 0, Some(5));
+        first.violations.insert(ViolationKind::UnrequestedResponse, 2);
+        first.misbehaviour.unanswered_requests = 3;
+        first.misbehaviour.disconnects = 1;
+        let second = connection(Some(peer_id), 10, None);
+        let mut failed = connection(None, 0, Some(1));
+        failed.error_report = Some(ParserErrorReport { position: 0, error: ParserError::WrongProofOfWork });
+
+        let reports = vec![first, second, failed];
+        let peers = scores(&reports, PeerMetric::WrongProofOfWork, 20);
+        assert_eq!(peers.len(), 2);
+        assert_eq!(peers[0].peer, "51.15.220.7:9732");
+        assert_eq!(peers[0].wrong_proof_of_work, 1);
+
+        let peer = &peers[1];
+        assert_eq!(peer.peer_id.as_deref(), Some(peer_id));
+        assert_eq!((peer.connections, peer.working_connections), (2, 1));
+        assert_eq!((peer.violations, peer.unanswered_requests, peer.disconnects), (2, 3, 1));
+        assert_eq!((peer.sent_bytes, peer.received_bytes), (20, 40));
+        assert_eq!((peer.connected_time, peer.last_seen), (15, 20));
+
+        let peers = scores(&reports, PeerMetric::Connections, 20);
+        assert_eq!(peers[0].peer, peer_id);
+    }
+}
@@ -0,0 +1,433 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::collections::HashSet;
+use tezos_messages::p2p::{
+    encoding::ack::AckMessage,
+    binary_message::BinaryMessage,
+};
+use crypto::{hash::HashType, blake2b};
+
+use crate::messages::p2p_message::{
+    P2pMessage,
+    TezosPeerMessage,
+    HandshakeMessage,
+    FullPeerMessage,
+    Violation,
+    ViolationKind,
+};
+use super::report::Misbehaviour;
+use crate::system::NetworkProfile;
+
+/// Checks the conversation of a single connection against the Tezos P2P state machine
+#[derive(Default)]
+pub struct Validator {
+    incoming: Side,
+    outgoing: Side,
+    // incoming messages which could not be decoded
+    malformed: u64,
+    // the messages must belong to the network, if known
+    network: Option<NetworkProfile>,
+}
+
+/// State of one direction of the conversation
+#[derive(Default)]
+struct Side {
+    handshake: HandshakeStep,
+    // this side refused the connection, it should not send anything more
+    nack: bool,
+    disconnected: bool,
+    // hashes requested by this side, the other side should respond only with them
+    requested_headers: HashSet<Vec<u8>>,
+    requested_operations: HashSet<Vec<u8>>,
+    requested_operations_for_blocks: HashSet<(Vec<u8>, i8)>,
+    // too many unanswered requests, cannot tell whether a response was requested
+    saturated: bool,
+    // hashes of the heads announced and the operations sent by this side
+    announced_heads: HashSet<Vec<u8>>,
+    sent_operations: HashSet<Vec<u8>>,
+    duplicates: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum HandshakeStep {
+    ConnectionMessage,
+    Metadata,
+    Ack,
+    Completed,
+}
+
+impl Default for HandshakeStep {
+    fn default() -> Self {
+        HandshakeStep::ConnectionMessage
+    }
+}
+
+impl Validator {
+    // the chunk is prefixed with two bytes of its length, the length is at most `u16::MAX`
+    const MAX_CHUNK_SIZE: usize = 2 + 0xffff;
+    // encrypted chunk contains at least the length and the 16 bytes of MAC
+    const MIN_ENCRYPTED_CHUNK_SIZE: usize = 2 + 16;
+    const MAX_PENDING_REQUESTS: usize = 0x10000;
+
+    /// Validator which also checks the conversation belongs to the network
+    pub fn new(network: &NetworkProfile) -> Self {
+        Validator {
+            network: Some(network.clone()),
+            ..Validator::default()
+        }
+    }
+
+    /// Check the message, returns all violations found in it
+    pub fn validate(&mut self, message: &P2pMessage) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        let incoming = message.incoming;
+
+        let size = message.original_bytes.len();
+        if size > Self::MAX_CHUNK_SIZE {
+            violations.push(Violation::new(
+                ViolationKind::ChunkTooBig,
+                format!("chunk of {} bytes exceeds the limit of {} bytes", size, Self::MAX_CHUNK_SIZE),
+            ));
+        }
+
+        let message = match message.message.first() {
+            Some(message) => message,
+            // cannot say anything about the message which was not decoded
+            None => {
+                // the chunk which could not be decrypted has no decrypted bytes
+                if incoming && !message.error.is_empty() && !message.decrypted_bytes.is_empty() {
+                    self.malformed += 1;
+                }
+                return violations;
+            },
+        };
+
+        if self.side(incoming).nack {
+            violations.push(Violation::new(ViolationKind::MessageAfterNack, "message after the nack"));
+        }
+        if self.side(incoming).disconnected {
+            violations.push(Violation::new(ViolationKind::MessageAfterDisconnect, "message after the disconnect"));
+        }
+
+        let (received, next) = match message {
+            TezosPeerMessage::HandshakeMessage(HandshakeMessage::ConnectionMessage(_)) => (HandshakeStep::ConnectionMessage, HandshakeStep::Metadata),
+            TezosPeerMessage::HandshakeMessage(HandshakeMessage::MetadataMessage(_)) => (HandshakeStep::Metadata, HandshakeStep::Ack),
+            TezosPeerMessage::HandshakeMessage(HandshakeMessage::AckMessage(_)) => (HandshakeStep::Ack, HandshakeStep::Completed),
+            TezosPeerMessage::PeerMessage(_) | TezosPeerMessage::PartialPeerMessage(_) => (HandshakeStep::Completed, HandshakeStep::Completed),
+        };
+        let step = self.side(incoming).handshake;
+        if step != received {
+            violations.push(Violation::new(
+                ViolationKind::HandshakeOrder,
+                format!("expected {:?}, but got {:?}", step, received),
+            ));
+        }
+        self.side(incoming).handshake = next;
+
+        if !matches!(message, TezosPeerMessage::HandshakeMessage(HandshakeMessage::ConnectionMessage(_))) &&
+            size < Self::MIN_ENCRYPTED_CHUNK_SIZE
+        {
+            violations.push(Violation::new(
+                ViolationKind::ChunkTooSmall,
+                format!("encrypted chunk of {} bytes is smaller than {} bytes", size, Self::MIN_ENCRYPTED_CHUNK_SIZE),
+            ));
+        }
+
+        if let Some(violation) = self.check_network(message) {
+            violations.push(violation);
+        }
+
+        match message {
+            TezosPeerMessage::HandshakeMessage(HandshakeMessage::AckMessage(ack)) => {
+                if !matches!(ack, AckMessage::Ack) {
+                    self.side(incoming).nack = true;
+                }
+            },
+            TezosPeerMessage::PeerMessage(message) => {
+                if let Some(violation) = self.check_peer_message(message, incoming) {
+                    violations.push(violation);
+                }
+            },
+            _ => (),
+        }
+
+        violations
+    }
+
+    /// Misbehaviour of the peer so far, the requests still pending are counted as unanswered
+    pub fn misbehaviour(&self) -> Misbehaviour {
+        Misbehaviour {
+            malformed_messages: self.malformed,
+            unanswered_requests: (self.outgoing.requested_headers.len() + self.outgoing.requested_operations.len()) as u64,
+            duplicate_announcements: self.incoming.duplicates,
+            disconnects: self.incoming.disconnected as u64,
+        }
+    }
+
+    /// Check the advertised versions and the chain id belong to the network
+    fn check_network(&self, message: &TezosPeerMessage) -> Option<Violation> {
+        let network = self.network.as_ref()?;
+        let chain_id = match message {
+            TezosPeerMessage::HandshakeMessage(HandshakeMessage::ConnectionMessage(m)) => {
+                return if m.versions().iter().any(|version| network.accepts(version)) {
+                    None
+                } else {
+                    Some(Violation::new(
+                        ViolationKind::WrongNetwork,
+                        format!("no version of the network {} advertised", network.chain_name),
+                    ))
+                };
+            },
+            TezosPeerMessage::PeerMessage(FullPeerMessage::CurrentHead(m)) => m.chain_id(),
+            TezosPeerMessage::PeerMessage(FullPeerMessage::GetCurrentHead(m)) => m.chain_id(),
+            TezosPeerMessage::PeerMessage(FullPeerMessage::CurrentBranch(m)) => m.chain_id(),
+            TezosPeerMessage::PeerMessage(FullPeerMessage::GetCurrentBranch(m)) => m.chain_id(),
+            _ => return None,
+        };
+        let expected = network.chain_id.as_ref()?;
+        let chain_id = HashType::ChainId.hash_to_b58check(chain_id);
+        if &chain_id != expected {
+            Some(Violation::new(
+                ViolationKind::WrongNetwork,
+                format!("chain {} does not belong to the network {}", chain_id, network.name),
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Remember the requests and check the responses
+    fn check_peer_message(&mut self, message: &FullPeerMessage, incoming: bool) -> Option<Violation> {
+        match message {
+            &FullPeerMessage::Disconnect => {
+                self.side(incoming).disconnected = true;
+                None
+            },
+            &FullPeerMessage::GetBlockHeaders(ref m) => {
+                self.side(incoming).request(|s| &mut s.requested_headers, m.get_block_headers().iter().cloned());
+                None
+            },
+            &FullPeerMessage::GetOperations(ref m) => {
+                self.side(incoming).request(|s| &mut s.requested_operations, m.get_operations().iter().cloned());
+                None
+            },
+            &FullPeerMessage::GetOperationsForBlocks(ref m) => {
+                let requested = m.get_operations_for_blocks()
+                    .iter()
+                    .map(|o| (o.hash().clone(), o.validation_pass()));
+                self.side(incoming).request(|s| &mut s.requested_operations_for_blocks, requested);
+                None
+            },
+            &FullPeerMessage::BlockHeader(ref m) => {
+                let hash = blake2b::digest_256(&m.block_header().as_bytes().ok()?);
+                let requester = self.side(!incoming);
+                if !requester.requested_headers.remove(&hash) && !requester.saturated {
+                    Some(Violation::new(ViolationKind::UnrequestedResponse, "block header was not requested"))
+                } else {
+                    None
+                }
+            },
+            &FullPeerMessage::CurrentHead(ref m) => {
+                // the head is announced again whenever the mempool changes, only the same message is a duplicate
+                let hash = blake2b::digest_256(&m.as_bytes().ok()?);
+                self.side(incoming).remember(|s| &mut s.announced_heads, hash);
+                None
+            },
+            &FullPeerMessage::Operation(ref m) => {
+                let hash = blake2b::digest_256(&m.operation().as_bytes().ok()?);
+                self.side(incoming).remember(|s| &mut s.sent_operations, hash.clone());
+                let requester = self.side(!incoming);
+                if !requester.requested_operations.remove(&hash) && !requester.saturated {
+                    Some(Violation::new(ViolationKind::UnrequestedResponse, "operation was not requested"))
+                } else {
+                    None
+                }
+            },
+            &FullPeerMessage::OperationsForBlocks(ref m) => {
+                let key = (m.operations_for_block().hash().clone(), m.operations_for_block().validation_pass());
+                let requester = self.side(!incoming);
+                // the response might be split in several messages, so do not remove the request
+                if !requester.requested_operations_for_blocks.contains(&key) && !requester.saturated {
+                    Some(Violation::new(ViolationKind::UnrequestedResponse, "operations for block were not requested"))
+                } else {
+                    None
+                }
+            },
+            _ => None,
+        }
+    }
+
+    fn side(&mut self, incoming: bool) -> &mut Side {
+        if incoming {
+            &mut self.incoming
+        } else {
+            &mut self.outgoing
+        }
+    }
+}
+
+impl Side {
+    fn request<T, F, I>(&mut self, set: F, items: I)
+    where
+        T: Eq + std::hash::Hash,
+        F: FnOnce(&mut Self) -> &mut HashSet<T>,
+        I: Iterator<Item = T>,
+    {
+        let set = set(self);
+        set.extend(items);
+        if set.len() > Validator::MAX_PENDING_REQUESTS {
+            set.clear();
+            self.saturated = true;
+        }
+    }
+
+    /// Remember the announced hash, count it if it was announced already
+    fn remember<F>(&mut self, set: F, hash: Vec<u8>)
+    where
+        F: FnOnce(&mut Self) -> &mut HashSet<Vec<u8>>,
+    {
+        let set = set(self);
+        let duplicate = !set.insert(hash);
+        if set.len() > Validator::MAX_PENDING_REQUESTS {
+            set.clear();
+        }
+        if duplicate {
+            self.duplicates += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tezos_messages::p2p::encoding::{
+        metadata::MetadataMessage,
+        operation::{OperationMessage, GetOperationsMessage},
+    };
+    use crate::messages::p2p_message::SourceType;
+    use super::*;
+
+    fn message(incoming: bool, size: usize, message: TezosPeerMessage) -> P2pMessage {
+        let remote_addr = "51.15.220.7:9732".parse().unwrap();
+        P2pMessage::new(remote_addr, incoming, SourceType::Remote, vec![0; size], vec![0; size], Ok(message))
+    }
+
+    fn check(validator: &mut Validator, message: P2pMessage) -> Vec<ViolationKind> {
+        validator.validate(&message).into_iter().map(|v| v.kind).collect()
+    }
+
+    fn metadata() -> TezosPeerMessage {
+        TezosPeerMessage::HandshakeMessage(HandshakeMessage::MetadataMessage(MetadataMessage::from_bytes(vec![0, 0]).unwrap()))
+    }
+
+    fn ack(ack: AckMessage) -> TezosPeerMessage {
+        TezosPeerMessage::HandshakeMessage(HandshakeMessage::AckMessage(ack))
+    }
+
+    fn peer(message: FullPeerMessage) -> TezosPeerMessage {
+        TezosPeerMessage::PeerMessage(message)
+    }
+
+    // both sides sent the connection message
+    fn after_connection_message() -> Validator {
+        let mut validator = Validator::default();
+        validator.incoming.handshake = HandshakeStep::Metadata;
+        validator.outgoing.handshake = HandshakeStep::Metadata;
+        validator
+    }
+
+    #[test]
+    fn handshake_order() {
+        let mut validator = Validator::default();
+        assert_eq!(check(&mut validator, message(true, 100, metadata())), vec![ViolationKind::HandshakeOrder]);
+
+        let mut validator = after_connection_message();
+        assert_eq!(check(&mut validator, message(false, 100, peer(FullPeerMessage::Bootstrap))), vec![ViolationKind::HandshakeOrder]);
+        assert!(check(&mut validator, message(true, 100, metadata())).is_empty());
+        assert!(check(&mut validator, message(true, 100, ack(AckMessage::Ack))).is_empty());
+        assert!(check(&mut validator, message(true, 100, peer(FullPeerMessage::Bootstrap))).is_empty());
+        assert_eq!(check(&mut validator, message(true, 100, ack(AckMessage::Ack))), vec![ViolationKind::HandshakeOrder]);
+    }
+
+    #[test]
+    fn nack_and_disconnect() {
+        let mut validator = after_connection_message();
+        assert!(check(&mut validator, message(true, 100, metadata())).is_empty());
+        assert!(check(&mut validator, message(false, 100, metadata())).is_empty());
+        assert!(check(&mut validator, message(true, 100, ack(AckMessage::NackV0))).is_empty());
+        // only the side which refused the connection should stop talking
+        assert!(check(&mut validator, message(false, 100, ack(AckMessage::Ack))).is_empty());
+        assert_eq!(check(&mut validator, message(true, 100, peer(FullPeerMessage::Bootstrap))), vec![ViolationKind::MessageAfterNack]);
+
+        let mut validator = after_connection_message();
+        for &incoming in [true, false].iter() {
+            assert!(check(&mut validator, message(incoming, 100, metadata())).is_empty());
+            assert!(check(&mut validator, message(incoming, 100, ack(AckMessage::Ack))).is_empty());
+        }
+        assert!(check(&mut validator, message(true, 100, peer(FullPeerMessage::Disconnect))).is_empty());
+        assert!(check(&mut validator, message(false, 100, peer(FullPeerMessage::Bootstrap))).is_empty());
+        assert_eq!(check(&mut validator, message(true, 100, peer(FullPeerMessage::Bootstrap))), vec![ViolationKind::MessageAfterDisconnect]);
+    }
+
+    #[test]
+    fn chunk_size() {
+        let mut validator = after_connection_message();
+        assert_eq!(check(&mut validator, message(true, 10, metadata())), vec![ViolationKind::ChunkTooSmall]);
+
+        // the size is checked even if the message was not decoded
+        let mut big = message(true, 0x10002, peer(FullPeerMessage::Bootstrap));
+        big.message.clear();
+        big.error.push("cannot decode".to_string());
+        assert_eq!(check(&mut validator, big), vec![ViolationKind::ChunkTooBig]);
+    }
+
+    #[test]
+    fn unrequested_response() {
+        let mut validator = Validator::default();
+        validator.incoming.handshake = HandshakeStep::Completed;
+        validator.outgoing.handshake = HandshakeStep::Completed;
+
+        let operation_bytes = vec![1; 40];
+        let operation = || peer(FullPeerMessage::Operation(OperationMessage::from_bytes(operation_bytes.clone()).unwrap()));
+        assert_eq!(check(&mut validator, message(true, 100, operation())), vec![ViolationKind::UnrequestedResponse]);
+
+        // the dynamic list of the operation hashes is prefixed with its length
+        let mut request = vec![0, 0, 0, 32];
+        request.extend_from_slice(&blake2b::digest_256(&operation_bytes));
+        let request = GetOperationsMessage::from_bytes(request).unwrap();
+        assert!(check(&mut validator, message(false, 100, peer(FullPeerMessage::GetOperations(request)))).is_empty());
+        assert!(check(&mut validator, message(true, 100, operation())).is_empty());
+        // answered already
+        assert_eq!(check(&mut validator, message(true, 100, operation())), vec![ViolationKind::UnrequestedResponse]);
+    }
+
+    #[test]
+    fn misbehaviour() {
+        let mut validator = Validator::default();
+        validator.incoming.handshake = HandshakeStep::Completed;
+        validator.outgoing.handshake = HandshakeStep::Completed;
+
+        let operation_bytes = vec![1; 40];
+        let operation = || peer(FullPeerMessage::Operation(OperationMessage::from_bytes(operation_bytes.clone()).unwrap()));
+        let mut request = vec![0, 0, 0, 32];
+        request.extend_from_slice(&blake2b::digest_256(&operation_bytes));
+        let request = GetOperationsMessage::from_bytes(request).unwrap();
+        check(&mut validator, message(false, 100, peer(FullPeerMessage::GetOperations(request))));
+        assert_eq!(validator.misbehaviour().unanswered_requests, 1);
+        check(&mut validator, message(true, 100, operation()));
+        assert_eq!(validator.misbehaviour().unanswered_requests, 0);
+        // the same operation sent again is a duplicate
+        check(&mut validator, message(true, 100, operation()));
+        check(&mut validator, message(true, 100, operation()));
+        assert_eq!(validator.misbehaviour().duplicate_announcements, 2);
+
+        let mut malformed = message(true, 100, peer(FullPeerMessage::Bootstrap));
+        malformed.message.clear();
+        malformed.error.push("cannot decode".to_string());
+        check(&mut validator, malformed);
+        assert_eq!(validator.misbehaviour().malformed_messages, 1);
+
+        check(&mut validator, message(true, 100, peer(FullPeerMessage::Disconnect)));
+        assert_eq!(validator.misbehaviour().disconnects, 1);
+    }
+}
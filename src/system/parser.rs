@@ -1,30 +1,74 @@
 use std::{
     convert::TryFrom,
     net::{SocketAddr, IpAddr},
-    sync::{Arc, Mutex},
+    sync::{Arc, RwLock},
 };
-use tokio::{stream::StreamExt, sync::mpsc};
-use sniffer::{BpfModule, SnifferEvent, RingBufferData, EventId};
+use tokio::{stream::StreamExt, sync::{mpsc, oneshot}, task::JoinHandle};
+use sniffer::{BpfModule, SnifferEvent, RingBufferData, EventId, SocketId};
 
-use super::{p2p, reporter::Reporter, processor, SystemSettings};
+use super::{KprobeStatus, p2p, chain::ChainSnapshot, propagation::PropagationSnapshot, mempool::MempoolSnapshot, reporter::Reporter, processor, SystemSettings};
 use crate::{messages::p2p_message::{P2pMessage, SourceType}};
 
+/// Handle of the running parser, to stop it in order
+pub struct ParserHandle {
+    stop: oneshot::Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl ParserHandle {
+    /// Stop capturing and detach the kprobes, wait until the working connections are joined
+    /// and the captured messages are stored
+    pub async fn shutdown(self) {
+        // the parser might already stopped, if the bpf module failed to load
+        let _ = self.stop.send(());
+        if let Err(error) = self.handle.await {
+            tracing::error!(error = tracing::field::display(&error), "parser task failed");
+        }
+    }
+}
+
 pub struct Parser {
-    module: BpfModule,
+    // `None` if the module failed to load, nothing is captured then
+    module: Option<BpfModule>,
     settings: SystemSettings,
     counter: u64,
     node_pid: Option<u32>,
 }
 
-enum Event {
-    RbData(RingBufferData),
-    P2pCommand(p2p::Command),
-}
-
 impl Parser {
     pub fn new(settings: &SystemSettings) -> Self {
+        let module = match BpfModule::load(&settings.namespace) {
+            Ok(module) => {
+                let kprobes = module.kprobes()
+                    .iter()
+                    .map(|(name, attached)| {
+                        if let Err(error) = attached {
+                            tracing::error!(kprobe = tracing::field::display(name), error = tracing::field::display(error), "failed to attach kprobe");
+                        }
+                        KprobeStatus {
+                            name: name.clone(),
+                            attached: attached.is_ok(),
+                            error: attached.clone().err(),
+                        }
+                    })
+                    .collect();
+                settings.status.set_kprobes(kprobes);
+                // the bpf code captures only the connections accepted on the node's port,
+                // and drops the outgoing connections to these ports before copying their data in the ring buffer
+                module.watch_port(settings.node_p2p_port);
+                for &port in Self::ignored_ports(settings).iter() {
+                    module.ignore_port(port);
+                }
+                Some(module)
+            },
+            Err(error) => {
+                tracing::error!(error = tracing::field::display(&error), "failed to load bpf module, nothing is captured");
+                settings.status.set_bpf_error(error);
+                None
+            },
+        };
         Parser {
-            module: BpfModule::load(&settings.namespace),
+            module,
             settings: settings.clone(),
             counter: 0,
             // unknown for now,
@@ -35,35 +79,60 @@ impl Parser {
     }
 
     /// spawn a (green)thread which parse the data from the kernel,
-    /// returns object which can report statistics
-    pub fn spawn(self) -> Arc<Mutex<Reporter>> {
-        let (tx_p2p_command, rx_p2p_command) = mpsc::channel(1);
-        let (tx_p2p_report, rx_p2p_report) = mpsc::channel(1);
-        tokio::spawn(self.run(rx_p2p_command, tx_p2p_report));
-        let reporter = Reporter::new(tx_p2p_command, rx_p2p_report);
-        Arc::new(Mutex::new(reporter))
+    /// returns object which can report statistics and the handle to stop the parser
+    pub fn spawn(self) -> (Reporter, ParserHandle) {
+        let p2p_snapshot = p2p::ReportSnapshot::default();
+        let p2p_comparison = Arc::new(RwLock::new(None));
+        let chain = ChainSnapshot::default();
+        let propagation = PropagationSnapshot::default();
+        let mempool = MempoolSnapshot::default();
+        let settings = self.settings.clone();
+        let (stop, stopped) = oneshot::channel();
+        let handle = tokio::spawn(self.run(p2p_snapshot.clone(), chain.clone(), propagation.clone(), mempool.clone(), stopped));
+        let reporter = Reporter::new(p2p_snapshot, p2p_comparison.clone(), chain, propagation, mempool, settings.clone());
+        p2p::spawn_comparator(settings, reporter.clone(), p2p_comparison);
+        (reporter, ParserHandle { stop, handle })
     }
 
     async fn run(
         self,
-        rx_p2p_command: mpsc::Receiver<p2p::Command>,
-        tx_p2p_report: mpsc::Sender<p2p::Report>,
+        p2p_snapshot: p2p::ReportSnapshot,
+        chain: ChainSnapshot,
+        propagation: PropagationSnapshot,
+        mempool: MempoolSnapshot,
+        mut stopped: oneshot::Receiver<()>,
     ) {
-        let db = processor::spawn_processor(self.settings.clone());
-        let rb = self.module.main_buffer();
+        let mut rb = match &self.module {
+            Some(module) => module.main_buffer(),
+            None => return,
+        };
+        let (db, processor) = processor::spawn_processor(self.settings.clone(), chain, propagation, mempool);
         let mut s = self;
-        // merge streams, let await either some data from the kernel,
-        // or some command from the overlying code
-        let mut stream =
-            rb.map(Event::RbData).merge(rx_p2p_command.map(Event::P2pCommand));
-        let mut p2p_parser = p2p::Parser::new(tx_p2p_report);
-        while let Some(event) = stream.next().await {
-            match event {
-                Event::RbData(slice) => s.process(&mut p2p_parser, slice, &db).await,
-                // while executing this command new slices from the kernel will not be processed
-                // so it is impossible to have data race
-                Event::P2pCommand(command) => p2p_parser.execute(command).await,
-            }
+        let mut p2p_parser = p2p::Parser::new(s.settings.storage.clone(), p2p_snapshot);
+        loop {
+            let slice = tokio::select! {
+                slice = rb.next() => match slice {
+                    Some(slice) => slice,
+                    None => break,
+                },
+                _ = &mut stopped => break,
+            };
+            s.settings.status.event();
+            s.process(&mut p2p_parser, slice, &db).await;
+        }
+
+        // detach the kprobes first, they must not stay attached even if the rest does not finish in time
+        if let Some(module) = &mut s.module {
+            module.detach(&s.settings.namespace);
+            tracing::info!("detached kprobes");
+        }
+
+        // the connections hold the senders too, the processor finishes when all of them are joined
+        tracing::info!("stopped capturing, joining the connections");
+        p2p_parser.terminate().await;
+        drop(db);
+        if let Err(error) = processor.await {
+            tracing::error!(error = tracing::field::display(&error), "processor task failed");
         }
     }
 
@@ -77,7 +146,19 @@ impl Parser {
                     msg = "Syscall Bind",
                 );
                 if address.ip().is_unspecified() && address.port() == self.settings.node_p2p_port {
-                    self.node_pid = Some(id.socket_id.pid);
+                    let pid = id.socket_id.pid;
+                    // the bpf code already watches the pid since the bind, the connections which follow it
+                    // are captured even before this event is processed; watch it here too, as the fallback,
+                    // and stop watching the previous node
+                    if let Some(module) = &self.module {
+                        match self.node_pid {
+                            Some(old) if old != pid => module.unwatch_pid(old),
+                            _ => (),
+                        }
+                        module.watch_pid(pid);
+                    }
+                    self.node_pid = Some(pid);
+                    self.settings.status.set_node_pid(pid);
                 }
             },
             Ok(SnifferEvent::Listen { id }) => {
@@ -117,23 +198,58 @@ impl Parser {
                 self.process_data(parser, id, data.to_vec(), false)
             },
             Ok(SnifferEvent::Debug { id, msg }) => tracing::warn!("{} {}", id, msg),
+            Ok(SnifferEvent::Overflow { id, incoming, lost }) => {
+                tracing::warn!(
+                    id = tracing::field::display(&id),
+                    incoming = incoming,
+                    lost = lost,
+                    msg = "Ring buffer overflow",
+                );
+                self.settings.storage.stat().ring_buffer_drop(id.socket_id.to_string(), lost);
+                parser.process_gap(id, incoming, lost, p2p::GapCause::RingBufferOverflow);
+            },
+            Ok(SnifferEvent::Truncated { id, incoming, lost }) => {
+                tracing::warn!(
+                    id = tracing::field::display(&id),
+                    incoming = incoming,
+                    lost = lost,
+                    msg = "Vectored syscall has too many buffers, the rest is not captured",
+                );
+                self.settings.storage.stat().iov_limit_drop(id.socket_id.to_string(), lost);
+                parser.process_gap(id, incoming, lost, p2p::GapCause::IovLimit);
+            },
+            Ok(SnifferEvent::SendFile { id, length }) => {
+                tracing::warn!(
+                    id = tracing::field::display(&id),
+                    length = length,
+                    msg = "Syscall Sendfile, the data cannot be captured",
+                );
+                self.settings.storage.stat().sendfile_drop(id.socket_id.to_string(), length);
+                parser.process_gap(id, false, length, p2p::GapCause::Sendfile);
+            },
         }
     }
 
-    fn should_ignore(&self, address: &SocketAddr) -> bool {
-        match address.port() {
-            0 | 65535 => {
-                return true;
-            },
+    fn ignore(&self, socket_id: SocketId) {
+        if let Some(module) = &self.module {
+            module.ignore(socket_id);
+        }
+    }
+
+    fn ignored_ports(settings: &SystemSettings) -> [u16; 8] {
+        [
+            0, 65535,
             // dns and other well known not tezos
-            53 | 80 | 443 | 22 => {
-                return true;
-            },
+            53, 80, 443, 22,
             // ignore rpc for now
-            p if p == self.settings.syslog_port || p == self.settings.rpc_port => {
-                return true;
-            },
-            _ => (),
+            settings.syslog_port, settings.rpc_port,
+        ]
+    }
+
+    // the bpf code filters by pid and port too, these checks remain in case the maps failed to update
+    fn should_ignore(&self, address: &SocketAddr) -> bool {
+        if Self::ignored_ports(&self.settings).contains(&address.port()) {
+            return true;
         }
         // lo v6
         if address.ip() == "::1".parse::<IpAddr>().unwrap() {
@@ -165,15 +281,15 @@ impl Parser {
         // the message is not belong to the node
         if Some(socket_id.pid) != self.node_pid {
             tracing::info!(id = tracing::field::display(&id), msg = "ignore, filtered by pid");
-            self.module.ignore(socket_id);
+            self.ignore(socket_id);
         } else if self.should_ignore(&address) {
             tracing::info!(id = tracing::field::display(&id), msg = "ignore");
-            self.module.ignore(socket_id);
+            self.ignore(socket_id);
         } else {
             let r = parser.process_connect(&self.settings, id, address, db, source_type).await;
             if !r.have_identity {
                 tracing::warn!("ignore connection because no identity");
-                self.module.ignore(socket_id);
+                self.ignore(socket_id);
             }
         }
     }
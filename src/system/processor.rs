@@ -1,14 +1,16 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use tracing::{error, trace};
-use tokio::sync::mpsc::{
-    UnboundedSender, unbounded_channel,
+use std::mem;
+use tracing::{info, error, trace};
+use tokio::{
+    sync::mpsc::{UnboundedSender, unbounded_channel},
+    task::JoinHandle,
 };
 use async_trait::async_trait;
-use crate::system::SystemSettings;
-use crate::messages::p2p_message::P2pMessage;
-use crate::storage::MessageStore;
+use crate::system::{SystemSettings, SystemStatus, alerts::Alerts, chain::ChainSnapshot, propagation::PropagationSnapshot, mempool::MempoolSnapshot};
+use crate::messages::p2p_message::{P2pMessage, TezosPeerMessage};
+use crate::storage::{MessageStore, TimeseriesStore, SampleKey, Counters, P2pMessageType};
 
 type ProcessorTrait = dyn Processor + Sync + Send + 'static;
 
@@ -17,72 +19,92 @@ type ProcessorTrait = dyn Processor + Sync + Send + 'static;
 pub trait Processor {
     /// Main processing function for messages
     async fn process(&mut self, msg: P2pMessage);
+
+    /// Finish the processing, all messages are sent, called on shutdown
+    async fn finish(&mut self) {}
 }
 
-/// Spawn new primary processor, returning channel to send the messages
-pub fn spawn_processor(settings: SystemSettings) -> UnboundedSender<P2pMessage> {
+/// Spawn new primary processor, returning channel to send the messages,
+/// the processor finishes when all senders are dropped, the handle resolves when everything is stored
+pub fn spawn_processor(
+    settings: SystemSettings,
+    chain: ChainSnapshot,
+    propagation: PropagationSnapshot,
+    mempool: MempoolSnapshot,
+) -> (UnboundedSender<P2pMessage>, JoinHandle<()>) {
     let (sender, mut receiver) = unbounded_channel::<P2pMessage>();
 
-    tokio::spawn(async move {
+    let handle = tokio::spawn(async move {
         let mut processors: Vec<Box<ProcessorTrait>> = Default::default();
         // Initially, only database processor is spawned
-        processors.push(Box::new(DatabaseProcessor::new(settings.storage.clone(), settings.max_message_number)));
-        loop {
-            if let Some(message) = receiver.recv().await {
-                for processor in processors.iter_mut() {
-                    processor.process(message.clone()).await;
-                }
-            } else {
-                error!("p2p processing channel closed unexpectedly");
-                break;
+        processors.push(Box::new(DatabaseProcessor::new(settings.storage.clone(), settings.max_message_number, settings.status.clone())));
+        processors.push(Box::new(ChainProcessor { chain }));
+        processors.push(Box::new(PropagationProcessor { propagation }));
+        processors.push(Box::new(MempoolProcessor { mempool }));
+        processors.push(Box::new(TimeseriesProcessor::new(settings.storage.timeseries().clone())));
+        if settings.alerts.enabled() {
+            processors.push(Box::new(AlertProcessor { alerts: settings.alerts.clone() }));
+        }
+        while let Some(message) = receiver.recv().await {
+            for processor in processors.iter_mut() {
+                processor.process(message.clone()).await;
             }
         }
+        info!("p2p processing channel closed, finishing the processors");
+        for processor in processors.iter_mut() {
+            processor.finish().await;
+        }
     });
 
-    sender
+    (sender, handle)
 }
 
 /// Database processor, which stores all received messages
 struct DatabaseProcessor {
     store: MessageStore,
     sender: UnboundedSender<P2pMessage>,
+    handle: JoinHandle<()>,
     max_message_number: u64,
+    // how many messages wait to be stored and how many times the writer was restarted
+    status: SystemStatus,
 }
 
 impl DatabaseProcessor {
     /// Create new processor on top of the given message store
-    pub fn new(store: MessageStore, max_message_number: u64) -> Self {
+    pub fn new(store: MessageStore, max_message_number: u64, status: SystemStatus) -> Self {
+        let (sender, handle) = Self::start_database_task(store.clone(), max_message_number, status.clone());
         let ret = Self {
-            sender: Self::start_database_task(store.clone(), max_message_number),
+            sender,
+            handle,
             store,
             max_message_number,
+            status,
         };
 
         ret
     }
 
     /// Start the processing task
-    fn start_database_task(store: MessageStore, max_message_number: u64) -> UnboundedSender<P2pMessage> {
+    fn start_database_task(store: MessageStore, max_message_number: u64, status: SystemStatus) -> (UnboundedSender<P2pMessage>, JoinHandle<()>) {
         let (sender, mut receiver) = unbounded_channel::<P2pMessage>();
-        tokio::spawn(async move {
-            loop {
-                if let Some(mut msg) = receiver.recv().await {
-                    match store.p2p().store_message(&mut msg) {
-                        Ok(id) => {
-                            trace!(id, "stored new message");
-                            if id >= max_message_number {
-                                match store.p2p().delete_message(id - max_message_number) {
-                                    Ok(()) => trace!(id, "removed old message"),
-                                    Err(err) => error!(error = tracing::field::display(&err), "failed to remove message"),
-                                }
+        let handle = tokio::spawn(async move {
+            while let Some(mut msg) = receiver.recv().await {
+                match store.p2p().store_message(&mut msg) {
+                    Ok(id) => {
+                        trace!(id, "stored new message");
+                        if id >= max_message_number {
+                            match store.p2p().delete_message(id - max_message_number) {
+                                Ok(()) => trace!(id, "removed old message"),
+                                Err(err) => error!(error = tracing::field::display(&err), "failed to remove message"),
                             }
-                        },
-                        Err(err) => error!(error = tracing::field::display(&err), "failed to store message"),
-                    }
+                        }
+                    },
+                    Err(err) => error!(error = tracing::field::display(&err), "failed to store message"),
                 }
+                status.db_stored();
             }
         });
-        sender
+        (sender, handle)
     }
 }
 
@@ -91,12 +113,120 @@ impl Processor for DatabaseProcessor {
     async fn process(&mut self, mut msg: P2pMessage) {
         loop {
             if let Err(err) = self.sender.send(msg) {
-                error!(error = tracing::field::display(&err), "database channel closed abruptly");
+                error!(error = tracing::field::display(&err), "database channel closed abruptly, restarting the database task");
                 msg = err.0;
-                self.sender = Self::start_database_task(self.store.clone(), self.max_message_number);
+                self.status.db_restarted();
+                let (sender, handle) = Self::start_database_task(self.store.clone(), self.max_message_number, self.status.clone());
+                self.sender = sender;
+                self.handle = handle;
             } else {
+                self.status.db_queued();
                 return;
             }
         }
     }
-}
\ No newline at end of file
+
+    async fn finish(&mut self) {
+        // dropping the sender lets the task store the queued messages and stop
+        let (sender, _) = unbounded_channel();
+        drop(mem::replace(&mut self.sender, sender));
+        if let Err(err) = (&mut self.handle).await {
+            error!(error = tracing::field::display(&err), "database task failed");
+        }
+    }
+}
+
+/// Chain processor, which tracks the heads of the node and of the peers
+struct ChainProcessor {
+    chain: ChainSnapshot,
+}
+
+#[async_trait]
+impl Processor for ChainProcessor {
+    async fn process(&mut self, msg: P2pMessage) {
+        self.chain.update(&msg);
+    }
+}
+
+/// Propagation processor, which traces how the blocks spread across the peers
+struct PropagationProcessor {
+    propagation: PropagationSnapshot,
+}
+
+#[async_trait]
+impl Processor for PropagationProcessor {
+    async fn process(&mut self, msg: P2pMessage) {
+        self.propagation.update(&msg);
+    }
+}
+
+/// Mempool processor, which observes the gossiped operations
+struct MempoolProcessor {
+    mempool: MempoolSnapshot,
+}
+
+#[async_trait]
+impl Processor for MempoolProcessor {
+    async fn process(&mut self, msg: P2pMessage) {
+        self.mempool.update(&msg);
+    }
+}
+
+/// Time series processor, which counts the traffic in memory and stores the ended buckets,
+/// so the connection tasks never wait for the database
+struct TimeseriesProcessor {
+    timeseries: TimeseriesStore,
+    // unix timestamp in seconds of the last check for the ended buckets
+    last_store: u64,
+}
+
+impl TimeseriesProcessor {
+    fn new(timeseries: TimeseriesStore) -> Self {
+        TimeseriesProcessor {
+            timeseries,
+            last_store: 0,
+        }
+    }
+}
+
+#[async_trait]
+impl Processor for TimeseriesProcessor {
+    async fn process(&mut self, msg: P2pMessage) {
+        let key = SampleKey {
+            remote_addr: msg.remote_addr,
+            incoming: msg.incoming,
+            message_type: P2pMessageType::extract(&msg).as_str().to_string(),
+        };
+        // the chunk which could not be decrypted is counted by its encrypted size
+        let bytes = if msg.decrypted_bytes.is_empty() {
+            msg.original_bytes.len()
+        } else {
+            msg.decrypted_bytes.len()
+        };
+        // the message might span many chunks, it is counted by the last one
+        let completed = !msg.decrypted_bytes.is_empty()
+            && !matches!(msg.message.first(), Some(TezosPeerMessage::PartialPeerMessage(_)));
+        let counters = Counters { bytes: bytes as u64, messages: completed as u64 };
+        self.timeseries.record(msg.timestamp, key, counters);
+
+        let seconds = (msg.timestamp / 1_000_000_000) as u64;
+        if seconds > self.last_store {
+            self.last_store = seconds;
+            if let Err(err) = self.timeseries.store_ended(seconds) {
+                error!(error = tracing::field::display(&err), "failed to store the time series");
+            }
+        }
+    }
+}
+
+/// Alert processor, which evaluates the alerting rules over the messages
+struct AlertProcessor {
+    alerts: Alerts,
+}
+
+#[async_trait]
+impl Processor for AlertProcessor {
+    async fn process(&mut self, msg: P2pMessage) {
+        self.alerts.p2p(&msg);
+    }
+}
@@ -0,0 +1,309 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
+use serde::Serialize;
+use crypto::hash::HashType;
+use super::chain::block_hash;
+use crate::messages::p2p_message::{P2pMessage, TezosPeerMessage, FullPeerMessage};
+
+/// How many recent blocks are traced
+const MAX_BLOCKS: usize = 0x400;
+
+#[derive(Default)]
+/// Everything captured about the single block
+struct Block {
+    level: Option<i32>,
+    // when the block was announced by each peer for the first time
+    announced: HashMap<SocketAddr, u128>,
+    // the node asked for the header
+    header_requested: Option<u128>,
+    header_received: Option<u128>,
+    // the last operations of the block received
+    operations_received: Option<u128>,
+    // the node announced the block as its head
+    node_announced: Option<u128>,
+}
+
+impl Block {
+    fn first_seen(&self) -> Option<(SocketAddr, u128)> {
+        self.announced.iter()
+            .map(|(remote_addr, timestamp)| (*remote_addr, *timestamp))
+            .min_by_key(|&(remote_addr, timestamp)| (timestamp, remote_addr))
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+/// When the peer announced the block, relative to the first announcement
+pub struct PeerAnnouncement {
+    pub remote_addr: SocketAddr,
+    pub timestamp: u128,
+    pub delay: u128,
+}
+
+#[derive(Debug, Clone, Serialize)]
+/// Propagation of the block, all latencies are in nanoseconds since the first announcement
+pub struct BlockPropagation {
+    pub hash: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_seen: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_peer: Option<SocketAddr>,
+    // sorted by the delay
+    pub peers: Vec<PeerAnnouncement>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub header_request_latency: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub header_latency: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub operations_latency: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node_announce_latency: Option<u128>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+/// Nearest-rank percentiles of the latencies in nanoseconds
+pub struct Percentiles {
+    pub count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p50: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p90: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p99: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<u128>,
+}
+
+impl Percentiles {
+    fn new(mut values: Vec<u128>) -> Self {
+        values.sort();
+        let rank = |p: usize| -> Option<u128> {
+            if values.is_empty() {
+                None
+            } else {
+                let index = (values.len() * p + 99) / 100;
+                Some(values[index.max(1) - 1])
+            }
+        };
+        Percentiles {
+            count: values.len(),
+            p50: rank(50),
+            p90: rank(90),
+            p99: rank(99),
+            max: values.last().cloned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+/// Latencies aggregated over all traced blocks
+pub struct PropagationSummary {
+    pub blocks: usize,
+    // the peer announced the block after the first peer
+    pub peer_delay: Percentiles,
+    pub header_request_latency: Percentiles,
+    pub header_latency: Percentiles,
+    pub operations_latency: Percentiles,
+    pub node_announce_latency: Percentiles,
+}
+
+#[derive(Default)]
+struct PropagationState {
+    blocks: HashMap<String, Block>,
+    // hashes in the order of the first appearance, to forget the oldest blocks
+    order: VecDeque<String>,
+}
+
+impl PropagationState {
+    fn update(&mut self, message: &P2pMessage) {
+        let timestamp = message.timestamp;
+        let announced = match message.message.first() {
+            Some(TezosPeerMessage::PeerMessage(FullPeerMessage::CurrentHead(m))) => m.current_block_header(),
+            Some(TezosPeerMessage::PeerMessage(FullPeerMessage::CurrentBranch(m))) => m.current_branch().current_head(),
+            _ => {
+                self.update_fetch(message);
+                return;
+            },
+        };
+        if let Some(hash) = block_hash(announced) {
+            self.announced(hash, announced.level(), message.incoming, message.remote_addr, timestamp);
+        }
+    }
+
+    fn update_fetch(&mut self, message: &P2pMessage) {
+        let timestamp = message.timestamp;
+        match message.message.first() {
+            Some(TezosPeerMessage::PeerMessage(FullPeerMessage::GetBlockHeaders(m))) if !message.incoming => {
+                for hash in m.get_block_headers() {
+                    self.block(HashType::BlockHash.hash_to_b58check(hash))
+                        .header_requested.get_or_insert(timestamp);
+                }
+            },
+            Some(TezosPeerMessage::PeerMessage(FullPeerMessage::BlockHeader(m))) if message.incoming => {
+                if let Some(hash) = block_hash(m.block_header()) {
+                    let block = self.block(hash);
+                    block.level = Some(m.block_header().level());
+                    block.header_received.get_or_insert(timestamp);
+                }
+            },
+            Some(TezosPeerMessage::PeerMessage(FullPeerMessage::OperationsForBlocks(m))) if message.incoming => {
+                let hash = HashType::BlockHash.hash_to_b58check(m.operations_for_block().hash());
+                self.block(hash).operations_received = Some(timestamp);
+            },
+            _ => (),
+        }
+    }
+
+    fn block(&mut self, hash: String) -> &mut Block {
+        if !self.blocks.contains_key(&hash) {
+            self.order.push_back(hash.clone());
+            while self.order.len() > MAX_BLOCKS {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.blocks.remove(&oldest);
+                }
+            }
+        }
+        self.blocks.entry(hash).or_default()
+    }
+
+    fn announced(&mut self, hash: String, level: i32, incoming: bool, remote_addr: SocketAddr, timestamp: u128) {
+        let block = self.block(hash);
+        block.level = Some(level);
+        if incoming {
+            block.announced.entry(remote_addr).or_insert(timestamp);
+        } else {
+            block.node_announced.get_or_insert(timestamp);
+        }
+    }
+
+    fn propagation(&self, hash: &str) -> Option<BlockPropagation> {
+        let block = self.blocks.get(hash)?;
+        let first = block.first_seen();
+        let first_seen = first.map(|(_, timestamp)| timestamp);
+        let latency = |timestamp: Option<u128>| Some(timestamp?.saturating_sub(first_seen?));
+        let mut peers = block.announced.iter()
+            .map(|(remote_addr, timestamp)| PeerAnnouncement {
+                remote_addr: *remote_addr,
+                timestamp: *timestamp,
+                delay: timestamp - first_seen.unwrap_or(*timestamp),
+            })
+            .collect::<Vec<_>>();
+        peers.sort_by_key(|peer| (peer.delay, peer.remote_addr));
+        Some(BlockPropagation {
+            hash: hash.to_string(),
+            level: block.level,
+            first_seen,
+            first_peer: first.map(|(remote_addr, _)| remote_addr),
+            peers,
+            header_request_latency: latency(block.header_requested),
+            header_latency: latency(block.header_received),
+            operations_latency: latency(block.operations_received),
+            node_announce_latency: latency(block.node_announced),
+        })
+    }
+
+    fn summary(&self) -> PropagationSummary {
+        let propagations = self.blocks.keys()
+            .filter_map(|hash| self.propagation(hash))
+            // only the blocks announced by some peer have the reference time
+            .filter(|propagation| propagation.first_seen.is_some())
+            .collect::<Vec<_>>();
+        let collect = |f: fn(&BlockPropagation) -> Option<u128>| {
+            Percentiles::new(propagations.iter().filter_map(f).collect())
+        };
+        PropagationSummary {
+            blocks: propagations.len(),
+            peer_delay: Percentiles::new(propagations.iter().flat_map(|p| p.peers.iter().skip(1).map(|peer| peer.delay)).collect()),
+            header_request_latency: collect(|p| p.header_request_latency),
+            header_latency: collect(|p| p.header_latency),
+            operations_latency: collect(|p| p.operations_latency),
+            node_announce_latency: collect(|p| p.node_announce_latency),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+/// Propagation of the recent blocks derived from the captured messages, shared with the endpoints
+pub struct PropagationSnapshot {
+    state: Arc<RwLock<PropagationState>>,
+}
+
+impl PropagationSnapshot {
+    /// Trace the block in the captured message
+    pub fn update(&self, message: &P2pMessage) {
+        self.state.write().unwrap().update(message)
+    }
+
+    /// Propagation of the block, `None` if the block is unknown
+    pub fn propagation(&self, hash: &str) -> Option<BlockPropagation> {
+        self.state.read().unwrap().propagation(hash)
+    }
+
+    /// Percentiles over the recent blocks
+    pub fn summary(&self) -> PropagationSummary {
+        self.state.read().unwrap().summary()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEC: u128 = 1_000_000_000;
+
+    #[test]
+    fn propagation() {
+        let mut state = PropagationState::default();
+        let a: SocketAddr = "51.15.220.7:9732".parse().unwrap();
+        let b: SocketAddr = "51.15.220.8:9732".parse().unwrap();
+        let hash = "BLockGenesisGenesisGenesisGenesisGenesisf79b5d1CoW2".to_string();
+
+        state.announced(hash.clone(), 7, true, b, 10 * SEC);
+        state.announced(hash.clone(), 7, true, a, 12 * SEC);
+        // the later announcement of the same peer is ignored
+        state.announced(hash.clone(), 7, true, b, 13 * SEC);
+        state.block(hash.clone()).header_requested = Some(11 * SEC);
+        state.block(hash.clone()).header_received = Some(14 * SEC);
+        state.announced(hash.clone(), 7, false, a, 15 * SEC);
+
+        let propagation = state.propagation(&hash).unwrap();
+        assert_eq!(propagation.first_peer, Some(b));
+        assert_eq!(propagation.peers[1].remote_addr, a);
+        assert_eq!(propagation.peers[1].delay, 2 * SEC);
+        assert_eq!(propagation.header_request_latency, Some(SEC));
+        assert_eq!(propagation.header_latency, Some(4 * SEC));
+        assert_eq!(propagation.operations_latency, None);
+        assert_eq!(propagation.node_announce_latency, Some(5 * SEC));
+
+        let summary = state.summary();
+        assert_eq!(summary.blocks, 1);
+        assert_eq!(summary.peer_delay.p50, Some(2 * SEC));
+        assert_eq!(summary.header_latency.max, Some(4 * SEC));
+        assert_eq!(summary.operations_latency.count, 0);
+    }
+
+    #[test]
+    fn percentiles() {
+        let p = Percentiles::new((1..=100).collect());
+        assert_eq!((p.p50, p.p90, p.p99, p.max), (Some(50), Some(90), Some(99), Some(100)));
+        let p = Percentiles::new(vec![3]);
+        assert_eq!((p.p50, p.p99), (Some(3), Some(3)));
+    }
+
+    #[test]
+    fn forget_oldest() {
+        let mut state = PropagationState::default();
+        for i in 0..(MAX_BLOCKS + 1) {
+            state.block(format!("block{}", i));
+        }
+        assert_eq!(state.blocks.len(), MAX_BLOCKS);
+        assert!(state.propagation("block0").is_none());
+    }
+}
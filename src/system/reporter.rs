@@ -1,26 +1,89 @@
-use tokio::sync::mpsc;
-use super::p2p;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+use super::{p2p, chain, propagation, mempool, NetworkProfile, SystemSettings};
 
+#[derive(Clone)]
 pub struct Reporter {
-    tx_p2p_command: mpsc::Sender<p2p::Command>,
-    rx_p2p_report: mpsc::Receiver<p2p::Report>,
+    p2p_snapshot: p2p::ReportSnapshot,
+    p2p_comparison: Arc<RwLock<Option<p2p::Comparison>>>,
+    chain: chain::ChainSnapshot,
+    propagation: propagation::PropagationSnapshot,
+    mempool: mempool::MempoolSnapshot,
+    settings: SystemSettings,
 }
 
 impl Reporter {
     pub fn new(
-        tx_p2p_command: mpsc::Sender<p2p::Command>,
-        rx_p2p_report: mpsc::Receiver<p2p::Report>,
+        p2p_snapshot: p2p::ReportSnapshot,
+        p2p_comparison: Arc<RwLock<Option<p2p::Comparison>>>,
+        chain: chain::ChainSnapshot,
+        propagation: propagation::PropagationSnapshot,
+        mempool: mempool::MempoolSnapshot,
+        settings: SystemSettings,
     ) -> Self {
         Reporter {
-            tx_p2p_command,
-            rx_p2p_report,
+            p2p_snapshot,
+            p2p_comparison,
+            chain,
+            propagation,
+            mempool,
+            settings,
         }
     }
 
-    pub async fn get_p2p_report(&mut self) -> Option<p2p::Report> {
-        match self.tx_p2p_command.send(p2p::Command::GetReport).await {
-            Ok(()) => self.rx_p2p_report.recv().await,
-            Err(_) => None,
-        }
+    /// Profile of the network the debugger was started for
+    pub fn get_network(&self) -> &NetworkProfile {
+        &self.settings.network
+    }
+
+    /// Settings the system is running with, includes its runtime status
+    pub fn get_settings(&self) -> &SystemSettings {
+        &self.settings
+    }
+
+    pub fn get_p2p_report(&self) -> p2p::Report {
+        self.p2p_snapshot.report()
+    }
+
+    /// The latest comparison of the debugger counts with the node's peer metadata,
+    /// `None` if the node was not reachable yet
+    pub fn get_p2p_comparison(&self) -> Option<p2p::Comparison> {
+        self.p2p_comparison.read().unwrap().clone()
+    }
+
+    /// Heads of the node and of the best peer
+    pub fn get_chain_heads(&self) -> chain::ChainHeads {
+        self.chain.heads(&self.peer_ids())
+    }
+
+    /// Heads announced by the peers
+    pub fn get_chain_peers(&self) -> Vec<chain::PeerHead> {
+        self.chain.peers(&self.peer_ids())
+    }
+
+    /// Propagation of the recent block, `None` if the block was not seen
+    pub fn get_block_propagation(&self, hash: &str) -> Option<propagation::BlockPropagation> {
+        self.propagation.propagation(hash)
+    }
+
+    /// Percentiles of the propagation latencies over the recent blocks
+    pub fn get_propagation_summary(&self) -> propagation::PropagationSummary {
+        self.propagation.summary()
+    }
+
+    /// The recent gossiped operations, newest first
+    pub fn get_mempool(&self, limit: usize, filters: &mempool::MempoolFilters) -> Vec<mempool::MempoolOperation> {
+        self.mempool.operations(limit, filters, &self.peer_ids())
+    }
+
+    /// Peer ids of the working connections by the remote address
+    fn peer_ids(&self) -> HashMap<String, String> {
+        self.get_p2p_report()
+            .working_connections()
+            .iter()
+            .filter_map(|report| Some((report.remote_address.clone(), report.peer_id.clone()?)))
+            .collect()
     }
 }
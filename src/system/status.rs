@@ -0,0 +1,238 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::sync::{
+    Arc, RwLock,
+    atomic::{AtomicU32, AtomicU64, AtomicBool, Ordering},
+};
+use serde::Serialize;
+use crate::storage::get_ts;
+
+const NANOS: u128 = 1_000_000_000;
+
+/// The events are not flowing if the ring buffer was silent for longer, in seconds
+const EVENTS_TIMEOUT: u64 = 60;
+
+/// The database writer is not keeping up if more messages wait to be stored
+const MAX_DB_LAG: u64 = 0x10000;
+
+#[derive(Debug, Clone, Serialize)]
+/// Whether the kprobe was attached to the kernel function
+pub struct KprobeStatus {
+    pub name: String,
+    pub attached: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+struct Status {
+    started: u128,
+    // zero until the node does bind syscall on its p2p port
+    node_pid: AtomicU32,
+    identity_loaded: AtomicBool,
+    // the error of loading the bpf module, `None` if loaded, or not loaded yet
+    bpf_error: RwLock<Option<String>>,
+    kprobes: RwLock<Vec<KprobeStatus>>,
+    events: AtomicU64,
+    // unix timestamp in seconds of the last event from the ring buffer, zero if none yet
+    last_event: AtomicU64,
+    db_queued: AtomicU64,
+    db_stored: AtomicU64,
+    db_dropped: AtomicU64,
+    db_restarts: AtomicU64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+/// Whether the debugger is actually capturing
+pub struct Health {
+    pub bpf_loaded: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bpf_error: Option<String>,
+    pub kprobes_attached: bool,
+    // the kprobes which failed to attach
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failed_kprobes: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node_pid: Option<u32>,
+    pub events: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_event: Option<u64>,
+    pub events_flowing: bool,
+    // messages waiting to be stored
+    pub db_lag: u64,
+    pub db_dropped: u64,
+    pub db_restarts: u64,
+    pub db_keeping_up: bool,
+}
+
+impl Health {
+    /// The capturing pipeline is working, the node might not run yet
+    pub fn healthy(&self) -> bool {
+        self.bpf_loaded && self.kprobes_attached && self.db_keeping_up
+    }
+
+    /// The node is found and its traffic is captured and stored
+    pub fn ready(&self) -> bool {
+        self.healthy() && self.node_pid.is_some() && self.events_flowing
+    }
+}
+
+#[derive(Clone)]
+/// Runtime status of the debugger, updated by the parts of the system as they learn it
+pub struct SystemStatus {
+    inner: Arc<Status>,
+}
+
+impl Default for SystemStatus {
+    fn default() -> Self {
+        SystemStatus {
+            inner: Arc::new(Status {
+                started: get_ts(),
+                node_pid: AtomicU32::new(0),
+                identity_loaded: AtomicBool::new(false),
+                bpf_error: RwLock::new(None),
+                kprobes: RwLock::new(Vec::new()),
+                events: AtomicU64::new(0),
+                last_event: AtomicU64::new(0),
+                db_queued: AtomicU64::new(0),
+                db_stored: AtomicU64::new(0),
+                db_dropped: AtomicU64::new(0),
+                db_restarts: AtomicU64::new(0),
+            }),
+        }
+    }
+}
+
+impl SystemStatus {
+    /// Unix timestamp in nanoseconds the debugger was started at
+    pub fn started(&self) -> u128 {
+        self.inner.started
+    }
+
+    /// Seconds since the debugger was started
+    pub fn uptime(&self) -> u64 {
+        (get_ts().saturating_sub(self.inner.started) / NANOS) as u64
+    }
+
+    pub fn set_node_pid(&self, pid: u32) {
+        self.inner.node_pid.store(pid, Ordering::Relaxed)
+    }
+
+    /// Pid of the monitored node, `None` if not discovered yet
+    pub fn node_pid(&self) -> Option<u32> {
+        match self.inner.node_pid.load(Ordering::Relaxed) {
+            0 => None,
+            pid => Some(pid),
+        }
+    }
+
+    pub fn set_identity_loaded(&self, loaded: bool) {
+        self.inner.identity_loaded.store(loaded, Ordering::Relaxed)
+    }
+
+    pub fn identity_loaded(&self) -> bool {
+        self.inner.identity_loaded.load(Ordering::Relaxed)
+    }
+
+    pub fn set_bpf_error(&self, error: String) {
+        *self.inner.bpf_error.write().unwrap() = Some(error);
+    }
+
+    pub fn set_kprobes(&self, kprobes: Vec<KprobeStatus>) {
+        *self.inner.kprobes.write().unwrap() = kprobes;
+    }
+
+    pub fn kprobes(&self) -> Vec<KprobeStatus> {
+        self.inner.kprobes.read().unwrap().clone()
+    }
+
+    /// The event came from the ring buffer
+    pub fn event(&self) {
+        self.inner.events.fetch_add(1, Ordering::Relaxed);
+        self.inner.last_event.store((get_ts() / NANOS) as u64, Ordering::Relaxed);
+    }
+
+    /// The message was sent to the database writer
+    pub fn db_queued(&self) {
+        self.inner.db_queued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The database writer finished with the message, stored or not
+    pub fn db_stored(&self) {
+        self.inner.db_stored.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The database writer died and was restarted, the messages queued for it are lost
+    pub fn db_restarted(&self) {
+        let queued = self.inner.db_queued.load(Ordering::Relaxed);
+        let stored = self.inner.db_stored.swap(queued, Ordering::Relaxed);
+        self.inner.db_dropped.fetch_add(queued.saturating_sub(stored), Ordering::Relaxed);
+        self.inner.db_restarts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Evaluate the health at the unix timestamp in seconds
+    pub fn health(&self, now: u64) -> Health {
+        let bpf_error = self.inner.bpf_error.read().unwrap().clone();
+        let kprobes = self.kprobes();
+        let failed_kprobes = kprobes.iter()
+            .filter(|kprobe| !kprobe.attached)
+            .map(|kprobe| kprobe.name.clone())
+            .collect::<Vec<_>>();
+        let last_event = match self.inner.last_event.load(Ordering::Relaxed) {
+            0 => None,
+            last_event => Some(last_event),
+        };
+        let db_lag = self.inner.db_queued.load(Ordering::Relaxed)
+            .saturating_sub(self.inner.db_stored.load(Ordering::Relaxed));
+        Health {
+            bpf_loaded: bpf_error.is_none() && !kprobes.is_empty(),
+            bpf_error,
+            kprobes_attached: !kprobes.is_empty() && failed_kprobes.is_empty(),
+            failed_kprobes,
+            node_pid: self.node_pid(),
+            events: self.inner.events.load(Ordering::Relaxed),
+            last_event,
+            events_flowing: last_event.map(|last| now.saturating_sub(last) <= EVENTS_TIMEOUT).unwrap_or(false),
+            db_lag,
+            db_dropped: self.inner.db_dropped.load(Ordering::Relaxed),
+            db_restarts: self.inner.db_restarts.load(Ordering::Relaxed),
+            db_keeping_up: db_lag <= MAX_DB_LAG,
+        }
+    }
+
+    /// Evaluate the health now
+    pub fn health_now(&self) -> Health {
+        self.health((get_ts() / NANOS) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn health() {
+        let status = SystemStatus::default();
+        assert!(!status.health(0).healthy());
+        status.set_kprobes(vec![KprobeStatus { name: "tcp_connect".to_string(), attached: true, error: None }]);
+        let health = status.health(0);
+        assert!(health.healthy() && !health.ready());
+
+        status.set_node_pid(42);
+        status.event();
+        let now = status.inner.last_event.load(Ordering::Relaxed);
+        assert!(status.health(now).ready());
+        assert!(!status.health(now + EVENTS_TIMEOUT + 1).ready());
+
+        status.db_queued();
+        status.db_queued();
+        status.db_stored();
+        assert_eq!(status.health(now).db_lag, 1);
+        status.db_restarted();
+        let health = status.health(now);
+        assert_eq!((health.db_lag, health.db_dropped, health.db_restarts), (0, 1, 1));
+
+        status.set_bpf_error("error loading BPF program".to_string());
+        assert!(!status.health(now).healthy());
+    }
+}
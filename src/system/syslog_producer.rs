@@ -7,7 +7,7 @@ use tokio::{
     net::UdpSocket,
 };
 use crate::system::SystemSettings;
-use crate::messages::log_message::LogMessage;
+use crate::storage::get_ts;
 
 /// Spawn new Syslog UDP server, for processing syslogs.
 pub async fn syslog_producer(settings: SystemSettings) -> io::Result<()> {
@@ -25,10 +25,14 @@ pub async fn syslog_producer(settings: SystemSettings) -> io::Result<()> {
             // Syslog are textual format, all received datagrams must be valid strings.
             if let Ok(log) = std::str::from_utf8(&datagram) {
                 let msg = syslog_loose::parse_message(log);
-                let mut log_msg = LogMessage::from(msg);
+                let date = msg.timestamp
+                    .map(|dt| dt.timestamp_nanos() as u128)
+                    .unwrap_or_else(get_ts);
+                let mut log_msg = settings.log_parsers.parse(date, msg.msg);
                 if let Err(err) = settings.storage.log().store_message(&mut log_msg) {
                     error!(error = tracing::field::display(&err), "failed to store log");
                 }
+                settings.alerts.log(&log_msg);
             }
         }
     });